@@ -0,0 +1,199 @@
+//! Approve-once tokens for prompted commands
+//!
+//! Tools that can't ask the user (OpenCode turns prompts into aborts) get a short
+//! approval id with each prompt instead. Once a human runs `bashguard approve <id>`,
+//! the exact same command in the same directory is allowed once.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{hash::sha256_hex, store};
+
+/// How long a requested or approved command stays valid
+const APPROVAL_TTL_MINUTES: i64 = 60;
+
+/// Length of approval ids, in hex digits
+const ID_LENGTH: usize = 6;
+
+/// A prompted command waiting for, or holding, a human's approval
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approval {
+    /// Short id for `bashguard approve` (e.g., "3fa9c2")
+    pub id: String,
+    /// SHA-256 of the working directory and the exact command
+    pub hash: String,
+    /// The command, for showing what is being approved
+    pub command: String,
+    pub cwd: PathBuf,
+    /// Session the command was prompted in
+    pub session_id: String,
+    pub requested: DateTime<Utc>,
+    /// When a human approved the command (`None` while pending)
+    #[serde(default)]
+    pub approved: Option<DateTime<Utc>>,
+}
+
+impl Approval {
+    /// Whether the approval can no longer be approved or used
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        let since = self.approved.unwrap_or(self.requested);
+        now - since >= Duration::minutes(APPROVAL_TTL_MINUTES)
+    }
+}
+
+/// Hash identifying `command` run in `cwd`
+fn command_hash(command: &str, cwd: &Path) -> String {
+    sha256_hex(format!("{}\0{}", cwd.display(), command).as_bytes())
+}
+
+/// Approvals stored in `approvals.json` in the workspace's state directory
+pub struct ApprovalStore {
+    path: PathBuf,
+}
+
+impl ApprovalStore {
+    /// Approval store of the current workspace
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_path(
+            store::workspace_state_dir()?.join("approvals.json"),
+        ))
+    }
+
+    /// Approval store at a custom path (for testing)
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// All stored approvals, including expired ones not yet cleaned up
+    pub fn load(&self) -> Result<Vec<Approval>> {
+        store::load(&self.path, "approvals")
+    }
+
+    /// Change the stored approvals with `change`, dropping expired ones
+    fn update<R>(
+        &self,
+        now: DateTime<Utc>,
+        change: impl FnOnce(&mut Vec<Approval>) -> Result<R>,
+    ) -> Result<R> {
+        store::update(&self.path, "approvals", |approvals: &mut Vec<Approval>| {
+            approvals.retain(|approval| !approval.is_expired(now));
+            change(approvals)
+        })
+    }
+
+    /// Approval request for a prompted command, reusing a pending one for the same
+    /// command and directory
+    pub fn request(
+        &self,
+        command: &str,
+        cwd: &Path,
+        session_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Approval> {
+        let hash = command_hash(command, cwd);
+        self.update(now, |approvals| {
+            if let Some(pending) = approvals
+                .iter()
+                .find(|approval| approval.hash == hash && approval.approved.is_none())
+            {
+                return Ok(pending.clone());
+            }
+
+            // Ids come from the hash and the time, so they are hard to guess from the
+            // command alone; take more digits on the rare collision
+            let seed = sha256_hex(format!("{}{}", hash, now.to_rfc3339()).as_bytes());
+            let id = (ID_LENGTH..=seed.len())
+                .map(|length| seed[..length].to_string())
+                .find(|id| approvals.iter().all(|approval| approval.id != *id))
+                .context("Failed to pick an approval id")?;
+
+            let approval = Approval {
+                id,
+                hash,
+                command: command.to_string(),
+                cwd: cwd.to_path_buf(),
+                session_id: session_id.to_string(),
+                requested: now,
+                approved: None,
+            };
+            approvals.push(approval.clone());
+            Ok(approval)
+        })
+    }
+
+    /// Approve the pending request with `id`
+    pub fn approve(&self, id: &str, now: DateTime<Utc>) -> Result<Approval> {
+        self.update(now, |approvals| {
+            let Some(approval) = approvals.iter_mut().find(|approval| approval.id == id) else {
+                bail!("No pending approval with id {}", id);
+            };
+            if approval.approved.is_some() {
+                bail!("Approval {} was already approved", id);
+            }
+            approval.approved = Some(now);
+            Ok(approval.clone())
+        })
+    }
+
+    /// Use up the approval for `command` run in `cwd`, if a human approved it
+    pub fn consume(
+        &self,
+        command: &str,
+        cwd: &Path,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Approval>> {
+        let hash = command_hash(command, cwd);
+        self.update(now, |approvals| {
+            let index = approvals
+                .iter()
+                .position(|approval| approval.hash == hash && approval.approved.is_some());
+            Ok(index.map(|index| approvals.remove(index)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_approve_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ApprovalStore::with_path(temp_dir.path().join("approvals.json"));
+        let cwd = Path::new("/work");
+        let now = Utc::now();
+
+        let request = store.request("git push", cwd, "s1", now).unwrap();
+        assert_eq!(request.id.len(), ID_LENGTH);
+        assert_eq!(store.request("git push", cwd, "s1", now).unwrap(), request);
+        assert_ne!(
+            store
+                .request("git push", Path::new("/other"), "s1", now)
+                .unwrap()
+                .id,
+            request.id
+        );
+
+        // Nothing is allowed until a human approves
+        assert_eq!(store.consume("git push", cwd, now).unwrap(), None);
+        assert!(store.approve("000000", now).is_err());
+        store.approve(&request.id, now).unwrap();
+        assert!(store.approve(&request.id, now).is_err());
+
+        // The exact command, once
+        assert_eq!(store.consume("git push -f", cwd, now).unwrap(), None);
+        let used = store.consume("git push", cwd, now).unwrap().unwrap();
+        assert_eq!(used.id, request.id);
+        assert_eq!(store.consume("git push", cwd, now).unwrap(), None);
+
+        // Requests expire
+        let request = store.request("make deploy", cwd, "s1", now).unwrap();
+        let later = now + Duration::minutes(APPROVAL_TTL_MINUTES);
+        assert!(store.approve(&request.id, later).is_err());
+    }
+}
@@ -2,9 +2,15 @@ use std::{fmt, str::FromStr};
 
 use clap::{Parser, Subcommand};
 
+pub mod approve;
 pub mod check;
+pub mod explain;
+pub mod grant;
+pub mod grants;
 pub mod init;
 pub mod profiles;
+pub mod revoke;
+pub mod sandbox_exec;
 pub mod test;
 pub mod validate;
 
@@ -23,6 +29,12 @@ pub enum Command {
     Validate(validate::Args),
     Profiles(profiles::Args),
     Test(test::Args),
+    Explain(explain::Args),
+    SandboxExec(sandbox_exec::Args),
+    Grant(grant::Args),
+    Revoke(revoke::Args),
+    Grants(grants::Args),
+    Approve(approve::Args),
 }
 
 #[derive(Clone, Debug)]
@@ -31,6 +43,17 @@ pub enum Tool {
     OpenCode,
 }
 
+impl Tool {
+    /// Whether the tool can ask the user about prompted commands itself (otherwise
+    /// prompts come with an id for `bashguard approve`)
+    pub fn can_prompt(&self) -> bool {
+        match self {
+            Tool::Claude => true,
+            Tool::OpenCode => false,
+        }
+    }
+}
+
 impl FromStr for Tool {
     type Err = String;
 
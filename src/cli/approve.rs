@@ -0,0 +1,8 @@
+use clap::Parser;
+
+/// Allow a prompted command once (by the approval id shown with the prompt)
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// The approval id
+    pub id: String,
+}
@@ -12,4 +12,8 @@ pub struct Args {
     /// Output format (required)
     #[clap(long)]
     pub format: Tool,
+
+    /// Print a full rule evaluation trace to stderr
+    #[clap(long)]
+    pub explain: bool,
 }
@@ -0,0 +1,17 @@
+use clap::Parser;
+
+/// Show a full rule evaluation trace for a command
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// The command to explain
+    #[clap(short, long)]
+    pub command: String,
+
+    /// Output in JSON format
+    #[clap(long)]
+    pub json: bool,
+
+    /// Session to apply temporary grants and escalation for
+    #[clap(long)]
+    pub session: Option<String>,
+}
@@ -0,0 +1,21 @@
+use chrono::Duration;
+use clap::Parser;
+
+use crate::config::parse_duration;
+
+/// Temporarily allow commands starting like the given one (e.g., `git push`), with
+/// no flags beyond its own
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// How long the grant lasts (e.g., 90s, 30m, 2h, 1d)
+    #[clap(long = "for", value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Limit the grant to a session
+    #[clap(long)]
+    pub session: Option<String>,
+
+    /// The command prefix to allow
+    #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    pub spec: Vec<String>,
+}
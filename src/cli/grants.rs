@@ -0,0 +1,9 @@
+use clap::Parser;
+
+/// List temporary grants
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// Also list expired grants
+    #[clap(long)]
+    pub all: bool,
+}
@@ -0,0 +1,9 @@
+use clap::Parser;
+
+/// Remove temporary grants
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// Ids of the grants to remove (as listed by `bashguard grants`)
+    #[clap(required = true)]
+    pub ids: Vec<String>,
+}
@@ -0,0 +1,13 @@
+use clap::Parser;
+
+/// Run a command in a sandbox (what the `sandbox` action rewrites commands to)
+#[derive(Clone, Debug, Parser)]
+pub struct Args {
+    /// Sandbox profile from the config's `[sandboxes]`
+    #[clap(long, default_value = "default")]
+    pub policy: String,
+
+    /// The command to run with `bash -c`
+    #[clap(last = true, required = true)]
+    pub command: Vec<String>,
+}
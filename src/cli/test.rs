@@ -6,4 +6,8 @@ pub struct Args {
     /// The command to test
     #[clap(short, long)]
     pub command: String,
+
+    /// Session to apply temporary grants and escalation for
+    #[clap(long)]
+    pub session: Option<String>,
 }
@@ -0,0 +1,42 @@
+use chrono::Duration;
+
+/// Parse a duration: a number followed by `s`, `m`, `h` or `d` (e.g., "30m"), as
+/// used for grants, escalation windows and limits
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid duration: '{}'. Use e.g. 90s, 30m, 2h or 1d.",
+            value
+        )
+    };
+    let (unit_at, _) = value.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(unit_at);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    match amount.checked_mul(seconds) {
+        Some(total) if total > 0 => Duration::try_seconds(total).ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::seconds(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::minutes(30)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::hours(2)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::days(1)));
+        for invalid in ["", "m", "30", "0m", "-5m", "30w", "5µ"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
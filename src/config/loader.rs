@@ -5,7 +5,10 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 
-use super::types::{Config, Profile, ProfileMetadata};
+use super::{
+    duration::parse_duration,
+    types::{Condition, Config, Profile, ProfileMetadata},
+};
 
 pub struct ConfigLoader {
     config_dir: PathBuf,
@@ -65,6 +68,12 @@ impl ConfigLoader {
             config.loaded_profiles.push(profile);
         }
 
+        check_windows(&config)?;
+        check_arg_indexes(&config)?;
+
+        let config_path = config_path.exists().then_some(config_path);
+        config.assign_rule_origins(config_path);
+
         Ok(config)
     }
 
@@ -150,6 +159,7 @@ impl ConfigLoader {
         let mut profile: Profile = toml::from_str(&contents)
             .with_context(|| format!("Failed to parse profile: {}", name))?;
         profile.profile.name = name.to_string();
+        profile.path = Some(profile_path);
 
         Ok(profile)
     }
@@ -162,6 +172,72 @@ impl Config {
     }
 }
 
+/// Windows are parsed when evaluating, so report invalid ones when loading
+fn check_windows(config: &Config) -> Result<()> {
+    let escalation_windows = config
+        .settings
+        .escalation
+        .iter()
+        .map(|rule| ("settings.escalation", &rule.window));
+    let limit_windows = config
+        .settings
+        .limits
+        .iter()
+        .map(|limit| &limit.limit)
+        .chain(
+            config
+                .rules
+                .iter()
+                .chain(config.loaded_profiles.iter().flat_map(|p| p.rules.iter()))
+                .filter_map(|rule| rule.limit.as_ref()),
+        )
+        .map(|limit| ("limit", &limit.window));
+    for (setting, window) in escalation_windows.chain(limit_windows) {
+        if let Some(window) = window {
+            if let Err(e) = parse_duration(window) {
+                bail!("Invalid window in {}: {}", setting, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `arg` keys are parsed when matching, so report ones that aren't indexes when loading
+/// (a rule using one would never match)
+fn check_arg_indexes(config: &Config) -> Result<()> {
+    fn check(condition: &Condition, setting: &str) -> Result<()> {
+        if let Some(index) = condition.arg.keys().find(|i| i.parse::<usize>().is_err()) {
+            bail!(
+                "Invalid arg index {:?} in {}: keys are 0-based argument positions",
+                index,
+                setting
+            );
+        }
+        let nested = condition.all.iter().chain(&condition.any);
+        nested
+            .chain(condition.not.as_deref())
+            .try_for_each(|condition| check(condition, setting))
+    }
+
+    let rules = config
+        .rules
+        .iter()
+        .chain(config.loaded_profiles.iter().flat_map(|p| p.rules.iter()))
+        .map(|rule| match &rule.id {
+            Some(id) => (&rule.condition, format!("rule {}", id)),
+            None => (&rule.condition, "rules".to_string()),
+        });
+    let limits = config
+        .settings
+        .limits
+        .iter()
+        .map(|limit| (&limit.condition, "settings.limits".to_string()));
+    for (condition, setting) in rules.chain(limits) {
+        check(condition, &setting)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use tempfile::TempDir;
@@ -208,4 +284,48 @@ mod tests {
         assert_eq!(config.available_profiles.len(), 1);
         assert_eq!(config.available_profiles[0].name, "git/read-only");
     }
+
+    #[test]
+    fn test_unknown_effect_rejected() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("config.toml"),
+            r#"
+            [[rules]]
+            effects_absent = ["netwrok"]
+            action = "allow"
+            "#,
+        )
+        .unwrap();
+        let loader =
+            ConfigLoader::with_paths(temp.path().to_path_buf(), temp.path().join("profiles"));
+
+        let error = format!("{:#}", loader.load().unwrap_err());
+        assert!(error.contains("unknown variant `netwrok`"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_arg_index_rejected() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("config.toml"),
+            r#"
+            [[rules]]
+            id = "push-origin"
+            program = "git"
+            not = { arg = { first = "origin" } }
+            action = "deny"
+            "#,
+        )
+        .unwrap();
+        let loader =
+            ConfigLoader::with_paths(temp.path().to_path_buf(), temp.path().join("profiles"));
+
+        let error = format!("{:#}", loader.load().unwrap_err());
+        assert!(
+            error.contains(r#"Invalid arg index "first" in rule push-origin"#),
+            "{}",
+            error
+        );
+    }
 }
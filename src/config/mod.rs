@@ -1,5 +1,11 @@
+mod duration;
 mod loader;
 mod types;
 
+pub use duration::parse_duration;
 pub use loader::ConfigLoader;
-pub use types::{Action, Config, Profile, ProfileMetadata, ProfilesConfig, Rule, Settings};
+pub use types::{
+    Action, ArgPattern, Condition, Config, EscalationRule, Limit, Mode, Profile, ProfileMetadata,
+    ProfilesConfig, Resolution, RiskBand, RiskSettings, RiskWeights, Rule, RuleOrigin,
+    SandboxProfile, SessionLimit, Settings, DEFAULT_SANDBOX,
+};
@@ -1,5 +1,9 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 
+use crate::parser::Effect;
+
 /// The main configuration structure
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
@@ -12,6 +16,15 @@ pub struct Config {
     #[serde(default)]
     pub rules: Vec<Rule>,
 
+    /// Sandbox profiles for `action = "sandbox"`, by name
+    #[serde(default)]
+    pub sandboxes: BTreeMap<String, SandboxProfile>,
+
+    /// Active temporary grants, checked after inline rules and before profiles
+    /// (populated from `GrantStore`)
+    #[serde(skip)]
+    pub grants: Vec<Rule>,
+
     /// Loaded profile data (populated by loader)
     #[serde(skip)]
     pub loaded_profiles: Vec<Profile>,
@@ -36,11 +49,73 @@ impl Config {
         &self.available_profiles
     }
 
+    /// Sandbox profile by name. "default" is always available (the workspace, /tmp
+    /// and /dev writable, network allowed) unless overridden.
+    pub fn sandbox(&self, name: &str) -> Option<SandboxProfile> {
+        match self.sandboxes.get(name) {
+            Some(sandbox) => Some(sandbox.clone()),
+            None if name == DEFAULT_SANDBOX => Some(SandboxProfile::default()),
+            None => None,
+        }
+    }
+
     /// Check if a profile is currently active
     pub fn is_profile_active(&self, name: &str) -> bool {
         self.profiles.builtins.iter().any(|p| p == name)
             || self.profiles.custom.iter().any(|p| p == name)
     }
+
+    /// Record where each inline and profile rule came from
+    pub fn assign_rule_origins(&mut self, config_path: Option<PathBuf>) {
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            rule.origin = RuleOrigin {
+                profile: None,
+                path: config_path.clone(),
+                index,
+                grant: None,
+            };
+        }
+
+        for profile in &mut self.loaded_profiles {
+            for (index, rule) in profile.rules.iter_mut().enumerate() {
+                rule.origin = RuleOrigin {
+                    profile: Some(profile.profile.name.clone()),
+                    path: profile.path.clone(),
+                    index,
+                    grant: None,
+                };
+            }
+        }
+    }
+}
+
+/// Name of the sandbox profile used when a rule doesn't name one
+pub const DEFAULT_SANDBOX: &str = "default";
+
+/// Restrictions for commands run by `bashguard sandbox-exec`: everything is readable,
+/// only the workspace, /tmp, /dev and `writable` are writable
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxProfile {
+    /// Additional writable paths (`~/` is expanded)
+    #[serde(default)]
+    pub writable: Vec<String>,
+
+    /// Whether the command may use the network (dropped with a new network namespace)
+    #[serde(default = "default_network")]
+    pub network: bool,
+}
+
+impl Default for SandboxProfile {
+    fn default() -> Self {
+        Self {
+            writable: Vec::new(),
+            network: default_network(),
+        }
+    }
+}
+
+fn default_network() -> bool {
+    true
 }
 
 /// Global settings
@@ -50,27 +125,341 @@ pub struct Settings {
     #[serde(default = "default_action")]
     pub default_action: Action,
 
+    /// Whether decisions are enforced, or only reported (for rolling out new rules)
+    #[serde(default)]
+    pub mode: Mode,
+
     /// Whether to log decisions
     #[serde(default)]
     pub log_decisions: bool,
+
+    /// How to choose between multiple matching rules
+    #[serde(default)]
+    pub resolution: Resolution,
+
+    /// Action for otherwise-allowed commands containing expansion or substitution,
+    /// whose real arguments can't be known statically (unset keeps them allowed)
+    #[serde(default)]
+    pub dynamic_content_action: Option<Action>,
+
+    /// Action for commands whose program name is only known at runtime (`$CMD args`),
+    /// unless the deciding rule has a `dynamic_program` condition
+    #[serde(default = "default_action")]
+    pub dynamic_program_action: Action,
+
+    /// Action for commands using a known obfuscation technique (`base64 -d | sh`,
+    /// `r''m`, `$'\x72\x6d'`, ...), unless the deciding rule has an `obfuscation`
+    /// condition
+    #[serde(default = "default_action")]
+    pub obfuscation_action: Action,
+
+    /// Read make/just/npm/cargo recipes from the working directory and evaluate
+    /// their commands along with the task-runner call
+    #[serde(default)]
+    pub resolve_recipes: bool,
+
+    /// Expand unquoted glob patterns in arguments against the working directory, so
+    /// rules see the paths the shell would pass (`rm *.log` becomes `rm a.log b.log`)
+    #[serde(default)]
+    pub expand_globs: bool,
+
+    /// Patterns matching more paths than this are kept as written
+    #[serde(default = "default_max_glob_matches")]
+    pub max_glob_matches: usize,
+
+    /// Parse local shell scripts run by a command (`./deploy.sh`, `bash x.sh`,
+    /// `source env.sh`) and evaluate their commands along with the call
+    #[serde(default)]
+    pub resolve_scripts: bool,
+
+    /// Action for scripts that can't be evaluated: outside the workspace, unreadable,
+    /// larger than `max_script_size`, run by a non-shell interpreter, or task-runner
+    /// recipes that weren't found
+    #[serde(default = "default_action")]
+    pub unresolved_script_action: Action,
+
+    /// Largest script, in bytes, that is parsed
+    #[serde(default = "default_max_script_size")]
+    pub max_script_size: u64,
+
+    /// Numeric risk scoring of commands
+    #[serde(default)]
+    pub risk: RiskSettings,
+
+    /// Stricter decisions in sessions where commands were denied, so an agent
+    /// working around a denial (with `sudo`, another tool, ...) is noticed
+    #[serde(default)]
+    pub escalation: Vec<EscalationRule>,
+
+    /// Caps on how often commands run in a session (e.g., at most 5 inputs with a
+    /// network command per 10 minutes)
+    #[serde(default)]
+    pub limits: Vec<SessionLimit>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             default_action: Action::Prompt,
+            mode: Mode::default(),
             log_decisions: false,
+            resolution: Resolution::default(),
+            dynamic_content_action: None,
+            dynamic_program_action: Action::Prompt,
+            obfuscation_action: Action::Prompt,
+            resolve_recipes: false,
+            expand_globs: false,
+            max_glob_matches: default_max_glob_matches(),
+            resolve_scripts: false,
+            unresolved_script_action: Action::Prompt,
+            max_script_size: default_max_script_size(),
+            risk: RiskSettings::default(),
+            escalation: Vec::new(),
+            limits: Vec::new(),
+        }
+    }
+}
+
+/// Risk scoring: each signal a command shows adds its weight to the command's score,
+/// the input's score is the sum over its commands, and score bands map it to an
+/// action (the stricter of that and the rule decision wins)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RiskSettings {
+    /// Score commands (shown by `test`, in logs and in prompt messages) and apply
+    /// `bands`
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub weights: RiskWeights,
+
+    /// The band with the highest `min` at or below the score applies
+    #[serde(default)]
+    pub bands: Vec<RiskBand>,
+}
+
+/// Weight of each risk signal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiskWeights {
+    /// Deletes or overwrites data (the `destructive` effect)
+    pub destructive: u32,
+    /// Has a force flag of its program (`rm -f`, `git push --force`, ...)
+    pub force: u32,
+    /// Has a path argument outside the workspace
+    pub outside_workspace: u32,
+    /// Contains command substitution
+    pub substitution: u32,
+    /// Runs with elevated privileges (the `privilege` effect)
+    pub privilege: u32,
+    /// Uses an obfuscation technique
+    pub obfuscation: u32,
+    /// Talks to other hosts (the `network` effect)
+    pub network: u32,
+}
+
+impl Default for RiskWeights {
+    fn default() -> Self {
+        Self {
+            destructive: 40,
+            force: 20,
+            outside_workspace: 30,
+            substitution: 10,
+            privilege: 40,
+            obfuscation: 50,
+            network: 20,
         }
     }
 }
 
+/// Action for inputs scoring at least `min`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskBand {
+    pub min: u32,
+    pub action: Action,
+}
+
+/// Escalation after denied commands: once the session has `denials` denied
+/// commands within `window`, every command (or with `similar`, every command
+/// resembling a denied one) gets at least `action`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EscalationRule {
+    /// Denied commands needed to escalate
+    #[serde(default = "default_denials")]
+    pub denials: usize,
+
+    /// How far back denials count (e.g., "5m"; unset for the last 15 minutes)
+    #[serde(default)]
+    pub window: Option<String>,
+
+    /// Only escalate commands resembling a denied one: the same program with a shared
+    /// subcommand (or flag or target), or a shared network, destructive or privilege
+    /// effect on a shared target
+    #[serde(default)]
+    pub similar: bool,
+
+    pub action: Action,
+
+    /// Message for the agent (a default one says why and not to work around denials)
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+fn default_denials() -> usize {
+    1
+}
+
+/// A cap on how often commands run in a session: once `count` checked inputs it
+/// applies to ran within `window`, later ones get `action`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limit {
+    pub count: usize,
+
+    /// Period the count applies to (e.g., "10m"; unset for the whole session)
+    #[serde(default)]
+    pub window: Option<String>,
+
+    /// Action once the limit is reached
+    #[serde(default = "default_action")]
+    pub action: Action,
+
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// A session limit on inputs with a command matching `condition` (every input if it
+/// has no conditions)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionLimit {
+    /// Optional identifier, used in messages and as the counter's name
+    #[serde(default)]
+    pub id: Option<String>,
+
+    #[serde(flatten)]
+    pub condition: Condition,
+
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+/// Whether decisions are enforced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    /// Block, prompt and rewrite as decided
+    #[default]
+    Enforce,
+    /// Evaluate everything but never block: decisions other than allow become audits
+    /// saying what would have happened. Commands only a human may run are still
+    /// denied.
+    Audit,
+}
+
+/// Strategy for choosing between multiple rules that match the same command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolution {
+    /// First matching rule wins (inline rules, then profiles in listed order)
+    #[default]
+    FirstMatch,
+    /// Highest `priority` wins; ties go to the first matching rule
+    Priority,
+    /// Highest `priority` wins, then the most constrained rule, then the first
+    Specificity,
+    /// Strictest action wins (deny > prompt > sandbox > rewrite > warn > audit > allow),
+    /// then highest `priority`, then the first
+    DenyWins,
+}
+
 fn default_action() -> Action {
     Action::Prompt
 }
 
+fn default_max_glob_matches() -> usize {
+    100
+}
+
+fn default_max_script_size() -> u64 {
+    64 * 1024
+}
+
 /// A rule that matches commands and specifies an action
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Rule {
+    /// Optional user-supplied identifier, used in messages and logs
+    #[serde(default)]
+    pub id: Option<String>,
+
+    /// Optional human-readable name
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Conditions the command must satisfy (flat fields are the common shorthand)
+    #[serde(flatten)]
+    pub condition: Condition,
+
+    /// Action to take when rule matches
+    pub action: Action,
+
+    /// Message to display on deny/prompt
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Command to run instead, for `action = "rewrite"`. `{command}` is the matched
+    /// command as written, `{program}` its program, `{args}` its arguments and
+    /// `{positional}` those that aren't subcommands or flags (e.g., "trash {args}" or
+    /// "{command} --dry-run").
+    #[serde(default)]
+    pub rewrite: Option<String>,
+
+    /// Commands to suggest instead when the rule blocks or prompts, with the same
+    /// placeholders as `rewrite`
+    #[serde(default)]
+    pub suggest: Vec<String>,
+
+    /// Advice for the agent when the rule blocks or prompts
+    #[serde(default)]
+    pub guidance: Option<String>,
+
+    /// Sandbox profile for `action = "sandbox"` (default: "default")
+    #[serde(default)]
+    pub sandbox: Option<String>,
+
+    /// Cap on how often inputs decided by this rule run in a session (e.g.,
+    /// `{ count = 3, window = "1h" }`)
+    #[serde(default)]
+    pub limit: Option<Limit>,
+
+    /// Priority used by non-first-match resolution strategies (higher wins)
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Where the rule was loaded from (populated by loader)
+    #[serde(skip)]
+    pub origin: RuleOrigin,
+}
+
+impl Rule {
+    /// Identifier for this rule: the user-supplied `id` if set, otherwise derived
+    /// from its origin (e.g., "git/read-only#3", "inline#0" or "grant:g2")
+    pub fn id(&self) -> String {
+        if let Some(id) = &self.id {
+            return id.clone();
+        }
+        match (&self.origin.grant, &self.origin.profile) {
+            (Some(grant), _) => format!("grant:{}", grant),
+            (None, Some(profile)) => format!("{}#{}", profile, self.origin.index),
+            (None, None) => format!("inline#{}", self.origin.index),
+        }
+    }
+}
+
+/// Conditions a command must satisfy for a rule to match.
+///
+/// All fields set on a condition must hold (implicit AND). `all`, `any` and `not`
+/// nest further conditions for more complex logic.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Condition {
     /// Program name to match (e.g., "git")
     #[serde(default)]
     pub program: Option<String>,
@@ -91,6 +480,27 @@ pub struct Rule {
     #[serde(default)]
     pub args_regex: Option<String>,
 
+    /// Positional argument matchers keyed by 0-based index into the command's args
+    /// (e.g., `arg = { 0 = "origin", 1 = { glob = "release/*" } }`)
+    #[serde(default)]
+    pub arg: BTreeMap<String, ArgPattern>,
+
+    /// At least one argument must match
+    #[serde(default)]
+    pub any_arg: Option<ArgPattern>,
+
+    /// Every argument must match (vacuously true when there are no args)
+    #[serde(default)]
+    pub all_args: Option<ArgPattern>,
+
+    /// Minimum number of arguments
+    #[serde(default)]
+    pub args_min: Option<usize>,
+
+    /// Maximum number of arguments
+    #[serde(default)]
+    pub args_max: Option<usize>,
+
     /// Flags that must be present
     #[serde(default)]
     pub flags_present: Vec<String>,
@@ -99,25 +509,154 @@ pub struct Rule {
     #[serde(default)]
     pub flags_absent: Vec<String>,
 
+    /// If set, the only flags the command may have (`[]` for none)
+    #[serde(default)]
+    pub flags_only: Option<Vec<String>>,
+
+    /// Effects of which the command must have at least one (`"read"`, `"write"`,
+    /// `"network"`, `"destructive"`, `"privilege"`)
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+
+    /// Effects the command must not have (a command without known effects never
+    /// matches)
+    #[serde(default)]
+    pub effects_absent: Vec<Effect>,
+
     /// Glob pattern for working directory
     #[serde(default)]
     pub working_dir: Option<String>,
 
-    /// Action to take when rule matches
-    pub action: Action,
+    /// Hex SHA-256 the script run by the command must have (pins a reviewed script,
+    /// see `settings.resolve_scripts`)
+    #[serde(default)]
+    pub script_sha256: Option<String>,
 
-    /// Message to display on deny/prompt
+    /// If false, only match commands without parameter expansion ($VAR, ${VAR})
     #[serde(default)]
-    pub message: Option<String>,
+    pub allow_expansion: Option<bool>,
+
+    /// If false, only match commands without command substitution ($(...) or backticks)
+    #[serde(default)]
+    pub allow_substitution: Option<bool>,
+
+    /// If false, only match commands without redirection
+    #[serde(default)]
+    pub allow_redirect: Option<bool>,
+
+    /// If false, only match commands that are not part of a pipeline
+    #[serde(default)]
+    pub allow_pipe: Option<bool>,
+
+    /// If set, only match commands whose program name is (true) or is not (false)
+    /// only known at runtime
+    #[serde(default)]
+    pub dynamic_program: Option<bool>,
+
+    /// Obfuscation technique the command must use: `"any"`, `"none"` or a technique
+    /// name (e.g., `"decode-to-shell"`)
+    #[serde(default)]
+    pub obfuscation: Option<String>,
+
+    /// If set, only match commands that run (true) or don't run (false) inside a
+    /// loop (`for`, `while`, `until`)
+    #[serde(default)]
+    pub in_loop: Option<bool>,
+
+    /// If set, only match commands that only run on some paths (true), such as in
+    /// an `if` or `case` branch or after `&&`/`||`, or that always run (false)
+    #[serde(default)]
+    pub conditional: Option<bool>,
+
+    /// If set, only match commands that do (true) or don't (false) keep running in
+    /// the background (`&`, `nohup`, `setsid`, `disown`)
+    #[serde(default)]
+    pub background: Option<bool>,
+
+    /// Every nested condition must match
+    #[serde(default)]
+    pub all: Vec<Condition>,
+
+    /// At least one nested condition must match
+    #[serde(default)]
+    pub any: Vec<Condition>,
+
+    /// Nested condition must not match
+    #[serde(default)]
+    pub not: Option<Box<Condition>>,
+}
+
+/// Pattern matched against a single argument
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ArgPattern {
+    /// Plain string: the argument must equal it exactly
+    Exact(String),
+    /// Glob pattern (e.g., `{ glob = "*.tf" }`)
+    Glob { glob: String },
+    /// Regex, unanchored unless the pattern anchors itself (e.g., `{ regex = "^prod-" }`)
+    Regex { regex: String },
+}
+
+impl fmt::Display for ArgPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgPattern::Exact(s) => write!(f, "{:?}", s),
+            ArgPattern::Glob { glob } => write!(f, "glob {:?}", glob),
+            ArgPattern::Regex { regex } => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+/// Where a rule was defined
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RuleOrigin {
+    /// Profile name, or `None` for inline rules from the config file
+    pub profile: Option<String>,
+
+    /// File the rule was loaded from
+    pub path: Option<PathBuf>,
+
+    /// Index of the rule within its file
+    pub index: usize,
+
+    /// Id of the temporary grant the rule comes from (see `GrantStore`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grant: Option<String>,
+}
+
+impl fmt::Display for RuleOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.grant, &self.profile) {
+            (Some(grant), _) => write!(f, "grant {}", grant)?,
+            (None, Some(profile)) => write!(f, "profile {} rules[{}]", profile, self.index)?,
+            (None, None) => write!(f, "inline rules[{}]", self.index)?,
+        }
+        if let Some(path) = &self.path {
+            write!(f, " in {}", path.display())?;
+        }
+        Ok(())
+    }
 }
 
 /// Action to take for a command
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Action {
     Allow,
     Deny,
+    #[default]
     Prompt,
+    /// Run the command given by the rule's `rewrite` template instead (only valid on
+    /// rules)
+    Rewrite,
+    /// Allow, telling the agent and the user the message
+    Warn,
+    /// Allow, recording the message in the log and showing it to the user
+    Audit,
+    /// Run the command under `bashguard sandbox-exec` with the rule's `sandbox`
+    /// profile
+    Sandbox,
 }
 
 /// A profile containing a set of rules
@@ -128,6 +667,10 @@ pub struct Profile {
 
     #[serde(default)]
     pub rules: Vec<Rule>,
+
+    /// File the profile was loaded from (populated by loader)
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 /// Profile metadata
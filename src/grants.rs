@@ -0,0 +1,219 @@
+//! Temporary grants: allow rules added with `bashguard grant` that expire, apply to a
+//! single session, or both
+//!
+//! Grants are stored in the workspace's state directory rather than the config, so
+//! approving `git push` for the next 30 minutes doesn't mean editing (and later
+//! cleaning up) `config.toml`.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Action, ArgPattern, Condition, Rule, RuleOrigin},
+    store, ParsedCommand,
+};
+
+/// An allow rule for commands starting like `spec`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    /// Short id used by `bashguard revoke` (e.g., "g3")
+    pub id: String,
+    /// The granted command prefix (e.g., "git push"): commands with its program,
+    /// subcommands and flags, and its arguments first, are allowed
+    pub spec: String,
+    /// Session the grant is limited to (`None` for all sessions)
+    #[serde(default)]
+    pub session: Option<String>,
+    /// When the grant stops applying (`None` for the end of the session)
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+}
+
+impl Grant {
+    /// Whether the grant applies to `session` at `now`
+    pub fn is_active(&self, session: Option<&str>, now: DateTime<Utc>) -> bool {
+        let in_session = match &self.session {
+            Some(granted) => session == Some(granted.as_str()),
+            None => true,
+        };
+        in_session && !self.is_expired(now)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// The allow rule for this grant
+    pub fn rule(&self) -> Result<Rule> {
+        Ok(Rule {
+            name: Some(format!("grant `{}`", self.spec)),
+            condition: spec_condition(&self.spec)?,
+            action: Action::Allow,
+            origin: RuleOrigin {
+                grant: Some(self.id.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+}
+
+/// Condition for a command prefix: the same program and subcommands, exactly its
+/// flags (so a grant for `git push` doesn't cover `git push --force`) and its
+/// arguments in the same positions
+fn spec_condition(spec: &str) -> Result<Condition> {
+    let commands = ParsedCommand::parse_all(spec)?;
+    let [command] = commands.as_slice() else {
+        bail!("A grant must be a single command, got `{}`", spec);
+    };
+    if command.dynamic_program || command.has_expansion || command.has_substitution {
+        bail!("A grant must be a plain command, got `{}`", spec);
+    }
+
+    let mut flags_present: Vec<String> = command.flags.iter().cloned().collect();
+    flags_present.sort();
+    Ok(Condition {
+        program: Some(command.program.clone()),
+        subcommands: command.subcommands.clone(),
+        flags_only: Some(flags_present.clone()),
+        flags_present,
+        arg: command
+            .args
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| (index.to_string(), ArgPattern::Exact(arg.clone())))
+            .collect(),
+        ..Default::default()
+    })
+}
+
+/// Grants stored in `grants.json` in the workspace's state directory
+pub struct GrantStore {
+    path: PathBuf,
+}
+
+impl GrantStore {
+    /// Grant store of the current workspace
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_path(
+            store::workspace_state_dir()?.join("grants.json"),
+        ))
+    }
+
+    /// Grant store at a custom path (for testing)
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// All stored grants, including expired ones not yet cleaned up
+    pub fn load(&self) -> Result<Vec<Grant>> {
+        store::load(&self.path, "grants")
+    }
+
+    /// Allow rules of the grants active for `session` at `now`, in the order granted
+    pub fn active_rules(&self, session: Option<&str>, now: DateTime<Utc>) -> Result<Vec<Rule>> {
+        self.load()?
+            .iter()
+            .filter(|grant| grant.is_active(session, now))
+            .map(Grant::rule)
+            .collect()
+    }
+
+    /// Store a new grant for `spec`, dropping expired grants
+    pub fn grant(
+        &self,
+        spec: &str,
+        session: Option<String>,
+        duration: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> Result<Grant> {
+        if session.is_none() && duration.is_none() {
+            bail!("A grant needs a session or a duration; add a rule to config.toml to allow a command permanently");
+        }
+
+        store::update(&self.path, "grants", |grants: &mut Vec<Grant>| {
+            let next = grants
+                .iter()
+                .filter_map(|grant| grant.id.strip_prefix('g')?.parse::<u64>().ok())
+                .max()
+                .unwrap_or(0)
+                + 1;
+            let grant = Grant {
+                id: format!("g{}", next),
+                spec: spec.trim().to_string(),
+                session,
+                expires: duration.map(|duration| now + duration),
+                created: now,
+            };
+            // Fail now rather than on every check
+            grant.rule()?;
+
+            grants.retain(|grant| !grant.is_expired(now));
+            grants.push(grant.clone());
+            Ok(grant)
+        })
+    }
+
+    /// Remove the grant with `id`
+    pub fn revoke(&self, id: &str) -> Result<Grant> {
+        store::update(&self.path, "grants", |grants: &mut Vec<Grant>| {
+            let Some(index) = grants.iter().position(|grant| grant.id == id) else {
+                bail!("No grant with id {}", id);
+            };
+            Ok(grants.remove(index))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::rules::RuleMatcher;
+
+    #[test]
+    fn test_grant_lifecycle() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = GrantStore::with_path(temp_dir.path().join("grants.json"));
+        let now = Utc::now();
+
+        assert!(store.grant("git push", None, None, now).is_err());
+        let timed = store
+            .grant("git push", None, Some(Duration::minutes(30)), now)
+            .unwrap();
+        let scoped = store
+            .grant("npm publish --dry-run", Some("s1".to_string()), None, now)
+            .unwrap();
+        assert_eq!((timed.id.as_str(), scoped.id.as_str()), ("g1", "g2"));
+
+        let rules = store.active_rules(Some("s1"), now).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].id(), "grant:g1");
+        let matches = |rule: &Rule, input: &str| {
+            RuleMatcher::matches(rule, &ParsedCommand::parse_all(input).unwrap()[0])
+        };
+        assert!(matches(&rules[0], "git push origin main"));
+        assert!(!matches(&rules[0], "git push --force origin main"));
+        assert!(!matches(&rules[0], "git pull"));
+        assert!(matches(&rules[1], "npm publish --dry-run"));
+        assert!(!matches(&rules[1], "npm publish"));
+        assert!(!matches(&rules[1], "npm publish --dry-run --force"));
+
+        // Session-scoped grants only apply to their session, timed ones until expiry
+        assert_eq!(store.active_rules(Some("s2"), now).unwrap().len(), 1);
+        let later = now + Duration::hours(1);
+        assert_eq!(store.active_rules(Some("s1"), later).unwrap().len(), 1);
+        store
+            .grant("ls", None, Some(Duration::minutes(5)), later)
+            .unwrap();
+        assert_eq!(store.load().unwrap().len(), 2);
+
+        assert_eq!(store.revoke("g2").unwrap(), scoped);
+        assert!(store.revoke("g2").is_err());
+    }
+}
@@ -0,0 +1,25 @@
+//! Content hashes, used to pin scripts by content, identify approved commands and
+//! name state files
+
+use sha2::{Digest, Sha256};
+
+/// SHA-256 digest of `data` as a lowercase hex string
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_digest() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,12 +1,20 @@
 #![doc = include_str!("../README.md")]
 
+pub mod approvals;
 pub mod cli;
 pub mod config;
+pub mod grants;
+mod hash;
 pub mod logger;
 pub mod parser;
 pub mod rules;
+mod store;
+pub mod usage;
 
+pub use approvals::{Approval, ApprovalStore};
 pub use config::{Config, Profile, Settings};
-pub use logger::SessionLogger;
+pub use grants::{Grant, GrantStore};
+pub use logger::{DecisionContext, SessionLogger};
 pub use parser::ParsedCommand;
 pub use rules::{Decision, Evaluator};
+pub use usage::UsageStore;
@@ -1,13 +1,21 @@
 use std::{
     fs::{self, OpenOptions},
-    io::Write,
+    io::{BufRead, BufReader, Write},
     path::PathBuf,
 };
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{Decision, ParsedCommand};
+use crate::{
+    config::{Action, Rule},
+    parser::Effect,
+    rules::{sandbox_command, Denial, RiskScore, SessionHistory},
+    Approval, Decision, Grant, ParsedCommand,
+};
+
+/// Log for grant events of grants not limited to a session
+const GRANTS_LOG: &str = "grants";
 
 /// Log entry for a hook action
 #[derive(Debug, Serialize)]
@@ -18,7 +26,72 @@ pub struct LogEntry {
     pub parsed: ParsedCommandLog,
     pub decision: String,
     pub decision_reason: Option<String>,
-    pub matched_rule: Option<String>,
+    pub matched_rule: Option<MatchedRuleLog>,
+    /// Command run instead of `command`, for rewrite decisions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewritten_command: Option<String>,
+    /// Risk score of the input and the signals behind it (when risk scoring is on)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk: Option<RiskScore>,
+    /// Whether escalation after earlier denials made the decision (such denials
+    /// aren't escalated on again)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub escalated: bool,
+}
+
+/// How a logged decision was reached
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DecisionContext<'a> {
+    /// Rule that produced the decision
+    pub matched_rule: Option<&'a Rule>,
+    /// Risk score of the input (when risk scoring is on)
+    pub risk: Option<&'a RiskScore>,
+    /// Whether escalation after earlier denials made the decision
+    pub escalated: bool,
+}
+
+/// Log entry for a grant being added or revoked
+#[derive(Debug, Serialize)]
+pub struct GrantLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub session_id: String,
+    /// "grant" or "revoke"
+    pub event: String,
+    pub grant: Grant,
+}
+
+/// Log entry for a human approving a prompted command
+#[derive(Debug, Serialize)]
+pub struct ApprovalLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub session_id: String,
+    /// "approve"
+    pub event: String,
+    pub approval: Approval,
+}
+
+/// Identity and origin of the rule that produced a decision
+#[derive(Debug, Serialize)]
+pub struct MatchedRuleLog {
+    pub id: String,
+    pub name: Option<String>,
+    pub action: Action,
+    pub profile: Option<String>,
+    pub path: Option<PathBuf>,
+    pub index: usize,
+}
+
+impl From<&Rule> for MatchedRuleLog {
+    fn from(rule: &Rule) -> Self {
+        Self {
+            id: rule.id(),
+            name: rule.name.clone(),
+            action: rule.action,
+            profile: rule.origin.profile.clone(),
+            path: rule.origin.path.clone(),
+            index: rule.origin.index,
+        }
+    }
 }
 
 /// Simplified parsed command for logging
@@ -28,6 +101,8 @@ pub struct ParsedCommandLog {
     pub subcommands: Vec<String>,
     pub flags: Vec<String>,
     pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effects: Vec<Effect>,
 }
 
 impl From<&ParsedCommand> for ParsedCommandLog {
@@ -37,10 +112,35 @@ impl From<&ParsedCommand> for ParsedCommandLog {
             subcommands: cmd.subcommands.clone(),
             flags: cmd.flags.iter().cloned().collect(),
             args: cmd.args.clone(),
+            effects: cmd.effects.clone(),
         }
     }
 }
 
+/// The parts of a logged decision read back for the session history
+#[derive(Deserialize)]
+struct LoggedDecision {
+    timestamp: DateTime<Utc>,
+    command: String,
+    decision: String,
+    parsed: LoggedCommand,
+    #[serde(default)]
+    escalated: bool,
+}
+
+#[derive(Deserialize)]
+struct LoggedCommand {
+    program: String,
+    #[serde(default)]
+    subcommands: Vec<String>,
+    #[serde(default)]
+    flags: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    effects: Vec<Effect>,
+}
+
 /// Logger that writes to session-specific log files
 pub struct SessionLogger {
     log_dir: PathBuf,
@@ -86,7 +186,7 @@ impl SessionLogger {
         command: &str,
         parsed: &ParsedCommand,
         decision: &Decision,
-        matched_rule: Option<&crate::config::Rule>,
+        context: DecisionContext,
     ) -> std::io::Result<()> {
         self.ensure_log_dir()?;
 
@@ -94,6 +194,10 @@ impl SessionLogger {
             Decision::Allow => ("allow".to_string(), None),
             Decision::Deny { message } => ("deny".to_string(), Some(message.clone())),
             Decision::Prompt { message } => ("prompt".to_string(), Some(message.clone())),
+            Decision::Rewrite { message, .. } => ("rewrite".to_string(), Some(message.clone())),
+            Decision::Warn { message } => ("warn".to_string(), Some(message.clone())),
+            Decision::Audit { message } => ("audit".to_string(), Some(message.clone())),
+            Decision::Sandbox { message, .. } => ("sandbox".to_string(), Some(message.clone())),
         };
 
         let entry = LogEntry {
@@ -103,16 +207,89 @@ impl SessionLogger {
             parsed: ParsedCommandLog::from(parsed),
             decision: decision_str,
             decision_reason: reason,
-            matched_rule: matched_rule.map(|r| format!("{:?}", r)),
+            matched_rule: context.matched_rule.map(MatchedRuleLog::from),
+            rewritten_command: match decision {
+                Decision::Rewrite { command, .. } => Some(command.clone()),
+                Decision::Sandbox { policy, .. } => Some(sandbox_command(policy, command)),
+                _ => None,
+            },
+            risk: context.risk.cloned(),
+            escalated: context.escalated,
         };
 
+        self.append(session_id, &entry)
+    }
+
+    /// Log a grant being added or revoked, in the log of the session it is limited
+    /// to (grants for all sessions go to the `grants` log)
+    pub fn log_grant(&self, event: &str, grant: &Grant) -> std::io::Result<()> {
+        self.ensure_log_dir()?;
+
+        let session_id = grant.session.as_deref().unwrap_or(GRANTS_LOG);
+        let entry = GrantLogEntry {
+            timestamp: Utc::now(),
+            session_id: session_id.to_string(),
+            event: event.to_string(),
+            grant: grant.clone(),
+        };
+
+        self.append(session_id, &entry)
+    }
+
+    /// Log a human approving a prompted command, in the log of the session that was
+    /// prompted
+    pub fn log_approval(&self, event: &str, approval: &Approval) -> std::io::Result<()> {
+        self.ensure_log_dir()?;
+
+        let entry = ApprovalLogEntry {
+            timestamp: Utc::now(),
+            session_id: approval.session_id.clone(),
+            event: event.to_string(),
+            approval: approval.clone(),
+        };
+
+        self.append(&approval.session_id, &entry)
+    }
+
+    /// What happened earlier in a session, read back from its log
+    pub fn history(&self, session_id: &str) -> std::io::Result<SessionHistory> {
+        let log_path = self.log_file_path(session_id);
+        if !log_path.exists() {
+            return Ok(SessionHistory::default());
+        }
+
+        let mut history = SessionHistory::default();
+        for line in BufReader::new(fs::File::open(&log_path)?).lines() {
+            // Grant and approval events have no decision
+            let Ok(entry) = serde_json::from_str::<LoggedDecision>(&line?) else {
+                continue;
+            };
+            // Escalated denials would otherwise keep escalating the session themselves
+            if entry.decision == "deny" && !entry.escalated {
+                history.denials.push(Denial {
+                    timestamp: entry.timestamp,
+                    input: entry.command,
+                    program: entry.parsed.program,
+                    subcommands: entry.parsed.subcommands,
+                    flags: entry.parsed.flags,
+                    targets: entry.parsed.args,
+                    effects: entry.parsed.effects,
+                });
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Append an entry to a session's log
+    fn append(&self, session_id: &str, entry: &impl Serialize) -> std::io::Result<()> {
         let log_path = self.log_file_path(session_id);
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&log_path)?;
 
-        let json = serde_json::to_string(&entry).map_err(std::io::Error::other)?;
+        let json = serde_json::to_string(entry).map_err(std::io::Error::other)?;
 
         writeln!(file, "{}", json)?;
 
@@ -131,6 +308,7 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
+    use crate::config::{Condition, RuleOrigin};
 
     #[test]
     fn test_log_file_path_sanitization() {
@@ -158,7 +336,13 @@ mod tests {
         let decision = Decision::Allow;
 
         logger
-            .log_action("test-session", "git status", parsed, &decision, None)
+            .log_action(
+                "test-session",
+                "git status",
+                parsed,
+                &decision,
+                DecisionContext::default(),
+            )
             .unwrap();
 
         let log_path = logger.log_file_path("test-session");
@@ -169,4 +353,120 @@ mod tests {
         assert!(content.contains("test-session"));
         assert!(content.contains("\"decision\":\"allow\""));
     }
+
+    #[test]
+    fn test_log_matched_rule() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut logger = SessionLogger::new();
+        logger.log_dir = temp_dir.path().to_path_buf();
+
+        let parsed_commands = ParsedCommand::parse_all("git push").unwrap();
+        let rule = Rule {
+            condition: Condition {
+                program: Some("git".to_string()),
+                ..Default::default()
+            },
+            action: Action::Deny,
+            origin: RuleOrigin {
+                profile: Some("git/read-only".to_string()),
+                path: None,
+                index: 4,
+                grant: None,
+            },
+            ..Default::default()
+        };
+        let decision = Decision::Deny {
+            message: "Blocked".to_string(),
+        };
+
+        logger
+            .log_action(
+                "test-session",
+                "git push",
+                &parsed_commands[0],
+                &decision,
+                DecisionContext {
+                    matched_rule: Some(&rule),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let content = fs::read_to_string(logger.log_file_path("test-session")).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(entry["matched_rule"]["id"], "git/read-only#4");
+        assert_eq!(entry["matched_rule"]["profile"], "git/read-only");
+        assert_eq!(entry["matched_rule"]["index"], 4);
+        assert!(entry.get("risk").is_none());
+    }
+
+    #[test]
+    fn test_log_grant() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut logger = SessionLogger::new();
+        logger.log_dir = temp_dir.path().to_path_buf();
+
+        let mut grant = Grant {
+            id: "g1".to_string(),
+            spec: "git push".to_string(),
+            session: Some("test-session".to_string()),
+            expires: None,
+            created: Utc::now(),
+        };
+        logger.log_grant("grant", &grant).unwrap();
+        grant.session = None;
+        logger.log_grant("revoke", &grant).unwrap();
+
+        let content = fs::read_to_string(logger.log_file_path("test-session")).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(entry["event"], "grant");
+        assert_eq!(entry["grant"]["spec"], "git push");
+        assert!(logger.log_file_path(GRANTS_LOG).exists());
+    }
+
+    #[test]
+    fn test_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut logger = SessionLogger::new();
+        logger.log_dir = temp_dir.path().to_path_buf();
+        assert_eq!(logger.history("s1").unwrap(), SessionHistory::default());
+
+        let deny = Decision::Deny {
+            message: "Blocked".to_string(),
+        };
+        for (input, decision, escalated) in [
+            ("ls", &Decision::Allow, false),
+            ("curl https://example.com", &deny, false),
+            ("wget https://example.com", &deny, true),
+        ] {
+            let parsed = ParsedCommand::parse_all(input).unwrap();
+            logger
+                .log_action(
+                    "s1",
+                    input,
+                    &parsed[0],
+                    decision,
+                    DecisionContext {
+                        escalated,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+        }
+        let grant = Grant {
+            id: "g1".to_string(),
+            spec: "git push".to_string(),
+            session: Some("s1".to_string()),
+            expires: None,
+            created: Utc::now(),
+        };
+        logger.log_grant("grant", &grant).unwrap();
+
+        // Escalated denials don't feed escalation again
+        let history = logger.history("s1").unwrap();
+        assert_eq!(history.denials.len(), 1);
+        assert_eq!(history.denials[0].program, "curl");
+        assert_eq!(history.denials[0].targets, vec!["https://example.com"]);
+        assert_eq!(history.denials[0].effects, vec![Effect::Network]);
+    }
 }
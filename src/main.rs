@@ -1,4 +1,5 @@
 use std::{
+    env,
     io::{self, BufRead},
     process::exit,
 };
@@ -6,13 +7,18 @@ use std::{
 use anyhow::{Context, Result};
 use bashguard::{
     cli::{self, Cli, Command},
-    Config, Decision, Evaluator, ParsedCommand, SessionLogger,
+    parser::{self, CommandContext},
+    rules::{sandbox_command, Guidance},
+    ApprovalStore, Config, Decision, DecisionContext, Evaluator, GrantStore, ParsedCommand,
+    SessionLogger, UsageStore,
 };
+use chrono::Utc;
 use clap::Parser;
 use serde_json::Value;
 
 mod init;
 mod profiles;
+mod sandbox;
 
 fn main() {
     let cli = Cli::parse();
@@ -23,6 +29,12 @@ fn main() {
         Command::Validate(args) => validate(args),
         Command::Profiles(args) => profiles(args),
         Command::Test(args) => test(args),
+        Command::Explain(args) => explain(args),
+        Command::SandboxExec(args) => sandbox::sandbox_exec(args),
+        Command::Grant(args) => grant(args),
+        Command::Revoke(args) => revoke(args),
+        Command::Grants(args) => grants(args),
+        Command::Approve(args) => approve(args),
     };
 
     if let Err(e) = result {
@@ -32,7 +44,11 @@ fn main() {
 }
 
 fn check(args: cli::check::Args) -> Result<()> {
-    let cli::check::Args { json, format } = args;
+    let cli::check::Args {
+        json,
+        format,
+        explain,
+    } = args;
 
     let stdin = io::stdin();
     let input: String = stdin
@@ -50,45 +66,221 @@ fn check(args: cli::check::Args) -> Result<()> {
         .as_str()
         .unwrap_or("unknown-session");
 
-    let config = Config::load()?;
+    let config = load_config(Some(session_id))?;
     // Parse ALL commands in the input (handles pipelines, chains, etc.)
-    let parsed_commands = ParsedCommand::parse_all(command_str)?;
-    let evaluator = Evaluator::new(&config);
+    let parsed_commands = parse_commands(&config, command_str)?;
+    let logger = SessionLogger::new();
+    // Held until the input is counted, so concurrent checks see each other's counts
+    let usage = UsageStore::new(session_id)?;
+    let usage_lock = if Evaluator::new(&config).has_limits() {
+        Some(usage.lock()?)
+    } else {
+        None
+    };
+    let evaluator = session_evaluator(&config, &logger, Some(session_id))?;
     // Evaluate ALL commands - strictest decision wins
-    let (decision, matched_rule) = evaluator.evaluate_all_with_trace(&parsed_commands);
+    let (decision, matched_rule) = evaluator.evaluate_input(command_str, &parsed_commands);
+    let decision = recheck_rewrite(&config, &evaluator, decision);
+    let decision = if format.can_prompt() {
+        decision
+    } else {
+        approve_once(command_str, session_id, decision)?
+    };
 
-    // Log using the first parsed command for display (the raw command is still logged)
-    let logger = SessionLogger::new();
-    if let Some(first_parsed) = parsed_commands.first() {
+    // Trace goes to stderr so hook output on stdout stays parseable
+    if explain {
+        eprintln!("{}", evaluator.explain(command_str, &parsed_commands));
+    }
+
+    // Log the command that decided (the raw command is still logged), so escalation
+    // knows what was denied
+    if let Some(deciding) = evaluator.deciding_command(&parsed_commands) {
         if let Err(e) = logger.log_action(
             session_id,
             command_str,
-            first_parsed,
+            deciding,
             &decision,
-            matched_rule.as_ref(),
+            DecisionContext {
+                matched_rule: matched_rule.as_ref(),
+                risk: evaluator.risk(&parsed_commands).as_ref(),
+                escalated: evaluator.escalated(&parsed_commands),
+            },
         ) {
             eprintln!("[bashguard] Failed to log action: {}", e);
         }
     }
 
+    // Count inputs that run against their limits (prompted ones may never run)
+    if let Some(usage_lock) = &usage_lock {
+        if !matches!(decision, Decision::Deny { .. } | Decision::Prompt { .. }) {
+            let limits = evaluator.limits(&parsed_commands);
+            if let Err(e) = usage.record(usage_lock, &limits, Utc::now()) {
+                eprintln!("[bashguard] Failed to record usage: {}", e);
+            }
+        }
+    }
+
+    let guidance = evaluator.guidance(&parsed_commands);
     if json {
         let output = match format {
-            cli::Tool::Claude => format_claude_code_output(&decision),
-            cli::Tool::OpenCode => format_opencode_output(&decision),
+            cli::Tool::Claude => {
+                format_claude_code_output(&decision, &hook_input["tool_input"], guidance.as_ref())
+            }
+            cli::Tool::OpenCode => {
+                format_opencode_output(&decision, command_str, guidance.as_ref())
+            }
         };
         println!("{}", serde_json::to_string(&output)?);
     } else {
-        match &decision {
-            Decision::Allow => println!("ALLOW"),
-            Decision::Deny { message } => println!("DENY: {}", message),
-            Decision::Prompt { message } => println!("PROMPT: {}", message),
+        println!("{}", decision);
+        if let (Decision::Deny { .. } | Decision::Prompt { .. }, Some(guidance)) =
+            (&decision, &guidance)
+        {
+            println!("{}", guidance);
         }
     }
 
     Ok(())
 }
 
-fn format_claude_code_output(decision: &Decision) -> Value {
+/// Load the config along with the temporary grants active for `session_id`
+fn load_config(session_id: Option<&str>) -> Result<Config> {
+    let mut config = Config::load()?;
+    config.grants = GrantStore::new()?.active_rules(session_id, Utc::now())?;
+    Ok(config)
+}
+
+/// Evaluator for a session, knowing its counters for limits and, when escalation is
+/// configured, its history
+fn session_evaluator<'a>(
+    config: &'a Config,
+    logger: &SessionLogger,
+    session_id: Option<&str>,
+) -> Result<Evaluator<'a>> {
+    let evaluator = Evaluator::new(config);
+    let Some(session_id) = session_id else {
+        return Ok(evaluator);
+    };
+
+    let evaluator = evaluator.with_usage(UsageStore::new(session_id)?.load()?);
+    if config.settings.escalation.is_empty() {
+        return Ok(evaluator);
+    }
+    let history = logger
+        .history(session_id)
+        .context("Failed to read the session log")?;
+    Ok(evaluator.with_history(history))
+}
+
+/// Parse all commands in the input, resolving anything the settings opt into
+fn parse_commands(config: &Config, command: &str) -> Result<Vec<ParsedCommand>> {
+    let mut parsed_commands = ParsedCommand::parse_all(command)?;
+
+    if config.settings.expand_globs {
+        let cwd = env::current_dir().context("Failed to get current directory")?;
+        parsed_commands =
+            parser::expand_globs(parsed_commands, &cwd, config.settings.max_glob_matches);
+    }
+
+    if config.settings.resolve_recipes {
+        let cwd = env::current_dir().context("Failed to get current directory")?;
+        parsed_commands = parser::resolve_recipes(parsed_commands, &cwd);
+    }
+
+    if config.settings.resolve_scripts {
+        let cwd = env::current_dir().context("Failed to get current directory")?;
+        let evaluator = Evaluator::new(config);
+        parsed_commands = parser::resolve_scripts(
+            parsed_commands,
+            &cwd,
+            config.settings.max_script_size,
+            &|cmd| evaluator.trusts_script(cmd),
+        );
+    }
+
+    Ok(parsed_commands)
+}
+
+/// For tools that can't ask the user: allow a prompted command a human approved with
+/// `bashguard approve`, or prompt with an id to approve it by
+fn approve_once(command: &str, session_id: &str, decision: Decision) -> Result<Decision> {
+    let Decision::Prompt { message } = decision else {
+        return Ok(decision);
+    };
+
+    let store = ApprovalStore::new()?;
+    let cwd = env::current_dir().context("Failed to get current directory")?;
+    let now = Utc::now();
+    if let Some(approval) = store.consume(command, &cwd, now)? {
+        return Ok(Decision::Audit {
+            message: format!("Approved once (approval {}): {}", approval.id, message),
+        });
+    }
+
+    let approval = store.request(command, &cwd, session_id, now)?;
+    Ok(Decision::Prompt {
+        message: format!(
+            "{} (to allow it once, run `bashguard approve {}`)",
+            message, approval.id
+        ),
+    })
+}
+
+/// Evaluate a rewritten command against the policy before handing it back: it is only
+/// returned if allowed as is, otherwise its decision applies to the original command
+fn recheck_rewrite(config: &Config, evaluator: &Evaluator, decision: Decision) -> Decision {
+    let Decision::Rewrite { command, message } = decision else {
+        return decision;
+    };
+
+    let parsed_commands = match parse_commands(config, &command) {
+        Ok(parsed_commands) => parsed_commands,
+        Err(e) => {
+            return Decision::Prompt {
+                message: format!("Rewritten command `{}` cannot be parsed: {}", command, e),
+            }
+        }
+    };
+
+    match evaluator.evaluate_input(&command, &parsed_commands).0 {
+        Decision::Allow | Decision::Warn { .. } | Decision::Audit { .. } => {
+            Decision::Rewrite { command, message }
+        }
+        Decision::Deny { message } => Decision::Deny {
+            message: format!("Rewritten command `{}` is blocked: {}", command, message),
+        },
+        Decision::Prompt { message } => Decision::Prompt {
+            message: format!(
+                "Rewritten command `{}` needs approval: {}",
+                command, message
+            ),
+        },
+        Decision::Sandbox { policy, .. } => Decision::Rewrite {
+            command: sandbox_command(&policy, &command),
+            message,
+        },
+        Decision::Rewrite { .. } => Decision::Prompt {
+            message: format!(
+                "Rewritten command `{}` would be rewritten again: {}",
+                command, message
+            ),
+        },
+    }
+}
+
+/// Message for the agent, followed by what was blocked and what to do instead
+fn with_guidance(message: &str, guidance: Option<&Guidance>) -> String {
+    match guidance {
+        Some(guidance) => format!("{}\n\n{}", message, guidance),
+        None => message.to_string(),
+    }
+}
+
+fn format_claude_code_output(
+    decision: &Decision,
+    tool_input: &Value,
+    guidance: Option<&Guidance>,
+) -> Value {
     match decision {
         Decision::Allow => serde_json::json!({
             "hookSpecificOutput": {
@@ -101,26 +293,90 @@ fn format_claude_code_output(decision: &Decision) -> Value {
             "hookSpecificOutput": {
                 "hookEventName": "PreToolUse",
                 "permissionDecision": "deny",
-                "permissionDecisionReason": message
+                "permissionDecisionReason": with_guidance(message, guidance)
             }
         }),
         Decision::Prompt { message } => serde_json::json!({
             "hookSpecificOutput": {
                 "hookEventName": "PreToolUse",
                 "permissionDecision": "ask",
-                "permissionDecisionReason": message
+                "permissionDecisionReason": with_guidance(message, guidance)
             }
         }),
+        Decision::Rewrite { command, message } => {
+            // Keep the tool's other inputs (description, timeout, ...)
+            let mut updated_input = tool_input.clone();
+            updated_input["command"] = Value::from(command.as_str());
+            serde_json::json!({
+                "hookSpecificOutput": {
+                    "hookEventName": "PreToolUse",
+                    "permissionDecision": "allow",
+                    "permissionDecisionReason": message,
+                    "updatedInput": updated_input
+                }
+            })
+        }
+        Decision::Warn { message } => serde_json::json!({
+            "hookSpecificOutput": {
+                "hookEventName": "PreToolUse",
+                "permissionDecision": "allow",
+                "permissionDecisionReason": message,
+                "additionalContext": format!("[bashguard] {}", message)
+            },
+            "systemMessage": format!("[bashguard] {}", message)
+        }),
+        // Audits don't approve the command: the tool's own permission checks still apply
+        Decision::Audit { message } => serde_json::json!({
+            "systemMessage": format!("[bashguard audit] {}", message)
+        }),
+        // Sandboxed inputs run as a rewrite to `bashguard sandbox-exec`
+        Decision::Sandbox { policy, message } => format_claude_code_output(
+            &Decision::Rewrite {
+                command: sandbox_command(policy, tool_input["command"].as_str().unwrap_or("")),
+                message: message.clone(),
+            },
+            tool_input,
+            guidance,
+        ),
     }
 }
 
-fn format_opencode_output(decision: &Decision) -> Value {
+fn format_opencode_output(
+    decision: &Decision,
+    command: &str,
+    guidance: Option<&Guidance>,
+) -> Value {
     match decision {
         Decision::Allow => serde_json::json!({ "allow": true }),
-        Decision::Deny { message } => serde_json::json!({ "abort": message }),
+        Decision::Deny { message } => serde_json::json!({
+            "abort": with_guidance(message, guidance),
+            "guidance": guidance
+        }),
         Decision::Prompt { message } => serde_json::json!({
-            "abort": format!("[Requires approval] {}", message)
+            "abort": format!("[Requires approval] {}", with_guidance(message, guidance)),
+            "guidance": guidance
+        }),
+        Decision::Rewrite { command, message } => serde_json::json!({
+            "allow": true,
+            "rewrite": command,
+            "message": message
+        }),
+        Decision::Warn { message } => serde_json::json!({
+            "allow": true,
+            "warning": message
+        }),
+        Decision::Audit { message } => serde_json::json!({
+            "allow": true,
+            "warning": format!("[audit] {}", message)
         }),
+        Decision::Sandbox { policy, message } => format_opencode_output(
+            &Decision::Rewrite {
+                command: sandbox_command(policy, command),
+                message: message.clone(),
+            },
+            command,
+            guidance,
+        ),
     }
 }
 
@@ -141,19 +397,31 @@ fn profiles(args: cli::profiles::Args) -> Result<()> {
 }
 
 fn test(args: cli::test::Args) -> Result<()> {
-    let cli::test::Args { command } = args;
+    let cli::test::Args { command, session } = args;
 
-    let config = Config::load()?;
+    let config = load_config(session.as_deref())?;
     // Parse ALL commands in the input
-    let parsed_commands = ParsedCommand::parse_all(&command)?;
-    let evaluator = Evaluator::new(&config);
+    let parsed_commands = parse_commands(&config, &command)?;
+    let evaluator = session_evaluator(&config, &SessionLogger::new(), session.as_deref())?;
     // Evaluate ALL commands
-    let (decision, matched_rule) = evaluator.evaluate_all_with_trace(&parsed_commands);
+    let (decision, matched_rule) = evaluator.evaluate_input(&command, &parsed_commands);
+    let decision = recheck_rewrite(&config, &evaluator, decision);
 
     println!("Command: {}", command);
     println!("\nParsed ({} command(s)):", parsed_commands.len());
     for (i, parsed) in parsed_commands.iter().enumerate() {
         println!("  [{}] Program: {}", i + 1, parsed.program);
+        if let Some(span) = parsed.span {
+            for line in span.underline(&command).lines() {
+                println!("      | {}", line);
+            }
+        }
+        if !parsed.nesting.is_empty() {
+            println!("      Nested in: {}", parsed.nesting_path());
+        }
+        if !parsed.control.is_empty() {
+            println!("      Control flow: {}", parsed.control);
+        }
         println!("      Subcommands: {:?}", parsed.subcommands);
         println!("      Flags: {:?}", parsed.flags);
         println!("      Args: {:?}", parsed.args);
@@ -163,14 +431,172 @@ fn test(args: cli::test::Args) -> Result<()> {
         if parsed.has_substitution {
             println!("      Has substitution: yes");
         }
+        if parsed.dynamic_program {
+            println!("      Dynamic program: yes");
+        }
+        if !parsed.dynamic_args.is_empty() {
+            println!("      Dynamic args: {:?}", parsed.dynamic_args);
+        }
+        if !parsed.effects.is_empty() {
+            let effects: Vec<&str> = parsed.effects.iter().map(|e| e.name()).collect();
+            println!("      Effects: {}", effects.join(", "));
+        }
+        if let Some(risk) = evaluator.command_risk(parsed) {
+            println!("      Risk: {}", risk);
+        }
+        if !parsed.obfuscation.is_empty() {
+            let techniques: Vec<&str> = parsed.obfuscation.iter().map(|o| o.name()).collect();
+            println!("      Obfuscation: {}", techniques.join(", "));
+        }
+        if parsed.has_redirect {
+            println!("      Has redirect: yes");
+        }
+        if parsed.is_piped {
+            println!("      Piped: yes");
+        }
+        if let Some(outer) = &parsed.embedded_in {
+            println!("      Embedded in: {}", outer);
+        }
+        if parsed.context != CommandContext::Local {
+            println!("      Runs: {}", parsed.context);
+        }
+        if let Some(source) = &parsed.file_source {
+            println!("      From: {}", source);
+        }
+        if let Some(script) = &parsed.script {
+            println!("      Script: {}", script);
+        }
     }
 
+    if let Some(risk) = evaluator.risk(&parsed_commands) {
+        println!("\nRisk score: {}", risk);
+    }
     println!("\nOverall Decision: {:?}", decision);
     if let Some(rule) = matched_rule {
-        println!("Matched rule: {:?}", rule);
+        match &rule.name {
+            Some(name) => println!("Matched rule: {} ({})", rule.id(), name),
+            None => println!("Matched rule: {}", rule.id()),
+        }
+        println!("Defined in: {}", rule.origin);
     } else {
         println!("Matched rule: (default action)");
     }
+    if let (Decision::Deny { .. } | Decision::Prompt { .. }, Some(guidance)) =
+        (&decision, evaluator.guidance(&parsed_commands))
+    {
+        println!("\n{}", guidance);
+    }
+
+    Ok(())
+}
+
+fn explain(args: cli::explain::Args) -> Result<()> {
+    let cli::explain::Args {
+        command,
+        json,
+        session,
+    } = args;
+
+    let config = load_config(session.as_deref())?;
+    let parsed_commands = parse_commands(&config, &command)?;
+    let evaluator = session_evaluator(&config, &SessionLogger::new(), session.as_deref())?;
+    let explanation = evaluator.explain(&command, &parsed_commands);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&explanation)?);
+    } else {
+        println!("{}", explanation);
+    }
+
+    Ok(())
+}
+
+fn grant(args: cli::grant::Args) -> Result<()> {
+    let cli::grant::Args {
+        duration,
+        session,
+        spec,
+    } = args;
+
+    let grant = GrantStore::new()?.grant(&spec.join(" "), session, duration, Utc::now())?;
+    if let Err(e) = SessionLogger::new().log_grant("grant", &grant) {
+        eprintln!("[bashguard] Failed to log grant: {}", e);
+    }
+
+    println!("Granted {}: {}", grant.id, describe_grant(&grant));
+
+    Ok(())
+}
+
+fn revoke(args: cli::revoke::Args) -> Result<()> {
+    let store = GrantStore::new()?;
+    let logger = SessionLogger::new();
+
+    for id in &args.ids {
+        let grant = store.revoke(id)?;
+        if let Err(e) = logger.log_grant("revoke", &grant) {
+            eprintln!("[bashguard] Failed to log revoke: {}", e);
+        }
+        println!("Revoked {}: {}", grant.id, describe_grant(&grant));
+    }
+
+    Ok(())
+}
+
+fn grants(args: cli::grants::Args) -> Result<()> {
+    let now = Utc::now();
+    let grants: Vec<_> = GrantStore::new()?
+        .load()?
+        .into_iter()
+        .filter(|grant| args.all || !grant.is_expired(now))
+        .collect();
+
+    if grants.is_empty() {
+        println!("No grants.");
+    }
+    for grant in &grants {
+        let expired = if grant.is_expired(now) {
+            " (expired)"
+        } else {
+            ""
+        };
+        println!("{}: {}{}", grant.id, describe_grant(grant), expired);
+    }
+
+    Ok(())
+}
+
+/// What a grant allows, for how long and where
+fn describe_grant(grant: &bashguard::Grant) -> String {
+    let mut description = format!("`{}`", grant.spec);
+    match &grant.expires {
+        Some(expires) => description.push_str(&format!(
+            " until {}",
+            expires
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        )),
+        None => description.push_str(" until the session ends"),
+    }
+    match &grant.session {
+        Some(session) => description.push_str(&format!(" in session {}", session)),
+        None => description.push_str(" in all sessions"),
+    }
+    description
+}
+
+fn approve(args: cli::approve::Args) -> Result<()> {
+    let approval = ApprovalStore::new()?.approve(&args.id, Utc::now())?;
+    if let Err(e) = SessionLogger::new().log_approval("approve", &approval) {
+        eprintln!("[bashguard] Failed to log approval: {}", e);
+    }
+
+    println!(
+        "Approved {}: `{}` in {} (allowed once)",
+        approval.id,
+        approval.command,
+        approval.cwd.display()
+    );
 
     Ok(())
 }
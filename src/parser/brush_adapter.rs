@@ -3,12 +3,26 @@
 //! This module provides functionality to parse shell commands using brush-parser
 //! and convert the resulting AST into Vec<ParsedCommand> for rule evaluation.
 
-use std::collections::HashMap;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
 
 use anyhow::{bail, Result};
-use brush_parser::{ast, parse_tokens, tokenize_str, unquote_str, ParserOptions, SourceInfo};
+use brush_parser::{
+    ast::{self, SourceLocation},
+    parse_tokens, tokenize_str, ParserOptions, SourceInfo,
+};
 
-use super::{command::ParsedCommand, semantic::SemanticAnalyzer};
+use super::{
+    command::{CommandContext, ControlFlow, Loop, LoopKind, Nesting, ParsedCommand, Span},
+    effects::{self, Effect},
+    embedded::{extract_embedded, Embedded, EmbeddedCommand},
+    expansion::{command_substitutions, expand_word, heredoc_substitutions, Expanded},
+    obfuscation,
+    semantic::SemanticAnalyzer,
+};
 
 /// Parse a command string using brush-parser and return all commands found.
 ///
@@ -16,8 +30,23 @@ use super::{command::ParsedCommand, semantic::SemanticAnalyzer};
 /// not just the first command. This is a security-critical design decision to prevent
 /// bypass via: `allowed-cmd | blocked-cmd` or `safe-cmd && dangerous-cmd`
 pub fn parse_with_brush(input: &str) -> Result<Vec<ParsedCommand>> {
+    let mut results = Vec::new();
+    let ctx = ExtractionContext::new(input);
+    extract_from_input(&ctx, &mut results)?;
+
+    // If we got nothing but input wasn't empty, that's an error
+    if results.is_empty() && !input.trim().is_empty() {
+        bail!("No commands found in input");
+    }
+
+    Ok(results)
+}
+
+/// Parse the context's input and extract all commands from it
+fn extract_from_input(ctx: &ExtractionContext, results: &mut Vec<ParsedCommand>) -> Result<()> {
     // Tokenize the input
-    let tokens = tokenize_str(input).map_err(|e| anyhow::anyhow!("Tokenizer error: {:?}", e))?;
+    let tokens =
+        tokenize_str(ctx.input).map_err(|e| anyhow::anyhow!("Tokenizer error: {:?}", e))?;
 
     // Parse tokens into AST
     let options = ParserOptions::default();
@@ -25,30 +54,140 @@ pub fn parse_with_brush(input: &str) -> Result<Vec<ParsedCommand>> {
     let program = parse_tokens(&tokens, &options, &source_info)
         .map_err(|e| anyhow::anyhow!("Parse error: {:?}", e))?;
 
-    let mut results = Vec::new();
-    let ctx = ExtractionContext::new(input);
-
     // Walk AST: Program contains complete_commands (which are CompoundLists)
     for complete_command in &program.complete_commands {
-        extract_from_compound_list(complete_command, &ctx, &mut results)?;
-    }
-
-    // If we got nothing but input wasn't empty, that's an error
-    if results.is_empty() && !input.trim().is_empty() {
-        bail!("No commands found in input");
+        extract_from_compound_list(complete_command, ctx, results)?;
     }
 
-    Ok(results)
+    Ok(())
 }
 
-/// Context for command extraction, carrying the original input
+/// Context for command extraction, carrying the text being parsed, where it sits in
+/// the original input and the shell variables known so far
 struct ExtractionContext<'a> {
     input: &'a str,
+    /// Byte offset of `input` in the original input, or `None` if it doesn't appear
+    /// there as written
+    offset: Option<usize>,
+    /// Span of the command `input` was taken from, used when `offset` is unknown
+    enclosing: Option<Span>,
+    /// Constructs the code being extracted is nested in
+    nesting: RefCell<Vec<Nesting>>,
+    /// Loops, branches and backgrounding around the code being extracted
+    control: RefCell<ControlFlow>,
+    /// Variables whose value is statically known from earlier assignments in the
+    /// input (e.g., `X=rm; $X -rf /`). Unknown variables are absent.
+    vars: RefCell<HashMap<String, String>>,
+    /// Whether variables may change without an assignment the parser sees (after a
+    /// nameref or trap is set), so none are tracked any more
+    untracked: Cell<bool>,
+    /// Shell functions defined so far, any of which may assign any variable
+    functions: RefCell<HashSet<String>>,
+    /// Whether the commands are thrown away and only the variables assigned matter,
+    /// as in the first pass over a loop body
+    discovering: Cell<bool>,
 }
 
 impl<'a> ExtractionContext<'a> {
     fn new(input: &'a str) -> Self {
-        Self { input }
+        Self {
+            input,
+            offset: Some(0),
+            enclosing: None,
+            nesting: RefCell::new(Vec::new()),
+            control: RefCell::new(ControlFlow::default()),
+            vars: RefCell::new(HashMap::new()),
+            untracked: Cell::new(false),
+            functions: RefCell::new(HashSet::new()),
+            discovering: Cell::new(false),
+        }
+    }
+
+    /// Run `extract` with the control flow changed by `update`
+    fn with_control<T>(
+        &self,
+        update: impl FnOnce(&mut ControlFlow),
+        extract: impl FnOnce() -> T,
+    ) -> T {
+        let before = self.control.borrow().clone();
+        update(&mut self.control.borrow_mut());
+        let result = extract();
+        *self.control.borrow_mut() = before;
+        result
+    }
+
+    /// Run `extract` for code nested in `nesting`
+    fn nested<T>(&self, nesting: Nesting, extract: impl FnOnce() -> T) -> T {
+        self.nesting.borrow_mut().push(nesting);
+        let result = extract();
+        self.nesting.borrow_mut().pop();
+        result
+    }
+
+    /// Byte offset in `input` of a parser location (a character index)
+    fn byte_index(&self, index: usize) -> usize {
+        if self.input.is_ascii() {
+            return index.min(self.input.len());
+        }
+        self.input
+            .char_indices()
+            .nth(index)
+            .map_or(self.input.len(), |(i, _)| i)
+    }
+
+    /// Text and span of the code at a parser location (the whole input if unknown)
+    fn locate(&self, location: Option<(usize, usize)>) -> (String, Option<Span>) {
+        let (start, end) = match location {
+            Some((start, end)) => (self.byte_index(start), self.byte_index(end)),
+            None => (0, self.input.len()),
+        };
+        let end = start + self.input[start..end.max(start)].trim_end().len();
+        let span = match self.offset {
+            Some(offset) => Some(Span {
+                start: offset + start,
+                end: offset + end,
+            }),
+            None => self.enclosing,
+        };
+        (self.input[start..end].to_string(), span)
+    }
+
+    fn vars(&self) -> HashMap<String, String> {
+        self.vars.borrow().clone()
+    }
+
+    fn set_vars(&self, vars: HashMap<String, String>) {
+        *self.vars.borrow_mut() = vars;
+    }
+
+    /// Forget variables changed since `before`, for code that may or may not run
+    fn keep_unchanged(&self, before: &HashMap<String, String>) {
+        self.vars
+            .borrow_mut()
+            .retain(|name, value| before.get(name) == Some(value));
+    }
+
+    fn assign(&self, name: &str, value: Option<String>) {
+        // Known values are split on the default IFS, so stop tracking once it changes
+        if name == "IFS" {
+            self.untrack();
+            return;
+        }
+        let mut vars = self.vars.borrow_mut();
+        match value {
+            Some(value) if !self.untracked.get() => vars.insert(name.to_string(), value),
+            _ => vars.remove(name),
+        };
+    }
+
+    fn forget_all(&self) {
+        self.vars.borrow_mut().clear();
+    }
+
+    /// Stop tracking variables for the rest of the input
+    fn untrack(&self) {
+        self.untracked.set(true);
+        self.forget_all();
     }
 }
 
@@ -61,7 +200,18 @@ fn extract_from_compound_list(
     // CompoundList is a tuple struct containing Vec<CompoundListItem>
     for item in &compound_list.0 {
         // CompoundListItem is (AndOrList, SeparatorOperator)
-        extract_from_and_or_list(&item.0, ctx, results)?;
+        match item.1 {
+            // Background commands run in a subshell, so their assignments don't persist
+            ast::SeparatorOperator::Async => {
+                let before = ctx.vars();
+                ctx.with_control(
+                    |control| control.background = true,
+                    || extract_from_and_or_list(&item.0, ctx, results),
+                )?;
+                ctx.set_vars(before);
+            }
+            ast::SeparatorOperator::Sequence => extract_from_and_or_list(&item.0, ctx, results)?,
+        }
     }
     Ok(())
 }
@@ -75,13 +225,18 @@ fn extract_from_and_or_list(
     // First pipeline
     extract_from_pipeline(&and_or.first, ctx, results)?;
 
-    // Additional pipelines (joined by && or ||)
+    // Additional pipelines (joined by && or ||) may not run
+    let before = ctx.vars();
     for item in &and_or.additional {
         let pipeline = match item {
             ast::AndOr::And(p) | ast::AndOr::Or(p) => p,
         };
-        extract_from_pipeline(pipeline, ctx, results)?;
+        ctx.with_control(
+            |control| control.conditional = true,
+            || extract_from_pipeline(pipeline, ctx, results),
+        )?;
     }
+    ctx.keep_unchanged(&before);
 
     Ok(())
 }
@@ -94,8 +249,23 @@ fn extract_from_pipeline(
 ) -> Result<()> {
     let is_piped = pipeline.seq.len() > 1;
 
-    for command in &pipeline.seq {
-        extract_from_command(command, ctx, is_piped, results)?;
+    let mut stages = Vec::new();
+    for (index, command) in pipeline.seq.iter().enumerate() {
+        stages.push(results.len());
+        if !is_piped {
+            extract_from_command(command, ctx, is_piped, results)?;
+            continue;
+        }
+
+        // Each side of a pipe runs in its own subshell
+        let before = ctx.vars();
+        ctx.nested(Nesting::Pipeline { index }, || {
+            extract_from_command(command, ctx, is_piped, results)
+        })?;
+        ctx.set_vars(before);
+    }
+    if is_piped {
+        obfuscation::detect_in_pipeline(results, &stages);
     }
 
     Ok(())
@@ -110,24 +280,74 @@ fn extract_from_command(
 ) -> Result<()> {
     match cmd {
         ast::Command::Simple(simple) => {
-            if let Some(parsed) = extract_simple_command(simple, ctx, is_piped)? {
-                results.push(parsed);
-            }
+            extract_simple_command(simple, ctx, is_piped, results)?;
         }
-        ast::Command::Compound(compound, _redirects) => {
+        ast::Command::Compound(compound, redirects) => {
+            // Redirects of the whole command are expanded before it runs
+            let (_, span) = ctx.locate(
+                compound
+                    .location()
+                    .map(|loc| (loc.start.index, loc.end.index)),
+            );
+            for redirect in redirects.iter().flat_map(|list| &list.0) {
+                extract_from_redirect(redirect, span, ctx, results)?;
+            }
             extract_from_compound_command(compound, ctx, results)?;
         }
         ast::Command::ExtendedTest(_test_expr) => {
             // Extended test expressions [[ ... ]] - these don't execute commands
             // but we could potentially analyze them in the future
         }
-        ast::Command::Function(_func_def) => {
-            // Function definitions don't execute immediately, skip
+        ast::Command::Function(func_def) => {
+            // Function definitions don't execute immediately, but calling one later may
+            // assign any variable
+            ctx.functions
+                .borrow_mut()
+                .insert(func_def.fname.value.clone());
         }
     }
     Ok(())
 }
 
+/// Extract the commands run by substitutions in a redirect of a compound command
+/// (spanning `span`), such as `done < <(CMD)` or a here document's `$(CMD)`
+fn extract_from_redirect(
+    redirect: &ast::IoRedirect,
+    span: Option<Span>,
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+) -> Result<()> {
+    let vars = ctx.vars();
+    let (word, subshell) = redirect_target(redirect);
+    if let Some(word) = word {
+        extract_substitutions(
+            word,
+            command_substitutions(&word.value),
+            span,
+            &vars,
+            ctx,
+            results,
+        );
+    }
+    if let Some(body) = expanded_heredoc(redirect) {
+        extract_substitutions(
+            body,
+            heredoc_substitutions(&body.value),
+            span,
+            &vars,
+            ctx,
+            results,
+        );
+    }
+    if let Some(subshell) = subshell {
+        ctx.nested(Nesting::ProcessSubstitution, || {
+            extract_from_compound_list(&subshell.list, ctx, results)
+        })?;
+        ctx.set_vars(vars);
+    }
+    Ok(())
+}
+
 /// Extract commands from compound commands (if/for/while/case/subshell/brace)
 fn extract_from_compound_command(
     compound: &ast::CompoundCommand,
@@ -136,50 +356,87 @@ fn extract_from_compound_command(
 ) -> Result<()> {
     match compound {
         ast::CompoundCommand::Subshell(subshell) => {
-            // Recursively extract from subshell
-            extract_from_compound_list(&subshell.list, ctx, results)?;
+            // Recursively extract from subshell, whose assignments don't persist
+            let before = ctx.vars();
+            ctx.nested(Nesting::Subshell, || {
+                extract_from_compound_list(&subshell.list, ctx, results)
+            })?;
+            ctx.set_vars(before);
         }
         ast::CompoundCommand::BraceGroup(brace) => {
-            extract_from_compound_list(&brace.list, ctx, results)?;
+            ctx.nested(Nesting::BraceGroup, || {
+                extract_from_compound_list(&brace.list, ctx, results)
+            })?;
         }
         ast::CompoundCommand::ForClause(for_clause) => {
+            // The values are expanded once, before the loop runs
+            let values = for_clause.values.as_deref().unwrap_or_default();
+            let vars = ctx.vars();
+            for word in values {
+                let (_, span) = ctx.locate(word.location().map(|l| (l.start.index, l.end.index)));
+                let ranges = command_substitutions(&word.value);
+                extract_substitutions(word, ranges, span, &vars, ctx, results);
+            }
+
             // for_clause.body is DoGroupCommand which has list: CompoundList
-            extract_from_compound_list(&for_clause.body.list, ctx, results)?;
+            let for_loop = Loop {
+                kind: LoopKind::For,
+                variable: Some(for_clause.variable_name.clone()),
+                words: values.iter().map(|w| w.value.clone()).collect(),
+            };
+            extract_from_loop(ctx, results, for_loop, |results| {
+                extract_from_compound_list(&for_clause.body.list, ctx, results)
+            })?;
         }
         ast::CompoundCommand::CaseClause(case_clause) => {
-            // Extract commands from each case item
-            for item in &case_clause.cases {
-                if let Some(cmd) = &item.cmd {
-                    extract_from_compound_list(cmd, ctx, results)?;
-                }
-            }
+            // Extract commands from each case item, any one of which may run
+            let branches: Vec<_> = case_clause
+                .cases
+                .iter()
+                .filter_map(|item| item.cmd.as_ref())
+                .collect();
+            extract_from_branches(ctx, results, branches.len(), true, |i, results| {
+                extract_from_compound_list(branches[i], ctx, results)
+            })?;
         }
         ast::CompoundCommand::IfClause(if_clause) => {
             // Extract from condition and body
             extract_from_compound_list(&if_clause.condition, ctx, results)?;
-            extract_from_compound_list(&if_clause.then, ctx, results)?;
 
-            // Extract from else clauses
-            if let Some(elses) = &if_clause.elses {
-                for else_clause in elses {
-                    if let Some(condition) = &else_clause.condition {
-                        extract_from_compound_list(condition, ctx, results)?;
-                    }
-                    extract_from_compound_list(&else_clause.body, ctx, results)?;
+            // Then the body and else clauses, only one of which runs
+            let elses = if_clause.elses.as_deref().unwrap_or_default();
+            let has_else = elses.iter().any(|e| e.condition.is_none());
+            extract_from_branches(ctx, results, elses.len() + 1, !has_else, |i, results| {
+                if i == 0 {
+                    return extract_from_compound_list(&if_clause.then, ctx, results);
                 }
-            }
+                let else_clause = &elses[i - 1];
+                if let Some(condition) = &else_clause.condition {
+                    extract_from_compound_list(condition, ctx, results)?;
+                }
+                extract_from_compound_list(&else_clause.body, ctx, results)
+            })?;
         }
         ast::CompoundCommand::WhileClause(while_clause) => {
             // WhileOrUntilClauseCommand is a tuple struct (CompoundList, DoGroupCommand, TokenLocation)
-            extract_from_compound_list(&while_clause.0, ctx, results)?;
-            extract_from_compound_list(&while_clause.1.list, ctx, results)?;
+            extract_from_loop(ctx, results, plain_loop(LoopKind::While), |results| {
+                extract_from_compound_list(&while_clause.0, ctx, results)?;
+                extract_from_compound_list(&while_clause.1.list, ctx, results)
+            })?;
         }
         ast::CompoundCommand::UntilClause(until_clause) => {
-            extract_from_compound_list(&until_clause.0, ctx, results)?;
-            extract_from_compound_list(&until_clause.1.list, ctx, results)?;
+            extract_from_loop(ctx, results, plain_loop(LoopKind::Until), |results| {
+                extract_from_compound_list(&until_clause.0, ctx, results)?;
+                extract_from_compound_list(&until_clause.1.list, ctx, results)
+            })?;
         }
         ast::CompoundCommand::ArithmeticForClause(arith_for) => {
-            extract_from_compound_list(&arith_for.body.list, ctx, results)?;
+            extract_from_loop(
+                ctx,
+                results,
+                plain_loop(LoopKind::ArithmeticFor),
+                |results| extract_from_compound_list(&arith_for.body.list, ctx, results),
+            )?;
         }
         ast::CompoundCommand::Arithmetic(_) => {
             // Arithmetic commands don't execute other commands
@@ -188,35 +445,146 @@ fn extract_from_compound_command(
     Ok(())
 }
 
-/// Extract a simple command into ParsedCommand
+/// Extract alternative branches, only one of which runs (`may_skip` if possibly
+/// none). Afterwards, only variables every path agrees on stay known.
+fn extract_from_branches(
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+    count: usize,
+    may_skip: bool,
+    mut extract: impl FnMut(usize, &mut Vec<ParsedCommand>) -> Result<()>,
+) -> Result<()> {
+    let before = ctx.vars();
+    let mut merged = before.clone();
+    let mut first = !may_skip;
+
+    for i in 0..count {
+        ctx.set_vars(before.clone());
+        ctx.with_control(|control| control.conditional = true, || extract(i, results))?;
+        let after = ctx.vars();
+        if first {
+            merged = after;
+            first = false;
+        } else {
+            merged.retain(|name, value| after.get(name) == Some(value));
+        }
+    }
+
+    ctx.set_vars(merged);
+    Ok(())
+}
+
+/// A loop without a loop variable
+fn plain_loop(kind: LoopKind) -> Loop {
+    Loop {
+        kind,
+        variable: None,
+        words: Vec::new(),
+    }
+}
+
+/// Extract a loop body, which may run any number of times. Variables assigned in
+/// the body (and the loop variable) are unknown inside and after it.
+fn extract_from_loop(
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+    body_loop: Loop,
+    mut extract: impl FnMut(&mut Vec<ParsedCommand>) -> Result<()>,
+) -> Result<()> {
+    let variable = body_loop.variable.clone();
+    ctx.with_control(
+        |control| control.loops.push(body_loop),
+        || {
+            // Only what the body assigns matters in a first pass over an enclosing
+            // loop, so one pass is enough (two would make nesting exponential)
+            if ctx.discovering.get() {
+                if let Some(variable) = &variable {
+                    ctx.assign(variable, None);
+                }
+                return extract(results);
+            }
+
+            // A first pass finds what the body assigns, so later iterations aren't
+            // evaluated with values from before the loop
+            let before = ctx.vars();
+            ctx.discovering.set(true);
+            let discovered = extract(&mut Vec::new());
+            ctx.discovering.set(false);
+            discovered?;
+            ctx.keep_unchanged(&before);
+
+            if let Some(variable) = &variable {
+                ctx.assign(variable, None);
+            }
+            let stable = ctx.vars();
+            extract(results)?;
+            ctx.keep_unchanged(&stable);
+            Ok(())
+        },
+    )
+}
+
+/// Extract a simple command into ParsedCommand, followed by any commands embedded
+/// in its arguments
 fn extract_simple_command(
     cmd: &ast::SimpleCommand,
     ctx: &ExtractionContext,
     is_piped: bool,
-) -> Result<Option<ParsedCommand>> {
+    results: &mut Vec<ParsedCommand>,
+) -> Result<()> {
     let mut env_vars: HashMap<String, String> = HashMap::new();
+    let mut assignments: Vec<&ast::Assignment> = Vec::new();
     let mut has_redirect = false;
-    let mut words: Vec<String> = Vec::new();
+    // Files written through redirects
+    let mut written: Vec<String> = Vec::new();
+    let mut words: Vec<Expanded> = Vec::new();
+    // Words as written, for spotting obfuscation that unquoting hides
+    let mut raw_words: Vec<String> = Vec::new();
+    // Every word of the command (including redirect targets), which may contain
+    // command substitutions, and its process substitutions. Their commands run
+    // before this one, with the variables known at this point.
+    let mut substituted: Vec<&ast::Word> = Vec::new();
+    let mut process_substitutions: Vec<&ast::SubshellCommand> = Vec::new();
+    // Bodies of here documents with an unquoted delimiter, which are expanded too
+    let mut heredocs: Vec<&ast::Word> = Vec::new();
+    // How stdin and the first argument are given, for spotting code fed to a shell
+    let (mut here_string, mut substituted_stdin, mut substituted_script) = (false, false, false);
+    let vars = ctx.vars();
 
     // Process prefix (assignments and redirects before command)
     if let Some(prefix) = &cmd.prefix {
         // CommandPrefix is a tuple struct containing Vec<CommandPrefixOrSuffixItem>
         for item in &prefix.0 {
             match item {
-                ast::CommandPrefixOrSuffixItem::AssignmentWord(assignment, _word) => {
+                ast::CommandPrefixOrSuffixItem::AssignmentWord(assignment, word) => {
                     let name = assignment_name_to_string(&assignment.name);
                     let value = assignment_value_to_string(&assignment.value);
                     env_vars.insert(name, value);
+                    assignments.push(assignment);
+                    substituted.push(word);
                 }
-                ast::CommandPrefixOrSuffixItem::IoRedirect(_) => {
+                ast::CommandPrefixOrSuffixItem::IoRedirect(redirect) => {
                     has_redirect = true;
+                    if let Some(word) = written_file(redirect) {
+                        written.push(join(expand(&word.value, ctx, false)));
+                    }
+                    let (string, substitution) = feeds_stdin(redirect);
+                    here_string |= string;
+                    substituted_stdin |= substitution;
+                    let (word, subshell) = redirect_target(redirect);
+                    substituted.extend(word);
+                    process_substitutions.extend(subshell);
+                    heredocs.extend(expanded_heredoc(redirect));
                 }
                 ast::CommandPrefixOrSuffixItem::Word(word) => {
-                    words.push(unquote_word(&word.value));
+                    words.extend(expand(&word.value, ctx, true));
+                    raw_words.push(word.value.clone());
+                    substituted.push(word);
                 }
-                ast::CommandPrefixOrSuffixItem::ProcessSubstitution(_, _) => {
+                ast::CommandPrefixOrSuffixItem::ProcessSubstitution(_, subshell) => {
                     // Process substitutions are like redirects
                     has_redirect = true;
+                    process_substitutions.push(subshell);
                 }
             }
         }
@@ -224,7 +592,20 @@ fn extract_simple_command(
 
     // Extract the command word (first word)
     if let Some(word) = &cmd.word_or_name {
-        words.push(unquote_word(&word.value));
+        words.extend(expand(&word.value, ctx, true));
+        raw_words.push(word.value.clone());
+        substituted.push(word);
+    }
+
+    // Assignments without a command set shell variables; with one, they only set
+    // its environment
+    let declares = words
+        .first()
+        .is_some_and(|w| DECLARATION_BUILTINS.contains(&w.text.as_str()));
+    if words.is_empty() {
+        for assignment in &assignments {
+            assign_variable(assignment, ctx);
+        }
     }
 
     // Process suffix (args and redirects after command)
@@ -232,34 +613,457 @@ fn extract_simple_command(
         // CommandSuffix is a tuple struct containing Vec<CommandPrefixOrSuffixItem>
         for item in &suffix.0 {
             match item {
-                ast::CommandPrefixOrSuffixItem::IoRedirect(_) => {
+                ast::CommandPrefixOrSuffixItem::IoRedirect(redirect) => {
                     has_redirect = true;
+                    if let Some(word) = written_file(redirect) {
+                        written.push(join(expand(&word.value, ctx, false)));
+                    }
+                    let (string, substitution) = feeds_stdin(redirect);
+                    here_string |= string;
+                    substituted_stdin |= substitution;
+                    let (word, subshell) = redirect_target(redirect);
+                    substituted.extend(word);
+                    process_substitutions.extend(subshell);
+                    heredocs.extend(expanded_heredoc(redirect));
                 }
                 ast::CommandPrefixOrSuffixItem::Word(word) => {
-                    words.push(unquote_word(&word.value));
+                    words.extend(expand(&word.value, ctx, true));
+                    raw_words.push(word.value.clone());
+                    substituted.push(word);
                 }
-                ast::CommandPrefixOrSuffixItem::AssignmentWord(assignment, _word) => {
+                ast::CommandPrefixOrSuffixItem::AssignmentWord(assignment, word) => {
+                    raw_words.push(word.value.clone());
+                    substituted.push(word);
                     // In suffix position, this is actually an argument that looks like an assignment
                     // e.g., `curl VAR=value` - VAR=value is an argument, not an env var
                     let name = assignment_name_to_string(&assignment.name);
                     let value = assignment_value_to_string(&assignment.value);
-                    words.push(format!("{}={}", name, value));
+                    let value = join(expand(&value, ctx, false));
+                    words.push(Expanded {
+                        text: format!("{}={}", name, value),
+                        glob: false,
+                    });
+                    // ...unless the command is `export`, `declare`, `local`, etc.
+                    if declares {
+                        assign_variable(assignment, ctx);
+                    }
                 }
-                ast::CommandPrefixOrSuffixItem::ProcessSubstitution(_, _) => {
+                ast::CommandPrefixOrSuffixItem::ProcessSubstitution(_, subshell) => {
                     has_redirect = true;
+                    substituted_script |= raw_words.iter().skip(1).all(|w| w.starts_with('-'));
+                    process_substitutions.push(subshell);
                 }
             }
         }
     }
 
-    // If no program (just assignments), return None
+    // The parser only locates plain words, so the command spans all of its parts
+    let range = substituted
+        .iter()
+        .filter_map(|word| word.location())
+        .map(|loc| (loc.start.index, loc.end.index))
+        // A process substitution's location stops before its closing parenthesis
+        .chain(
+            process_substitutions
+                .iter()
+                .filter_map(|s| s.location())
+                .map(|loc| (loc.start.index, loc.end.index + 1)),
+        )
+        .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+    let (raw, span) = ctx.locate(range);
+    let extract_nested = |results: &mut Vec<ParsedCommand>| -> Result<()> {
+        let after = ctx.vars();
+        for word in &substituted {
+            extract_substitutions(
+                word,
+                command_substitutions(&word.value),
+                span,
+                &vars,
+                ctx,
+                results,
+            );
+        }
+        for body in &heredocs {
+            extract_substitutions(
+                body,
+                heredoc_substitutions(&body.value),
+                span,
+                &vars,
+                ctx,
+                results,
+            );
+        }
+        for subshell in &process_substitutions {
+            ctx.set_vars(vars.clone());
+            ctx.nested(Nesting::ProcessSubstitution, || {
+                extract_from_compound_list(&subshell.list, ctx, results)
+            })?;
+        }
+        ctx.set_vars(after);
+        Ok(())
+    };
+
+    // If no program (just assignments), only substitutions in them run
     if words.is_empty() {
-        return Ok(None);
+        return extract_nested(results);
+    }
+
+    // Glob patterns are marked by their index among the arguments
+    let glob_args = words
+        .iter()
+        .skip(1)
+        .enumerate()
+        .filter(|(_, w)| w.glob)
+        .map(|(i, _)| i)
+        .collect();
+    let words: Vec<String> = words.into_iter().map(|w| w.text).collect();
+
+    forget_changed_variables(&words, ctx);
+
+    let mut parsed = build_command(ctx, words, env_vars, is_piped, has_redirect);
+    if !written.is_empty() {
+        parsed.effects = effects::classify(&parsed, true);
+    }
+    parsed.written_files = written;
+    parsed.raw = raw;
+    parsed.span = span;
+    parsed.glob_args = glob_args;
+    parsed.obfuscation = obfuscation::detect_in_words(&raw_words, &parsed);
+    obfuscation::detect_in_inputs(
+        &mut parsed,
+        here_string,
+        substituted_stdin || substituted_script,
+    );
+    extract_with_embedded(parsed, ctx, results);
+
+    extract_nested(results)
+}
+
+/// Extract the commands run by the command substitutions at `ranges` in a raw word,
+/// each parsed as its own input (in a subshell seeing `vars`). `span` is that of the
+/// command the word belongs to.
+fn extract_substitutions(
+    word: &ast::Word,
+    ranges: Vec<Range<usize>>,
+    span: Option<Span>,
+    vars: &HashMap<String, String>,
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+) {
+    // Offsets are exact only if the word appears in the input as written
+    let word_offset = word
+        .loc
+        .as_ref()
+        .map(|loc| ctx.byte_index(loc.start.index))
+        .filter(|&start| ctx.input[start..].starts_with(&word.value));
+
+    for range in ranges {
+        let mut nesting = ctx.nesting.borrow().clone();
+        nesting.push(Nesting::Substitution);
+        let inner = ExtractionContext {
+            input: &word.value[range.clone()],
+            offset: ctx
+                .offset
+                .zip(word_offset)
+                .map(|(offset, start)| offset + start + range.start),
+            enclosing: span,
+            nesting: RefCell::new(nesting),
+            control: RefCell::new(ctx.control.borrow().clone()),
+            vars: RefCell::new(vars.clone()),
+            untracked: Cell::new(ctx.untracked.get()),
+            functions: RefCell::new(ctx.functions.borrow().clone()),
+            discovering: Cell::new(ctx.discovering.get()),
+        };
+
+        let start = results.len();
+        if extract_from_input(&inner, results).is_err() {
+            // Keep unparseable substitutions whole so they fall through to the default
+            // action
+            results.truncate(start);
+            let (raw, span) = inner.locate(None);
+            let mut opaque = ParsedCommand::opaque(&raw);
+            opaque.span = span;
+            opaque.nesting = inner.nesting.into_inner();
+            results.push(opaque);
+        }
+    }
+}
+
+/// The file a redirect writes to, if any (not a file descriptor or `/dev/null`)
+fn written_file(redirect: &ast::IoRedirect) -> Option<&ast::Word> {
+    let word = match redirect {
+        ast::IoRedirect::File(
+            _,
+            ast::IoFileRedirectKind::Write
+            | ast::IoFileRedirectKind::Append
+            | ast::IoFileRedirectKind::Clobber
+            | ast::IoFileRedirectKind::ReadAndWrite,
+            ast::IoFileRedirectTarget::Filename(word),
+        ) => word,
+        ast::IoRedirect::OutputAndError(word, _) => word,
+        _ => return None,
+    };
+    (word.value != "/dev/null").then_some(word)
+}
+
+/// Whether a redirect gives stdin a here-string, or reads it from a process
+/// substitution (`< <(CMD)`)
+fn feeds_stdin(redirect: &ast::IoRedirect) -> (bool, bool) {
+    match redirect {
+        ast::IoRedirect::HereString(fd, _) => (fd.is_none_or(|fd| fd == 0), false),
+        ast::IoRedirect::File(
+            fd,
+            ast::IoFileRedirectKind::Read,
+            ast::IoFileRedirectTarget::ProcessSubstitution(..),
+        ) => (false, fd.is_none_or(|fd| fd == 0)),
+        _ => (false, false),
+    }
+}
+
+/// The target word or process substitution of a redirect
+fn redirect_target(
+    redirect: &ast::IoRedirect,
+) -> (Option<&ast::Word>, Option<&ast::SubshellCommand>) {
+    match redirect {
+        ast::IoRedirect::File(_, _, target) => match target {
+            ast::IoFileRedirectTarget::Filename(word)
+            | ast::IoFileRedirectTarget::Duplicate(word) => (Some(word), None),
+            ast::IoFileRedirectTarget::ProcessSubstitution(_, subshell) => (None, Some(subshell)),
+            ast::IoFileRedirectTarget::Fd(_) => (None, None),
+        },
+        ast::IoRedirect::HereString(_, word) | ast::IoRedirect::OutputAndError(word, _) => {
+            (Some(word), None)
+        }
+        ast::IoRedirect::HereDocument(..) => (None, None),
+    }
+}
+
+/// Body of a here document that the shell expands (its delimiter is unquoted)
+fn expanded_heredoc(redirect: &ast::IoRedirect) -> Option<&ast::Word> {
+    match redirect {
+        ast::IoRedirect::HereDocument(_, heredoc) if heredoc.requires_expansion => {
+            Some(&heredoc.doc)
+        }
+        _ => None,
+    }
+}
+
+/// Programs that keep a command running after the shell moves on (`disown` detaches
+/// jobs already started with `&`)
+const BACKGROUND_PROGRAMS: &[&str] = &["nohup", "setsid", "disown"];
+
+fn program_name(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+/// Builtins whose `NAME=value` arguments assign shell variables
+const DECLARATION_BUILTINS: &[&str] = &["export", "declare", "typeset", "local", "readonly"];
+
+/// Record a shell variable assignment, forgetting the variable if its value can't be
+/// known statically
+fn assign_variable(assignment: &ast::Assignment, ctx: &ExtractionContext) {
+    let name = match &assignment.name {
+        ast::AssignmentName::VariableName(name) => name,
+        ast::AssignmentName::ArrayElementName(name, _) => {
+            ctx.assign(name, None);
+            return;
+        }
+    };
+    let value = match &assignment.value {
+        ast::AssignmentValue::Scalar(word) => {
+            let value = join(expand(&word.value, ctx, false));
+            (!contains_expansion(&value) && !contains_substitution(&value)).then_some(value)
+        }
+        ast::AssignmentValue::Array(_) => None,
+    };
+    let value = match (assignment.append, value) {
+        (true, Some(value)) => ctx.vars.borrow().get(name).map(|old| old.clone() + &value),
+        (_, value) => value,
+    };
+    ctx.assign(name, value);
+}
+
+/// Builtins and common programs that can't change the shell's variables (other than
+/// as handled in `forget_changed_variables`). External programs run in a child
+/// process, but a name may also be a shell function defined outside the input.
+const KEEPS_VARIABLES: &[&str] = &[
+    // Builtins
+    ":", "[", "true", "false", "test", "echo", "printf", "pwd", "type", "hash", "shift", "set",
+    "export", "declare", "typeset", "local", "readonly", "exit", "return", "break", "continue",
+    "jobs", "disown", "umask", "kill", "cd", "pushd", "popd", // External programs
+    "ls", "cat", "head", "tail", "less", "more", "grep", "egrep", "rg", "find", "fd", "sed", "awk",
+    "cut", "sort", "uniq", "tr", "wc", "diff", "cmp", "tee", "xargs", "touch", "mkdir", "rmdir",
+    "rm", "cp", "mv", "ln", "chmod", "chown", "stat", "file", "du", "df", "date", "sleep", "env",
+    "id", "whoami", "uname", "which", "basename", "dirname", "realpath", "readlink", "mktemp",
+    "tar", "zip", "unzip", "gzip", "jq", "curl", "wget", "ssh", "scp", "rsync", "git", "gh",
+    "make", "just", "cargo", "rustc", "go", "npm", "npx", "pnpm", "yarn", "node", "python",
+    "python3", "pip", "docker", "kubectl", "sudo", "nohup", "setsid", "timeout", "nice", "time",
+    "ps", "pkill",
+];
+
+/// Forget the variables a command may assign. Only commands known not to assign
+/// variables keep them all: anything else (`eval`, `source`, a shell function) may
+/// change any of them.
+fn forget_changed_variables(words: &[String], ctx: &ExtractionContext) {
+    let program = words[0].as_str();
+    let args = &words[1..];
+    let operands = || args.iter().filter(|w| !w.starts_with('-'));
+
+    if ctx.functions.borrow().contains(program) {
+        ctx.forget_all();
+        return;
+    }
+    // Programs run by path are never shell functions
+    if program.contains('/') {
+        return;
+    }
+
+    match program {
+        // Variables set from input the parser can't see become unknown
+        "unset" | "read" | "mapfile" | "readarray" => {
+            for name in operands().map(String::as_str).chain(["REPLY", "MAPFILE"]) {
+                ctx.assign(name, None);
+            }
+        }
+        "printf" => {
+            // `printf -v NAME` or `printf -vNAME`
+            let name = args
+                .iter()
+                .enumerate()
+                .find_map(|(i, arg)| match arg.as_str() {
+                    "-v" => args.get(i + 1).map(String::as_str),
+                    _ => arg.strip_prefix("-v"),
+                });
+            if let Some(name) = name {
+                ctx.assign(variable_name(name), None);
+            }
+        }
+        "getopts" => {
+            for name in operands()
+                .skip(1)
+                .take(1)
+                .map(String::as_str)
+                .chain(["OPTARG", "OPTIND"])
+            {
+                ctx.assign(name, None);
+            }
+        }
+        "cd" | "pushd" | "popd" => {
+            for name in ["PWD", "OLDPWD", "DIRSTACK"] {
+                ctx.assign(name, None);
+            }
+        }
+        // Namerefs assign another variable through their own name, and traps and
+        // aliases run code the parser can't place
+        "declare" | "typeset" | "local"
+            if args.iter().any(|a| a.starts_with('-') && a.contains('n')) =>
+        {
+            ctx.untrack();
+        }
+        "trap" | "alias" => ctx.untrack(),
+        _ if KEEPS_VARIABLES.contains(&program) => {}
+        _ => ctx.forget_all(),
+    }
+}
+
+/// The variable assigned by a `NAME` or `NAME[index]` operand
+fn variable_name(operand: &str) -> &str {
+    operand.split('[').next().unwrap_or(operand)
+}
+
+/// Expand a raw word with the variables known so far
+fn expand(raw: &str, ctx: &ExtractionContext, split: bool) -> Vec<Expanded> {
+    expand_word(raw, &ctx.vars.borrow(), split)
+}
+
+/// Add a command to the results, followed by any commands embedded in it
+fn extract_with_embedded(
+    parsed: ParsedCommand,
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+) {
+    let mut parsed = parsed;
+    if BACKGROUND_PROGRAMS.contains(&program_name(&parsed.program)) {
+        parsed.control.background = true;
+    }
+    let embedded = extract_embedded(&parsed.program, &parsed.raw_args);
+    let outer = results.len();
+    results.push(parsed);
+
+    for inner in embedded {
+        extract_embedded_command(inner, outer, ctx, results);
+    }
+}
+
+/// Extract an embedded command (and anything nested inside it), tagging each result
+/// with the outer command (at index `outer` in the results) and where it runs.
+/// Embedded commands share the outer command's span, as their text can't be located
+/// in the input once unquoted.
+fn extract_embedded_command(
+    inner: Embedded,
+    outer: usize,
+    ctx: &ExtractionContext,
+    results: &mut Vec<ParsedCommand>,
+) {
+    let start = results.len();
+
+    match inner.command {
+        EmbeddedCommand::Argv(words) => {
+            let mut parsed = build_command(ctx, words.clone(), HashMap::new(), false, false);
+            parsed.raw = words.join(" ");
+            parsed.nesting = Vec::new();
+            parsed.control = ControlFlow::default();
+            parsed.obfuscation = obfuscation::detect_in_words(&words, &parsed);
+            extract_with_embedded(parsed, ctx, results);
+        }
+        EmbeddedCommand::Shell(script) => match parse_with_brush(&script) {
+            Ok(commands) => results.extend(commands),
+            // Keep unparseable scripts whole so they fall through to the default action
+            Err(_) => results.push(ParsedCommand::opaque(&script)),
+        },
     }
 
-    // Detect expansion and substitution in all words
+    // Nested results already tagged by a deeper level keep their tags
+    let (outer_program, outer_span, outer_nesting, outer_control, outer_privileged) = {
+        let outer = &results[outer];
+        (
+            outer.program.clone(),
+            outer.span,
+            outer.nesting.clone(),
+            outer.control.clone(),
+            outer.effects.contains(&Effect::Privilege),
+        )
+    };
+    for command in &mut results[start..] {
+        if command.context == CommandContext::Local {
+            command.context = inner.context.clone();
+        }
+        if command.embedded_in.is_none() {
+            command.embedded_in = Some(outer_program.clone());
+        }
+        command.span = outer_span;
+        command.nesting.splice(0..0, outer_nesting.iter().copied());
+        command.control.within(&outer_control);
+        // Commands run by a privileged command (`sudo CMD`) share its privileges
+        if outer_privileged && !command.effects.contains(&Effect::Privilege) {
+            command.effects.push(Effect::Privilege);
+            command.effects.sort();
+        }
+    }
+}
+
+/// Build a ParsedCommand from its words (program first)
+fn build_command(
+    ctx: &ExtractionContext,
+    words: Vec<String>,
+    env_vars: HashMap<String, String>,
+    is_piped: bool,
+    has_redirect: bool,
+) -> ParsedCommand {
+    // Detect expansion and substitution left after substituting known variables
     let has_expansion = words.iter().any(|w| contains_expansion(w));
     let has_substitution = words.iter().any(|w| contains_substitution(w));
+    let is_dynamic = |w: &String| contains_expansion(w) || contains_substitution(w);
 
     // Use semantic analyzer
     let program = words[0].clone();
@@ -267,24 +1071,50 @@ fn extract_simple_command(
 
     let analyzer = SemanticAnalyzer::new();
     let (subcommands, flags, args) = analyzer.analyze(&program, &remaining);
+    let dynamic_program = is_dynamic(&program);
+    let dynamic_args = remaining
+        .iter()
+        .filter(|w| is_dynamic(w))
+        .cloned()
+        .collect();
 
-    Ok(Some(ParsedCommand {
-        raw: ctx.input.to_string(),
+    let mut parsed = ParsedCommand {
+        raw: String::new(),
+        span: None,
+        nesting: ctx.nesting.borrow().clone(),
+        control: ctx.control.borrow().clone(),
         program,
         subcommands,
         args,
         flags,
         is_piped,
         has_redirect,
+        written_files: Vec::new(),
         env_vars,
         has_expansion,
         has_substitution,
-    }))
+        context: CommandContext::Local,
+        embedded_in: None,
+        raw_args: remaining,
+        file_source: None,
+        script: None,
+        dynamic_program,
+        dynamic_args,
+        glob_args: Vec::new(),
+        obfuscation: Vec::new(),
+        effects: Vec::new(),
+    };
+    parsed.effects = effects::classify(&parsed, false);
+    parsed
 }
 
-/// Unquote a word value using brush-parser's unquote_str
-fn unquote_word(value: &str) -> String {
-    unquote_str(value)
+/// Join the words of an expanded assignment value
+fn join(words: Vec<Expanded>) -> String {
+    words
+        .into_iter()
+        .map(|w| w.text)
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 /// Convert AssignmentName to String
@@ -394,6 +1224,10 @@ mod tests {
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].program, "echo");
         assert!(results[0].has_redirect);
+        assert_eq!(results[0].written_files, vec!["file.txt"]);
+
+        let results = parse_with_brush("D=out; cmd < in.txt 2>&1 >> $D/log &> /dev/null").unwrap();
+        assert_eq!(results[0].written_files, vec!["out/log"]);
     }
 
     #[test]
@@ -415,8 +1249,11 @@ mod tests {
     #[test]
     fn test_substitution_detection() {
         let results = parse_with_brush("echo $(date)").unwrap();
-        assert_eq!(results.len(), 1);
+        assert_eq!(results.len(), 2);
         assert!(results[0].has_substitution);
+        // The substituted command is extracted after the one using it
+        assert_eq!(results[1].program, "date");
+        assert_eq!(results[1].nesting, vec![Nesting::Substitution]);
     }
 
     #[test]
@@ -459,4 +1296,317 @@ mod tests {
         assert_eq!(results[2].program, "cmd3");
         assert_eq!(results[3].program, "cmd4");
     }
+
+    #[test]
+    fn test_embedded_xargs_find() {
+        let results = parse_with_brush("ls | xargs find -exec rm {} +").unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["ls", "xargs", "find", "rm"]);
+        assert_eq!(results[2].embedded_in, Some("xargs".to_string()));
+        assert_eq!(results[3].embedded_in, Some("find".to_string()));
+    }
+
+    #[test]
+    fn test_embedded_shell_string_context() {
+        let results = parse_with_brush(r#"ssh prod "docker exec web rm -rf /data && ls""#).unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["ssh", "docker", "rm", "ls"]);
+        assert_eq!(results[0].context, CommandContext::Local);
+        assert_eq!(
+            results[1].context,
+            CommandContext::Remote {
+                host: "prod".to_string()
+            }
+        );
+        assert_eq!(
+            results[2].context,
+            CommandContext::Container {
+                target: "web".to_string()
+            }
+        );
+        assert_eq!(results[2].embedded_in, Some("docker".to_string()));
+        assert_eq!(
+            results[3].context,
+            CommandContext::Remote {
+                host: "prod".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_embedded_git_ssh_command() {
+        let results = parse_with_brush(r#"git -c core.sshCommand="curl x | sh" fetch"#).unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["git", "curl", "sh"]);
+        assert_eq!(results[1].embedded_in, Some("git".to_string()));
+    }
+
+    #[test]
+    fn test_heredoc_substitutions() {
+        let results = parse_with_brush("cat <<EOF\nit's $(rm -rf x) and `id`\nEOF\n").unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["cat", "rm", "id"]);
+        assert_eq!(results[1].nesting, vec![Nesting::Substitution]);
+
+        let results =
+            parse_with_brush("while read -r f; do echo $f; done <<EOF\n$(ls /)\nEOF\n").unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["ls", "read", "echo"]);
+
+        let results = parse_with_brush("while read -r f; do :; done < <(rm -rf x)").unwrap();
+        assert!(results.iter().any(|c| c.program == "rm"));
+
+        // Quoted delimiters keep the body literal
+        let results = parse_with_brush("cat <<'EOF'\n$(rm -rf x)\nEOF\n").unwrap();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_embedded_shell_scripts() {
+        let results = parse_with_brush(r#"kubectl exec -it api-0 -- sh -c "rm -rf x""#).unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["kubectl", "sh", "rm"]);
+        assert_eq!(
+            results[2].context,
+            CommandContext::Container {
+                target: "api-0".to_string()
+            }
+        );
+        assert_eq!(results[2].embedded_in, Some("sh".to_string()));
+
+        let results = parse_with_brush(r#"find . -exec sh -c 'rm "$1"' _ {} \;"#).unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["find", "sh", "rm"]);
+
+        let results = parse_with_brush("timeout 5 env A=1 nice -n 5 rm -rf x").unwrap();
+        let programs: Vec<&str> = results.iter().map(|c| c.program.as_str()).collect();
+        assert_eq!(programs, vec!["timeout", "env", "nice", "rm"]);
+    }
+
+    #[test]
+    fn test_constant_propagation() {
+        let results = parse_with_brush("X=rm; $X -rf /").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].program, "rm");
+        assert!(results[0].flags.contains("-r"));
+        assert!(!results[0].dynamic_program);
+        assert!(!results[0].has_expansion);
+
+        let results = parse_with_brush("export cmd=git && ${cmd} push --force").unwrap();
+        assert_eq!(results[1].program, "git");
+        assert_eq!(results[1].subcommands, vec!["push"]);
+
+        // Unquoted values are split into words, quoted ones are not
+        let results = parse_with_brush(r#"X="rm -rf"; $X /tmp; echo "$X""#).unwrap();
+        assert_eq!(results[0].program, "rm");
+        assert_eq!(results[0].args, vec!["/tmp"]);
+        assert_eq!(results[1].args, vec!["rm -rf"]);
+
+        // Single quotes keep the reference literal
+        let results = parse_with_brush("X=rm; echo '$X'").unwrap();
+        assert_eq!(results[0].raw_args, vec!["$X"]);
+    }
+
+    #[test]
+    fn test_unknown_values_marked_dynamic() {
+        let results = parse_with_brush("$EDITOR notes.txt").unwrap();
+        assert!(results[0].dynamic_program);
+
+        let results = parse_with_brush("X=$(which rm); $X -rf / $HOME").unwrap();
+        assert_eq!(results[0].program, "which");
+        assert!(results[1].dynamic_program);
+        assert_eq!(results[1].dynamic_args, vec!["$HOME"]);
+
+        // Prefix assignments only set the command's environment
+        let results = parse_with_brush("X=rm env; $X").unwrap();
+        assert!(results[1].dynamic_program);
+    }
+
+    #[test]
+    fn test_assignments_respect_scope() {
+        // Subshells, pipes and background commands don't change the outer shell
+        for input in [
+            "X=rm; (X=ls); $X",
+            "X=rm; X=ls | cat; $X",
+            "X=rm; X=ls & $X",
+        ] {
+            let results = parse_with_brush(input).unwrap();
+            assert_eq!(results.last().unwrap().program, "rm", "{}", input);
+        }
+
+        // Assignments that may not run make the variable unknown
+        for input in [
+            "X=rm; true || X=ls; $X",
+            "X=rm; if test -f a; then X=ls; fi; $X",
+            "X=rm; for f in a b; do X=ls; done; $X",
+            "X=ls; for f in a b; do $X; X=rm; done",
+            "X=ls; for f in a; do for g in b; do $X; done; X=rm; done",
+            "X=rm; unset X; $X",
+            // Commands that assign variables the parser doesn't follow
+            "X=ls; printf -v X rm; $X -rf /",
+            "X=ls; f() { X=rm; }; f; $X -rf /",
+            "X=ls; eval X=rm; $X -rf /",
+            "X=ls; source ./env.sh; $X",
+            "X=ls; frobnicate; $X",
+            "X=ls; declare -n r=X; r=rm; $X",
+            "X=ls; trap 'X=rm' DEBUG; X=ls; $X",
+            // Values are split on an IFS the parser doesn't follow
+            "IFS=_; X=rm_x; $X",
+            "X=rm_x; export IFS=_; $X",
+        ] {
+            let results = parse_with_brush(input).unwrap();
+            assert!(results.iter().any(|c| c.dynamic_program), "{}", input);
+        }
+
+        // Every branch agreeing keeps the value
+        let results = parse_with_brush("if test -f a; then X=rm; else X=rm; fi; $X").unwrap();
+        assert_eq!(results.last().unwrap().program, "rm");
+
+        // Programs that can't assign variables keep them, unless redefined as functions
+        let results = parse_with_brush("X=rm; echo hi && git status; ./build.sh; $X").unwrap();
+        assert_eq!(results.last().unwrap().program, "rm");
+        let results = parse_with_brush("X=ls; git() { X=rm; }; git; $X").unwrap();
+        assert!(results.last().unwrap().dynamic_program);
+    }
+
+    #[test]
+    fn test_word_expansion() {
+        let home = std::env::var("HOME").unwrap();
+        let results = parse_with_brush("rm -rf ~/* {a,b}/ '~/x' \"*.txt\"").unwrap();
+        assert_eq!(
+            results[0].args,
+            vec![
+                format!("{}/*", home),
+                "a/".to_string(),
+                "b/".to_string(),
+                "~/x".to_string(),
+                "*.txt".to_string(),
+            ]
+        );
+        // Only the unquoted pattern is a glob
+        assert_eq!(results[0].glob_args, vec![1]);
+    }
+
+    #[test]
+    fn test_words_normalized_like_bash() {
+        let results = parse_with_brush(r"$'\x72\x6d' -rf /").unwrap();
+        assert_eq!(results[0].program, "rm");
+        assert!(results[0].flags.contains("-r"));
+
+        // Nested quotes in an embedded shell string survive to the inner command
+        let results =
+            parse_with_brush(r#"git -c core.sshCommand="ssh -i 'my key'" fetch"#).unwrap();
+        assert_eq!(results[1].program, "ssh");
+        assert_eq!(results[1].args, vec!["my key"]);
+    }
+
+    #[test]
+    fn test_spans_and_nesting() {
+        let input = r#"echo é && (cat x | rm -rf "$(find . -name y)") > out"#;
+        let results = parse_with_brush(input).unwrap();
+
+        let segments: Vec<&str> = results
+            .iter()
+            .map(|c| &input[c.span.unwrap().start..c.span.unwrap().end])
+            .collect();
+        assert_eq!(
+            segments,
+            vec![
+                "echo é",
+                "cat x",
+                r#"rm -rf "$(find . -name y)""#,
+                "find . -name y"
+            ]
+        );
+        assert!(results.iter().zip(&segments).all(|(c, s)| c.raw == *s));
+
+        let paths: Vec<String> = results.iter().map(|c| c.nesting_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "",
+                "subshell > pipeline[0]",
+                "subshell > pipeline[1]",
+                "subshell > pipeline[1] > $(...)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_commands_extracted() {
+        let programs = |input: &str| -> Vec<String> {
+            parse_with_brush(input)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.program)
+                .collect()
+        };
+
+        assert_eq!(programs("X=`whoami` env"), vec!["env", "whoami"]);
+        assert_eq!(programs("echo $(echo $(id))"), vec!["echo", "echo", "id"]);
+        assert_eq!(
+            programs("diff <(ls a) b > >(tee log)"),
+            vec!["diff", "ls", "tee"]
+        );
+        assert_eq!(programs("cat < $(mktemp)"), vec!["cat", "mktemp"]);
+
+        // Single quotes and arithmetic don't run commands
+        assert_eq!(programs("echo '$(rm x)' $((1 + 2))"), vec!["echo"]);
+
+        // Embedded commands share the span of the command containing them
+        let results = parse_with_brush("ls; find . -exec rm {} +").unwrap();
+        assert_eq!(results[2].program, "rm");
+        assert_eq!(results[2].span, results[1].span);
+        assert_eq!(results[2].raw, "rm {}");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let results =
+            parse_with_brush("for f in $(ls /) x; do rm \"$f\"; done; cd x && make &").unwrap();
+        assert_eq!(results[0].program, "ls");
+        assert!(results[0].control.is_empty());
+
+        let rm = &results[1].control;
+        assert_eq!(rm.loops.len(), 1);
+        assert_eq!(rm.loops[0].kind, LoopKind::For);
+        assert_eq!(rm.loops[0].variable.as_deref(), Some("f"));
+        assert_eq!(rm.loops[0].words, vec!["$(ls /)", "x"]);
+
+        assert!(!results[2].control.conditional && results[2].control.background);
+        assert!(results[3].control.conditional && results[3].control.background);
+
+        // Branches are conditional, but an `if` condition always runs
+        let results =
+            parse_with_brush("if test -d x; then rm -r x; else nohup ./server; fi").unwrap();
+        let flows: Vec<(bool, bool)> = results
+            .iter()
+            .map(|c| (c.control.conditional, c.control.background))
+            .collect();
+        assert_eq!(
+            flows,
+            vec![(false, false), (true, false), (true, true), (true, true)]
+        );
+        assert_eq!(results[3].program, "./server");
+    }
+
+    #[test]
+    fn test_nested_loops_extracted_in_linear_passes() {
+        // Each loop level must not extract its body twice over, or deep nesting from an
+        // agent could hang the hook
+        let depth = 20;
+        let mut input = String::new();
+        for i in 0..depth {
+            input.push_str(&format!("for v{} in a; do ", i));
+        }
+        input.push_str("rm -rf \"$v0\"");
+        input.push_str(&"; done".repeat(depth));
+
+        let start = std::time::Instant::now();
+        let results = parse_with_brush(&input).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].program, "rm");
+        assert_eq!(results[0].control.loops.len(), depth);
+    }
 }
@@ -1,32 +1,316 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+};
 
 use anyhow::Result;
+use serde::Serialize;
 
-use super::brush_adapter::parse_with_brush;
+use super::{
+    brush_adapter::parse_with_brush,
+    effects::{self, Effect},
+    obfuscation::Obfuscation,
+};
 
 /// A parsed shell command with semantic information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ParsedCommand {
-    /// The raw command string
+    /// The command's text as written (the segment of the input it was extracted from)
     pub raw: String,
+    /// Byte range of `raw` in the input, if the command was written there (commands
+    /// embedded in another command's arguments share its span)
+    pub span: Option<Span>,
+    /// Shell constructs the command is nested in, outermost first
+    pub nesting: Vec<Nesting>,
+    /// Loops, branches and backgrounding around the command
+    pub control: ControlFlow,
     /// The program being invoked (e.g., "git")
     pub program: String,
     /// Chained subcommands (e.g., ["remote", "add"])
     pub subcommands: Vec<String>,
     /// Positional arguments
     pub args: Vec<String>,
+    /// All words after the program, in their original order
+    pub raw_args: Vec<String>,
     /// Flags (both short and long, e.g., "-f", "--force")
     pub flags: HashSet<String>,
     /// Whether the command contains a pipe
     pub is_piped: bool,
     /// Whether the command has output redirection
     pub has_redirect: bool,
+    /// Files the command's redirects write to (`> out.log`), after expansion
+    pub written_files: Vec<String>,
     /// Environment variables set before the command
     pub env_vars: HashMap<String, String>,
     /// Whether the command contains parameter expansion ($VAR, ${VAR})
     pub has_expansion: bool,
     /// Whether the command contains command substitution ($(...) or backticks)
     pub has_substitution: bool,
+    /// Whether the program name is only known at runtime (e.g., `$CMD` where `CMD`
+    /// isn't assigned earlier in the input)
+    pub dynamic_program: bool,
+    /// Arguments whose value is only known at runtime, as written
+    pub dynamic_args: Vec<String>,
+    /// Indices into `raw_args` of unquoted glob patterns, which the shell expands
+    /// against the working directory (see `parser::expand_globs`)
+    pub glob_args: Vec<usize>,
+    /// Techniques used to hide what the command does (e.g., `base64 -d | sh`)
+    pub obfuscation: Vec<Obfuscation>,
+    /// What the command does to the system (reads, writes, network, ...), as far as
+    /// known for its program
+    pub effects: Vec<Effect>,
+    /// Where the command runs
+    pub context: CommandContext,
+    /// Program whose arguments this command was extracted from (e.g., "find" for
+    /// `find -exec rm {} \;`), or `None` for commands written directly
+    pub embedded_in: Option<String>,
+    /// File and line the command was read from, for commands resolved from task-runner
+    /// recipes or scripts rather than written in the input
+    pub file_source: Option<FileSource>,
+    /// Local script this command runs (e.g., `./deploy.sh` or `bash deploy.sh`), set
+    /// when script resolution is enabled
+    pub script: Option<ScriptFile>,
+}
+
+/// Byte range of a command in the input it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The input line containing the span, with a `^^^` underline beneath the
+    /// spanned part of it
+    pub fn underline(&self, input: &str) -> String {
+        let boundary = |i: usize| (0..=i.min(input.len())).rfind(|&i| input.is_char_boundary(i));
+        let start = boundary(self.start).unwrap_or_default();
+        let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let end = boundary(self.end)
+            .unwrap_or_default()
+            .clamp(start, line_end);
+
+        let line = &input[line_start..line_end];
+        let indent = input[line_start..start].chars().count();
+        let width = input[start..end].chars().count().max(1);
+        format!("{}\n{}{}", line, " ".repeat(indent), "^".repeat(width))
+    }
+}
+
+/// A shell construct containing a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Nesting {
+    /// Stage of a pipeline (0-based)
+    Pipeline { index: usize },
+    /// `( ... )`
+    Subshell,
+    /// `{ ...; }`
+    BraceGroup,
+    /// `$(...)` or backticks
+    Substitution,
+    /// `<(...)` or `>(...)`
+    ProcessSubstitution,
+}
+
+impl fmt::Display for Nesting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Nesting::Pipeline { index } => write!(f, "pipeline[{}]", index),
+            Nesting::Subshell => write!(f, "subshell"),
+            Nesting::BraceGroup => write!(f, "brace group"),
+            Nesting::Substitution => write!(f, "$(...)"),
+            Nesting::ProcessSubstitution => write!(f, "<(...)"),
+        }
+    }
+}
+
+/// How often and when a command runs, from the control flow around it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ControlFlow {
+    /// Loops the command runs in, outermost first
+    pub loops: Vec<Loop>,
+    /// Whether the command only runs on some paths (`if`/`case` branches, or after
+    /// `&&`/`||`)
+    pub conditional: bool,
+    /// Whether the command keeps running in the background (`&`, `nohup`, `setsid`,
+    /// `disown`)
+    pub background: bool,
+}
+
+impl ControlFlow {
+    /// Place this control flow inside `outer` (e.g., for a command embedded in, or
+    /// run by a script invoked from, a command with that control flow)
+    pub fn within(&mut self, outer: &ControlFlow) {
+        self.loops.splice(0..0, outer.loops.iter().cloned());
+        self.conditional |= outer.conditional;
+        self.background |= outer.background;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &ControlFlow::default()
+    }
+}
+
+impl fmt::Display for ControlFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = self.loops.iter().map(Loop::to_string).collect();
+        if self.conditional {
+            parts.push("conditional".to_string());
+        }
+        if self.background {
+            parts.push("background".to_string());
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// A loop around a command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Loop {
+    pub kind: LoopKind,
+    /// Loop variable (`for f in ...`)
+    pub variable: Option<String>,
+    /// Words the loop variable takes its values from, as written (`$(ls /)`), or
+    /// empty for `for f` (the positional parameters) and other loops
+    pub words: Vec<String>,
+}
+
+impl fmt::Display for Loop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.kind, &self.variable) {
+            (LoopKind::For, Some(variable)) if self.words.is_empty() => {
+                write!(f, "for {} loop", variable)
+            }
+            (LoopKind::For, Some(variable)) => {
+                write!(f, "for {} in {} loop", variable, self.words.join(" "))
+            }
+            (kind, _) => write!(f, "{} loop", kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoopKind {
+    For,
+    /// `for ((i = 0; i < n; i++))`
+    ArithmeticFor,
+    While,
+    Until,
+}
+
+impl fmt::Display for LoopKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            LoopKind::For => "for",
+            LoopKind::ArithmeticFor => "arithmetic for",
+            LoopKind::While => "while",
+            LoopKind::Until => "until",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Location of a command inside a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileSource {
+    pub path: PathBuf,
+    /// 1-based line number, or 0 if unknown
+    pub line: usize,
+    /// What caused the file to be read (e.g., "make deploy")
+    pub label: Option<String>,
+}
+
+impl fmt::Display for FileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if self.line > 0 {
+            write!(f, ":{}", self.line)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, " (via `{}`)", label)?;
+        }
+        Ok(())
+    }
+}
+
+/// A local script run by a command (or the file of a task-runner recipe that wasn't
+/// found)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScriptFile {
+    pub path: PathBuf,
+    /// Hex SHA-256 of the script's content, if it could be read
+    pub sha256: Option<String>,
+    pub status: ScriptStatus,
+}
+
+impl fmt::Display for ScriptFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.path.display(), self.status)?;
+        if let Some(sha256) = &self.sha256 {
+            write!(f, ", sha256 {}", sha256)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Whether a script's commands could be evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptStatus {
+    /// Parsed, its commands follow the command that runs it
+    Resolved,
+    OutsideWorkspace,
+    Unreadable,
+    TooLarge,
+    /// Run by an interpreter other than a shell (e.g., `#!/usr/bin/env python3`)
+    NotShell,
+    /// Nested too deeply in other scripts
+    TooDeep,
+    /// A task-runner recipe not defined in the file read for it (e.g., `make` targets
+    /// built by a pattern rule, `npm run` of a missing script)
+    RecipeNotFound,
+}
+
+impl fmt::Display for ScriptStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ScriptStatus::Resolved => "resolved",
+            ScriptStatus::OutsideWorkspace => "outside the workspace",
+            ScriptStatus::Unreadable => "unreadable",
+            ScriptStatus::TooLarge => "too large",
+            ScriptStatus::NotShell => "not a shell script",
+            ScriptStatus::TooDeep => "nested too deeply",
+            ScriptStatus::RecipeNotFound => "recipe not found",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Where a command runs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CommandContext {
+    /// On the local machine
+    #[default]
+    Local,
+    /// On a remote host (e.g., `ssh host CMD`)
+    Remote { host: String },
+    /// Inside a container or pod (e.g., `docker exec CTR CMD`)
+    Container { target: String },
+}
+
+impl fmt::Display for CommandContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandContext::Local => write!(f, "local"),
+            CommandContext::Remote { host } => write!(f, "remote ({})", host),
+            CommandContext::Container { target } => write!(f, "container ({})", target),
+        }
+    }
 }
 
 impl ParsedCommand {
@@ -39,6 +323,30 @@ impl ParsedCommand {
         parse_with_brush(command)
     }
 
+    /// Nesting path, outermost first (e.g., "subshell > pipeline[1] > $(...)")
+    pub fn nesting_path(&self) -> String {
+        self.nesting
+            .iter()
+            .map(Nesting::to_string)
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+
+    /// Effects of the program itself, leaving out writes through its redirects
+    pub fn program_effects(&self) -> Vec<Effect> {
+        effects::classify(self, false)
+    }
+
+    /// A command that could not be parsed, kept whole as its program name so it can
+    /// only match rules written for it (and otherwise falls to the default action)
+    pub fn opaque(text: &str) -> Self {
+        Self {
+            raw: text.to_string(),
+            program: text.to_string(),
+            ..Default::default()
+        }
+    }
+
     /// Parse a command string into a single ParsedCommand.
     ///
     /// **Note:** This only returns the first command found. For security evaluation,
@@ -133,6 +441,27 @@ mod tests {
         assert_eq!(cmds[2].program, "wc");
     }
 
+    #[test]
+    fn test_embedded_commands() {
+        let cmds = ParsedCommand::parse_all(r"find . -name '*.log' -exec rm -f {} \;").unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert_eq!(cmds[0].program, "find");
+        assert_eq!(cmds[1].program, "rm");
+        assert_eq!(cmds[1].embedded_in, Some("find".to_string()));
+        assert_eq!(cmds[1].context, CommandContext::Local);
+    }
+
+    #[test]
+    fn test_span_underline() {
+        let input = "cd é &&\n  rm -rf x";
+        let cmds = ParsedCommand::parse_all(input).unwrap();
+        assert_eq!(cmds[0].span.unwrap().underline(input), "cd é &&\n^^^^");
+        assert_eq!(
+            cmds[1].span.unwrap().underline(input),
+            "  rm -rf x\n  ^^^^^^^^"
+        );
+    }
+
     #[test]
     fn test_chain_all_commands() {
         let cmds = ParsedCommand::parse_all("cd /tmp && ls -la || echo failed").unwrap();
@@ -0,0 +1,370 @@
+//! Classification of what a command does to the system
+//!
+//! Policies are usually about effects ("read anything, never touch the network")
+//! rather than program names. Each spec below annotates a program, optionally
+//! narrowed to a subcommand and/or flags, with the effects it has; a command gets the
+//! union of every spec it matches. Programs without a spec have no known effects.
+
+use std::{fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::command::ParsedCommand;
+
+/// A category of effect a command has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Effect {
+    /// Reads files or system state
+    Read,
+    /// Creates or modifies files or system state
+    Write,
+    /// Talks to other hosts
+    Network,
+    /// Deletes or overwrites data, or stops processes, in a way that is hard to undo
+    Destructive,
+    /// Runs with, or changes, elevated privileges
+    Privilege,
+}
+
+use Effect::{Destructive, Network, Privilege, Read, Write};
+
+impl Effect {
+    pub const ALL: &'static [Effect] = &[Read, Write, Network, Destructive, Privilege];
+
+    /// Name used in configuration (e.g., `effects = ["network"]`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Read => "read",
+            Write => "write",
+            Network => "network",
+            Destructive => "destructive",
+            Privilege => "privilege",
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Effect specs: the program, then any subcommand words and flags the command must
+/// have (`"git push --force"`), and the effects a matching command has
+const SPECS: &[(&str, &[Effect])] = &[
+    // Reading files and system state
+    ("cat", &[Read]),
+    ("less", &[Read]),
+    ("more", &[Read]),
+    ("head", &[Read]),
+    ("tail", &[Read]),
+    ("ls", &[Read]),
+    ("tree", &[Read]),
+    ("stat", &[Read]),
+    ("file", &[Read]),
+    ("wc", &[Read]),
+    ("du", &[Read]),
+    ("df", &[Read]),
+    ("grep", &[Read]),
+    ("rg", &[Read]),
+    ("ag", &[Read]),
+    ("diff", &[Read]),
+    ("jq", &[Read]),
+    ("awk", &[Read]),
+    ("sed", &[Read]),
+    ("sed -i", &[Write]),
+    ("sed --in-place", &[Write]),
+    ("find", &[Read]),
+    ("find -delete", &[Write, Destructive]),
+    ("ps", &[Read]),
+    ("pwd", &[Read]),
+    ("which", &[Read]),
+    // Writing files
+    ("touch", &[Write]),
+    ("mkdir", &[Write]),
+    ("cp", &[Read, Write]),
+    ("mv", &[Write]),
+    ("ln", &[Write]),
+    ("tee", &[Write]),
+    ("install", &[Write]),
+    ("patch", &[Write]),
+    ("chmod", &[Write]),
+    ("chown", &[Write]),
+    ("tar", &[Read]),
+    ("tar -x", &[Write]),
+    ("tar -c", &[Write]),
+    ("unzip", &[Write]),
+    ("zip", &[Write]),
+    // Destroying data or processes
+    ("rm", &[Write, Destructive]),
+    ("rmdir", &[Write, Destructive]),
+    ("shred", &[Write, Destructive]),
+    ("truncate", &[Write, Destructive]),
+    ("dd", &[Write, Destructive]),
+    ("mkfs", &[Write, Destructive, Privilege]),
+    ("kill", &[Destructive]),
+    ("pkill", &[Destructive]),
+    ("killall", &[Destructive]),
+    // Network
+    ("curl", &[Network]),
+    ("curl -o", &[Write]),
+    ("curl -O", &[Write]),
+    ("wget", &[Network, Write]),
+    ("ssh", &[Network]),
+    ("scp", &[Read, Write]),
+    ("sftp", &[Network]),
+    ("rsync", &[Read, Write]),
+    ("rsync --delete", &[Destructive]),
+    ("nc", &[Network]),
+    ("ncat", &[Network]),
+    ("telnet", &[Network]),
+    ("ftp", &[Network]),
+    ("ping", &[Network]),
+    ("dig", &[Network]),
+    ("nslookup", &[Network]),
+    // Privilege
+    ("sudo", &[Privilege]),
+    ("doas", &[Privilege]),
+    ("su", &[Privilege]),
+    ("pkexec", &[Privilege]),
+    ("chroot", &[Privilege]),
+    ("setcap", &[Privilege, Write]),
+    ("mount", &[Privilege, Write]),
+    ("umount", &[Privilege, Write]),
+    ("systemctl", &[Privilege]),
+    ("useradd", &[Privilege, Write]),
+    ("usermod", &[Privilege, Write]),
+    ("passwd", &[Privilege, Write]),
+    // git (subcommands without a spec have no known effects)
+    ("git status", &[Read]),
+    ("git log", &[Read]),
+    ("git diff", &[Read]),
+    ("git show", &[Read]),
+    ("git blame", &[Read]),
+    ("git grep", &[Read]),
+    ("git shortlog", &[Read]),
+    ("git describe", &[Read]),
+    ("git ls-files", &[Read]),
+    ("git ls-tree", &[Read]),
+    ("git rev-parse", &[Read]),
+    ("git rev-list", &[Read]),
+    ("git cat-file", &[Read]),
+    ("git show-ref", &[Read]),
+    ("git for-each-ref", &[Read]),
+    ("git merge-base", &[Read]),
+    ("git ls-remote", &[Read, Network]),
+    ("git remote", &[Read]),
+    ("git add", &[Write]),
+    ("git commit", &[Write]),
+    ("git checkout", &[Write]),
+    ("git switch", &[Write]),
+    ("git restore", &[Write, Destructive]),
+    ("git merge", &[Write]),
+    ("git rebase", &[Write]),
+    ("git cherry-pick", &[Write]),
+    ("git revert", &[Write]),
+    ("git stash", &[Write]),
+    ("git stash drop", &[Destructive]),
+    ("git stash clear", &[Destructive]),
+    ("git tag", &[Write]),
+    ("git branch", &[Write]),
+    ("git branch -d", &[Destructive]),
+    ("git branch -D", &[Destructive]),
+    ("git branch --delete", &[Destructive]),
+    ("git apply", &[Write]),
+    ("git am", &[Write]),
+    ("git config", &[Write]),
+    ("git init", &[Write]),
+    ("git notes", &[Write]),
+    ("git update-ref", &[Write]),
+    ("git update-ref -d", &[Destructive]),
+    ("git symbolic-ref", &[Write]),
+    ("git worktree", &[Write]),
+    ("git worktree remove", &[Destructive]),
+    ("git worktree prune", &[Destructive]),
+    ("git submodule", &[Network, Write]),
+    ("git gc", &[Write, Destructive]),
+    ("git prune", &[Write, Destructive]),
+    ("git reflog expire", &[Write, Destructive]),
+    ("git filter-branch", &[Write, Destructive]),
+    ("git mv", &[Write]),
+    ("git rm", &[Write, Destructive]),
+    ("git reset", &[Write]),
+    ("git reset --hard", &[Destructive]),
+    ("git clean", &[Write, Destructive]),
+    ("git clone", &[Network, Write]),
+    ("git fetch", &[Network, Write]),
+    ("git pull", &[Network, Write]),
+    ("git push", &[Network, Write]),
+    ("git push --force", &[Destructive]),
+    ("git push -f", &[Destructive]),
+    ("git push --delete", &[Destructive]),
+    ("git remote add", &[Write]),
+    ("git remote remove", &[Write]),
+    ("git remote set-url", &[Write]),
+    // Package managers
+    ("npm install", &[Network, Write]),
+    ("npm ci", &[Network, Write, Destructive]),
+    ("npm publish", &[Network]),
+    ("pnpm install", &[Network, Write]),
+    ("pnpm publish", &[Network]),
+    ("yarn add", &[Network, Write]),
+    ("yarn publish", &[Network]),
+    ("pip install", &[Network, Write]),
+    ("cargo build", &[Read, Write]),
+    ("cargo test", &[Read, Write]),
+    ("cargo install", &[Network, Write]),
+    ("cargo publish", &[Network]),
+    // Containers and clusters
+    ("docker pull", &[Network, Write]),
+    ("docker push", &[Network]),
+    ("docker run", &[Write]),
+    ("docker rm", &[Destructive]),
+    ("docker rmi", &[Destructive]),
+    ("docker system prune", &[Destructive]),
+    ("docker run --privileged", &[Privilege]),
+    ("kubectl get", &[Read, Network]),
+    ("kubectl describe", &[Read, Network]),
+    ("kubectl logs", &[Read, Network]),
+    ("kubectl apply", &[Write, Network]),
+    ("kubectl create", &[Write, Network]),
+    ("kubectl edit", &[Write, Network]),
+    ("kubectl patch", &[Write, Network]),
+    ("kubectl scale", &[Write, Network]),
+    ("kubectl delete", &[Write, Destructive, Network]),
+    ("kubectl exec", &[Network]),
+    ("terraform plan", &[Read, Network]),
+    ("terraform apply", &[Write, Network]),
+    ("terraform destroy", &[Write, Destructive, Network]),
+];
+
+/// Programs that copy from or to another host when a path is remote
+const REMOTE_COPIERS: &[&str] = &["rsync", "scp"];
+
+/// `host:path`, `user@host:path`, `host::module` or `rsync://host/path` (but not a
+/// local `./a:b`)
+fn is_remote_path(arg: &str) -> bool {
+    arg.contains("://")
+        || arg
+            .split_once(':')
+            .is_some_and(|(host, _)| !host.is_empty() && !host.contains('/'))
+}
+
+/// Effects of a command, in `Effect::ALL` order. `writes_output` is set when the
+/// command's output is redirected to a file.
+pub(super) fn classify(command: &ParsedCommand, writes_output: bool) -> Vec<Effect> {
+    let name = Path::new(&command.program)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(&command.program);
+    // Subcommands of programs the semantic analyzer doesn't know are the leading
+    // positional arguments (`npm publish`)
+    let words = if command.subcommands.is_empty() {
+        &command.args
+    } else {
+        &command.subcommands
+    };
+
+    let mut effects: Vec<Effect> = SPECS
+        .iter()
+        .filter(|(spec, _)| matches_spec(spec, name, words, command))
+        .flat_map(|(_, effects)| effects.iter().copied())
+        .collect();
+    if writes_output {
+        effects.push(Write);
+    }
+    if REMOTE_COPIERS.contains(&name) && command.args.iter().any(|arg| is_remote_path(arg)) {
+        effects.push(Network);
+    }
+
+    effects.sort();
+    effects.dedup();
+    effects
+}
+
+fn matches_spec(spec: &str, program: &str, words: &[String], command: &ParsedCommand) -> bool {
+    let mut parts = spec.split(' ');
+    if parts.next() != Some(program) {
+        return false;
+    }
+
+    let mut words = words.iter();
+    parts.all(|part| {
+        if part.starts_with('-') {
+            command.flags.contains(part)
+        } else {
+            words.next().is_some_and(|w| w == part)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn effects(input: &str) -> Vec<Vec<&'static str>> {
+        ParsedCommand::parse_all(input)
+            .unwrap()
+            .iter()
+            .map(|c| c.effects.iter().map(|e| e.name()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_program_and_subcommand_effects() {
+        assert_eq!(effects("cat README.md"), vec![vec!["read"]]);
+        assert_eq!(effects("rm -rf build"), vec![vec!["write", "destructive"]]);
+        assert_eq!(effects("git status"), vec![vec!["read"]]);
+        assert_eq!(
+            effects("git push --force origin main"),
+            vec![vec!["write", "network", "destructive"]]
+        );
+        assert_eq!(effects("git apply x.patch"), vec![vec!["write"]]);
+        assert_eq!(
+            effects("git config core.hooksPath /tmp/h"),
+            vec![vec!["write"]]
+        );
+        assert_eq!(
+            effects("git update-ref -d refs/heads/main"),
+            vec![vec!["write", "destructive"]]
+        );
+        assert_eq!(
+            effects("git worktree remove --force ../w"),
+            vec![vec!["write", "destructive"]]
+        );
+        // git subcommands without a spec have no known effects
+        assert_eq!(effects("git frobnicate"), vec![Vec::<&str>::new()]);
+        assert_eq!(effects("npm publish"), vec![vec!["network"]]);
+        assert_eq!(effects("/usr/bin/curl -s x"), vec![vec!["network"]]);
+        assert_eq!(effects("sed -i s/a/b/ f"), vec![vec!["read", "write"]]);
+        assert_eq!(
+            effects("rsync -a dist/ build/"),
+            vec![vec!["read", "write"]]
+        );
+        assert_eq!(
+            effects("rsync -a dist/ deploy@prod:/srv"),
+            vec![vec!["read", "write", "network"]]
+        );
+        assert_eq!(
+            effects("scp -r host:logs ."),
+            vec![vec!["read", "write", "network"]]
+        );
+
+        // Unknown programs have no known effects
+        assert_eq!(effects("frobnicate x"), vec![Vec::<&str>::new()]);
+    }
+
+    #[test]
+    fn test_redirects_and_wrappers() {
+        assert_eq!(effects("echo hi > out.txt"), vec![vec!["write"]]);
+        assert_eq!(effects("grep x f 2>/dev/null"), vec![vec!["read"]]);
+        assert_eq!(effects("wc -l < in.txt"), vec![vec!["read"]]);
+
+        // Commands run by sudo run with its privileges
+        assert_eq!(
+            effects("sudo -u root rm -rf /var/x"),
+            vec![vec!["privilege"], vec!["write", "destructive", "privilege"]]
+        );
+    }
+}
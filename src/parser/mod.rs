@@ -1,7 +1,21 @@
 mod brush_adapter;
 mod command;
+mod effects;
+mod embedded;
+mod expansion;
+mod obfuscation;
+mod recipes;
+mod scripts;
 mod semantic;
 
 pub use brush_adapter::parse_with_brush;
-pub use command::ParsedCommand;
+pub use command::{
+    CommandContext, ControlFlow, FileSource, Loop, LoopKind, Nesting, ParsedCommand, ScriptFile,
+    ScriptStatus, Span,
+};
+pub use effects::Effect;
+pub use expansion::expand_globs;
+pub use obfuscation::Obfuscation;
+pub use recipes::resolve_recipes;
+pub use scripts::resolve_scripts;
 pub use semantic::SemanticAnalyzer;
@@ -0,0 +1,333 @@
+//! Detection of techniques used to hide what a command does
+//!
+//! `echo cm0gLXJmIC8= | base64 -d | sh`, `r''m -rf /` or `$'\x72\x6d'` run commands
+//! that no rule written against their plain form would see. Rather than trying to
+//! decode every trick, commands using one are tagged so they can be handled on their
+//! own (and are never silently allowed, see `settings.obfuscation_action`).
+
+use std::{fmt, path::Path};
+
+use serde::Serialize;
+
+use super::command::ParsedCommand;
+
+/// A known technique for hiding a command's real text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Obfuscation {
+    /// Decoded or generated text piped into a shell (`base64 -d | sh`,
+    /// `printf '\x72\x6d' | bash`)
+    DecodeToShell,
+    /// Code fed to a shell through a here-string or process substitution
+    /// (`bash <<< "rm x"`, `sh <(curl -s x)`)
+    FedToShell,
+    /// `eval` or `sh -c` running text only known at runtime (`eval "$(...)"`)
+    DynamicEval,
+    /// ANSI-C quoting in a program name, or numeric escapes in arguments (`$'\x72\x6d'`)
+    AnsiCEscape,
+    /// Quotes or backslashes in a program name that needs none (`r''m`, `\r\m`)
+    QuotedProgram,
+    /// `$IFS` used as a word separator (`cat${IFS}/etc/passwd`)
+    IfsExpansion,
+    /// Non-ASCII characters in a program name, which can imitate ASCII ones
+    Homoglyph,
+    /// Zero-width or bidirectional control characters
+    InvisibleCharacters,
+}
+
+impl Obfuscation {
+    pub const ALL: &'static [Obfuscation] = &[
+        Obfuscation::DecodeToShell,
+        Obfuscation::FedToShell,
+        Obfuscation::DynamicEval,
+        Obfuscation::AnsiCEscape,
+        Obfuscation::QuotedProgram,
+        Obfuscation::IfsExpansion,
+        Obfuscation::Homoglyph,
+        Obfuscation::InvisibleCharacters,
+    ];
+
+    /// Name used in configuration (e.g., `obfuscation = "decode-to-shell"`)
+    pub fn name(self) -> &'static str {
+        match self {
+            Obfuscation::DecodeToShell => "decode-to-shell",
+            Obfuscation::FedToShell => "fed-to-shell",
+            Obfuscation::DynamicEval => "dynamic-eval",
+            Obfuscation::AnsiCEscape => "ansi-c-escape",
+            Obfuscation::QuotedProgram => "quoted-program",
+            Obfuscation::IfsExpansion => "ifs-expansion",
+            Obfuscation::Homoglyph => "homoglyph",
+            Obfuscation::InvisibleCharacters => "invisible-characters",
+        }
+    }
+}
+
+impl fmt::Display for Obfuscation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Shells and interpreters that run code read from stdin
+const STDIN_INTERPRETERS: &[&str] = &[
+    "sh", "bash", "dash", "zsh", "ksh", "python", "python3", "perl", "ruby", "node", "php",
+];
+
+/// Techniques visible in a single command's words, given as written (`raw_words`,
+/// program first) and after unquoting (`command`)
+pub(super) fn detect_in_words(raw_words: &[String], command: &ParsedCommand) -> Vec<Obfuscation> {
+    let mut found = Vec::new();
+    let mut add = |technique| {
+        if !found.contains(&technique) {
+            found.push(technique);
+        }
+    };
+
+    let raw_program = raw_words.first().map(String::as_str).unwrap_or_default();
+    let program = command.program.as_str();
+
+    if raw_program.contains("$'") {
+        add(Obfuscation::AnsiCEscape);
+    }
+    if raw_words
+        .iter()
+        .skip(1)
+        .any(|w| has_numeric_ansi_c_escape(w))
+    {
+        add(Obfuscation::AnsiCEscape);
+    }
+
+    let plain_program = !program.is_empty()
+        && program
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._/+-".contains(c));
+    if plain_program && !raw_program.contains('$') && raw_program.contains(['\'', '"', '\\']) {
+        add(Obfuscation::QuotedProgram);
+    }
+
+    if raw_words
+        .iter()
+        .any(|w| w.contains("$IFS") || w.contains("${IFS"))
+    {
+        add(Obfuscation::IfsExpansion);
+    }
+
+    if !program.is_ascii() {
+        add(Obfuscation::Homoglyph);
+    }
+
+    if raw_words.iter().any(|w| w.chars().any(is_invisible)) {
+        add(Obfuscation::InvisibleCharacters);
+    }
+
+    let runs_code =
+        program == "eval" || (is_shell(program) && command.raw_args.iter().any(|a| a == "-c"));
+    if runs_code && (command.has_expansion || command.has_substitution) {
+        add(Obfuscation::DynamicEval);
+    }
+
+    found
+}
+
+/// Tag interpreters reading stdin from a pipeline whose earlier stage decodes or
+/// generates text. `stages` holds the index in `commands` of each pipeline stage.
+pub(super) fn detect_in_pipeline(commands: &mut [ParsedCommand], stages: &[usize]) {
+    let mut decoded = false;
+
+    for &stage in stages {
+        let Some(command) = commands.get_mut(stage) else {
+            continue;
+        };
+        if decoded
+            && reads_code_from_stdin(command)
+            && !command.obfuscation.contains(&Obfuscation::DecodeToShell)
+        {
+            command.obfuscation.push(Obfuscation::DecodeToShell);
+        }
+        decoded |= is_decoder(command);
+    }
+}
+
+/// Tag shells and interpreters running code given by a here-string on stdin
+/// (`here_string`) or a process substitution as their script (`substituted_script`,
+/// `sh <(...)`, `source <(...)`, `bash < <(...)`)
+pub(super) fn detect_in_inputs(
+    command: &mut ParsedCommand,
+    here_string: bool,
+    substituted_script: bool,
+) {
+    let sources = matches!(command.program.as_str(), "source" | ".");
+    let fed = (here_string && reads_code_from_stdin(command))
+        || (substituted_script
+            && (sources || STDIN_INTERPRETERS.contains(&name(&command.program)))
+            && !has_code_arg(command));
+    if fed && !command.obfuscation.contains(&Obfuscation::FedToShell) {
+        command.obfuscation.push(Obfuscation::FedToShell);
+    }
+}
+
+fn name(program: &str) -> &str {
+    Path::new(program)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(program)
+}
+
+fn is_shell(program: &str) -> bool {
+    matches!(name(program), "sh" | "bash" | "dash" | "zsh" | "ksh")
+}
+
+/// Whether a command turns encoded input (or its own escaped arguments) into text
+fn is_decoder(command: &ParsedCommand) -> bool {
+    let flag = |f: &str| command.flags.contains(f);
+
+    match name(&command.program) {
+        "base64" | "base32" | "basenc" => flag("-d") || flag("--decode") || flag("-D"),
+        "xxd" => flag("-r"),
+        "openssl" => flag("-d") || flag("-base64") || flag("-a"),
+        "gzip" | "bzip2" | "xz" => flag("-d") || flag("--decompress"),
+        "gunzip" | "zcat" | "bunzip2" | "unxz" | "uudecode" | "rev" => true,
+        "printf" => command.raw_args.iter().any(|a| has_numeric_escape(a)),
+        "echo" => flag("-e") && command.raw_args.iter().any(|a| has_numeric_escape(a)),
+        _ => false,
+    }
+}
+
+/// Whether a command runs code given on stdin (no script file or `-c` argument)
+fn reads_code_from_stdin(command: &ParsedCommand) -> bool {
+    if !STDIN_INTERPRETERS.contains(&name(&command.program)) {
+        return false;
+    }
+    let mut args = command
+        .raw_args
+        .iter()
+        .filter(|a| !a.starts_with('-') || a.as_str() == "-");
+    !has_code_arg(command) && args.next().is_none_or(|a| a == "-")
+}
+
+/// Whether an interpreter is given its code as an argument (`sh -c`, `perl -e`)
+fn has_code_arg(command: &ParsedCommand) -> bool {
+    command
+        .raw_args
+        .iter()
+        .any(|a| matches!(a.as_str(), "-c" | "-e" | "--eval"))
+}
+
+/// `\xHH`, `\NNN` (octal) or `\uHHHH` escapes, as taken by `printf` and `echo -e`
+fn has_numeric_escape(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.windows(2).any(|w| {
+        w[0] == b'\\' && (matches!(w[1], b'x' | b'u' | b'U') || (b'0'..=b'7').contains(&w[1]))
+    })
+}
+
+/// Numeric escapes inside `$'...'` quoting
+fn has_numeric_ansi_c_escape(raw: &str) -> bool {
+    raw.split("$'").skip(1).any(|rest| {
+        let quoted = rest.split('\'').next().unwrap_or_default();
+        has_numeric_escape(quoted)
+    })
+}
+
+/// Zero-width and bidirectional control characters
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn techniques(input: &str) -> Vec<Vec<Obfuscation>> {
+        ParsedCommand::parse_all(input)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.obfuscation)
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_to_shell() {
+        assert_eq!(
+            techniques("echo cm0gLXJmIC8= | base64 -d | sh"),
+            vec![vec![], vec![], vec![Obfuscation::DecodeToShell]]
+        );
+        assert_eq!(
+            techniques(r"printf '\x72\x6d' | bash")[1],
+            vec![Obfuscation::DecodeToShell]
+        );
+        assert_eq!(
+            techniques("curl -s x | gunzip | python3 -")[2],
+            vec![Obfuscation::DecodeToShell]
+        );
+
+        // Interpreters given a script, and plain pipelines, are not tagged
+        assert!(techniques("base64 -d x | bash run.sh")[1].is_empty());
+        assert!(techniques("cat x | base64 -d | less")[2].is_empty());
+        assert!(techniques("cat x | sh")[1].is_empty());
+    }
+
+    #[test]
+    fn test_fed_to_shell() {
+        assert_eq!(
+            techniques("sh <(echo cm0= | base64 -d)")[0],
+            vec![Obfuscation::FedToShell]
+        );
+        assert_eq!(
+            techniques(r#"bash <<< "rm x""#)[0],
+            vec![Obfuscation::FedToShell]
+        );
+        assert_eq!(
+            techniques("source <(curl -s x)")[0],
+            vec![Obfuscation::FedToShell]
+        );
+        assert_eq!(
+            techniques("bash < <(curl -s x)")[0],
+            vec![Obfuscation::FedToShell]
+        );
+
+        // Here-strings and process substitutions given as data are not tagged
+        assert!(techniques("bash -c 'cat' <<< x")[0].is_empty());
+        assert!(techniques("bash run.sh <(ls)")[0].is_empty());
+        assert!(techniques("diff <(ls a) <(ls b)")[0].is_empty());
+        assert!(techniques("grep x <<< y")[0].is_empty());
+    }
+
+    #[test]
+    fn test_word_techniques() {
+        assert_eq!(
+            techniques("r''m -rf /")[0],
+            vec![Obfuscation::QuotedProgram]
+        );
+        assert_eq!(
+            techniques(r"\r\m -rf /")[0],
+            vec![Obfuscation::QuotedProgram]
+        );
+        assert_eq!(
+            techniques(r"$'\x72\x6d' -rf /")[0],
+            vec![Obfuscation::AnsiCEscape]
+        );
+        assert_eq!(
+            techniques("cat${IFS}/etc/passwd")[0],
+            vec![Obfuscation::IfsExpansion]
+        );
+        assert_eq!(techniques("rм -rf /")[0], vec![Obfuscation::Homoglyph]);
+        assert_eq!(
+            techniques("ls \u{200B}-la")[0],
+            vec![Obfuscation::InvisibleCharacters]
+        );
+        assert_eq!(
+            techniques(r#"eval "$(curl -s x)""#)[0],
+            vec![Obfuscation::DynamicEval]
+        );
+
+        // Ordinary quoting and escapes in arguments are fine
+        assert!(techniques(r#"git commit -m "fix: it's done""#)[0].is_empty());
+        assert!(techniques(r"cut -d $'\t' -f1 x")[0].is_empty());
+        assert!(techniques(r#"bash -c "echo hi""#)[0].is_empty());
+    }
+}
@@ -0,0 +1,692 @@
+//! Resolution of task-runner recipes (make, just, npm/pnpm/yarn scripts, cargo aliases)
+//!
+//! `make deploy` or `npm run nuke` hide arbitrary shell behind a name. When enabled,
+//! the recipe's shell lines are read from the working directory and parsed into their
+//! own `ParsedCommand`s, tagged with the file and line they came from. Invocations
+//! whose recipe can't be found (`make nosuch`, a target built by a pattern rule or an
+//! included makefile, `npm run missing`) are marked like unresolved scripts.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    brush_adapter::parse_with_brush,
+    command::{FileSource, ParsedCommand, ScriptFile, ScriptStatus},
+};
+
+/// Maximum nesting of recipes that invoke other task runners
+const MAX_DEPTH: usize = 4;
+
+/// A shell line from a recipe
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecipeLine {
+    path: PathBuf,
+    line: usize,
+    text: String,
+}
+
+/// What a task-runner invocation runs
+#[derive(Debug, Default)]
+struct Recipe {
+    lines: Vec<RecipeLine>,
+    /// File that should define a recipe (or one it depends on) that wasn't found
+    unresolved: Option<PathBuf>,
+}
+
+/// Resolve task-runner invocations in `commands`, inserting each recipe's commands
+/// right after the invocation that runs them.
+pub fn resolve_recipes(commands: Vec<ParsedCommand>, dir: &Path) -> Vec<ParsedCommand> {
+    resolve_at_depth(commands, dir, 0)
+}
+
+fn resolve_at_depth(commands: Vec<ParsedCommand>, dir: &Path, depth: usize) -> Vec<ParsedCommand> {
+    let mut results = Vec::new();
+
+    for mut command in commands {
+        let recipe = recipe_lines(&command, dir);
+        let status = if recipe.unresolved.is_some() {
+            ScriptStatus::RecipeNotFound
+        } else {
+            ScriptStatus::TooDeep
+        };
+        let unresolved = match recipe.unresolved {
+            Some(path) => Some(path),
+            None if depth >= MAX_DEPTH => recipe.lines.first().map(|line| line.path.clone()),
+            None => None,
+        };
+        if let Some(path) = unresolved {
+            command.script = Some(ScriptFile {
+                path,
+                sha256: None,
+                status,
+            });
+        }
+        let lines = if depth < MAX_DEPTH {
+            recipe.lines
+        } else {
+            Vec::new()
+        };
+        let label = invocation_label(&command);
+        let (span, nesting, control) = (
+            command.span,
+            command.nesting.clone(),
+            command.control.clone(),
+        );
+        results.push(command);
+
+        for line in lines {
+            let parsed = match parse_with_brush(&line.text) {
+                Ok(parsed) => parsed,
+                // Keep unparseable lines whole so they fall through to the default action
+                Err(_) => vec![ParsedCommand::opaque(&line.text)],
+            };
+
+            for mut inner in resolve_at_depth(parsed, dir, depth + 1) {
+                if inner.file_source.is_none() {
+                    inner.file_source = Some(FileSource {
+                        path: line.path.clone(),
+                        line: line.line,
+                        label: Some(label.clone()),
+                    });
+                }
+                // Located in the input by the invocation that runs the recipe
+                inner.span = span;
+                inner.nesting.splice(0..0, nesting.iter().copied());
+                inner.control.within(&control);
+                results.push(inner);
+            }
+        }
+    }
+
+    results
+}
+
+fn invocation_label(command: &ParsedCommand) -> String {
+    std::iter::once(command.program.as_str())
+        .chain(command.raw_args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Shell lines run by a task-runner invocation (empty if it isn't one)
+fn recipe_lines(command: &ParsedCommand, dir: &Path) -> Recipe {
+    let args = &command.raw_args;
+    match command.program.as_str() {
+        "make" | "gmake" => make(args, dir),
+        "just" => just(args, dir),
+        "npm" | "pnpm" | "yarn" => package_script(&command.program, args, dir),
+        "cargo" => Recipe {
+            lines: cargo_alias(args, dir),
+            unresolved: None,
+        },
+        _ => Recipe::default(),
+    }
+}
+
+/// First non-option argument (skipping options listed in `with_value` and their values)
+fn first_target<'a>(args: &'a [String], with_value: &[&str]) -> Option<&'a str> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if with_value.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') && !arg.contains('=') {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+/// All non-option arguments
+fn targets<'a>(args: &'a [String], with_value: &[&str]) -> Vec<&'a str> {
+    let mut targets = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if with_value.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') && !arg.contains('=') {
+            targets.push(arg.as_str());
+        }
+    }
+    targets
+}
+
+fn option_value<'a>(args: &'a [String], names: &[&str]) -> Option<&'a str> {
+    args.iter()
+        .position(|a| names.contains(&a.as_str()))
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+/// Strip recipe line prefixes that control echo/error handling (`@`, `-`, `+`)
+fn strip_line_prefixes(line: &str) -> &str {
+    line.trim_start_matches(['@', '-', '+']).trim_start()
+}
+
+const MAKE_OPTIONS_WITH_VALUE: &[&str] = &["-C", "-f", "-I", "-j", "-l", "-o", "-W", "--file"];
+
+fn make(args: &[String], dir: &Path) -> Recipe {
+    let dir = match option_value(args, &["-C", "--directory"]) {
+        Some(sub) => dir.join(sub),
+        None => dir.to_path_buf(),
+    };
+    let path = match option_value(args, &["-f", "--file", "--makefile"]) {
+        Some(file) => dir.join(file),
+        None => match ["GNUmakefile", "makefile", "Makefile"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|p| p.is_file())
+        {
+            Some(path) => path,
+            None => return Recipe::default(),
+        },
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Recipe::default();
+    };
+
+    let makefile = parse_makefile(&contents);
+    let mut requested: Vec<String> = targets(args, MAKE_OPTIONS_WITH_VALUE)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    if requested.is_empty() {
+        match makefile.iter().find(|t| !t.name.starts_with('.')) {
+            Some(default) => requested.push(default.name.clone()),
+            None => return Recipe::default(),
+        }
+    }
+
+    let mut recipe = Recipe::default();
+    let mut visited = HashSet::new();
+    for target in &requested {
+        collect_make_target(&makefile, target, &path, &dir, &mut visited, &mut recipe);
+    }
+    recipe
+}
+
+#[derive(Debug)]
+struct MakeTarget {
+    name: String,
+    prerequisites: Vec<String>,
+    /// (line number, text)
+    recipe: Vec<(usize, String)>,
+}
+
+fn parse_makefile(contents: &str) -> Vec<MakeTarget> {
+    let mut targets: Vec<MakeTarget> = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+
+    for (i, raw) in contents.lines().enumerate() {
+        let line_no = i + 1;
+
+        if let Some(rest) = raw.strip_prefix('\t') {
+            if current.is_empty() {
+                continue;
+            }
+            // Join backslash continuations into one recipe line
+            let (start, mut text) = pending.take().unwrap_or((line_no, String::new()));
+            text.push_str(rest.trim_end_matches('\\'));
+            if rest.ends_with('\\') {
+                pending = Some((start, text));
+                continue;
+            }
+            for &t in &current {
+                targets[t].recipe.push((start, text.clone()));
+            }
+            continue;
+        }
+
+        pending = None;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // Rule line: `targets: prerequisites` (but not `VAR := value`)
+        match raw.split_once(':') {
+            Some((names, deps)) if !deps.starts_with('=') && !names.contains('=') => {
+                let deps = deps.split(';').next().unwrap_or("");
+                current.clear();
+                for name in names.split_whitespace() {
+                    current.push(targets.len());
+                    targets.push(MakeTarget {
+                        name: name.to_string(),
+                        prerequisites: deps.split_whitespace().map(str::to_string).collect(),
+                        recipe: Vec::new(),
+                    });
+                }
+            }
+            _ => current.clear(),
+        }
+    }
+
+    targets
+}
+
+/// Collect the recipe lines of a target and its prerequisites. A name without an
+/// explicit rule is fine if it's an existing file; otherwise make would build it with
+/// a rule that isn't read here (a pattern rule, an included makefile, a variable).
+fn collect_make_target(
+    makefile: &[MakeTarget],
+    name: &str,
+    path: &Path,
+    dir: &Path,
+    visited: &mut HashSet<String>,
+    recipe: &mut Recipe,
+) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+
+    let mut targets = makefile.iter().filter(|t| t.name == name).peekable();
+    if targets.peek().is_none() && (name.contains('$') || !dir.join(name).exists()) {
+        recipe.unresolved = Some(path.to_path_buf());
+    }
+    for target in targets {
+        for dep in &target.prerequisites {
+            collect_make_target(makefile, dep, path, dir, visited, recipe);
+        }
+        for (line, text) in &target.recipe {
+            recipe.lines.push(RecipeLine {
+                path: path.to_path_buf(),
+                line: *line,
+                text: strip_line_prefixes(text).to_string(),
+            });
+        }
+    }
+}
+
+fn just(args: &[String], dir: &Path) -> Recipe {
+    let path = match option_value(args, &["-f", "--justfile"]) {
+        Some(file) => dir.join(file),
+        None => match ["justfile", "Justfile", ".justfile"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|p| p.is_file())
+        {
+            Some(path) => path,
+            None => return Recipe::default(),
+        },
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Recipe::default();
+    };
+
+    let recipes = parse_justfile(&contents);
+    let requested = match first_target(args, &["-f", "--justfile", "-d", "--working-directory"]) {
+        Some(name) => name.to_string(),
+        None => match recipes.first() {
+            Some(default) => default.name.clone(),
+            None => return Recipe::default(),
+        },
+    };
+
+    let mut recipe = Recipe::default();
+    let mut visited = HashSet::new();
+    collect_just_recipe(&recipes, &requested, &path, &mut visited, &mut recipe);
+    recipe
+}
+
+#[derive(Debug)]
+struct JustRecipe {
+    name: String,
+    dependencies: Vec<String>,
+    /// (line number, text)
+    body: Vec<(usize, String)>,
+}
+
+fn parse_justfile(contents: &str) -> Vec<JustRecipe> {
+    let mut recipes: Vec<JustRecipe> = Vec::new();
+    let mut in_recipe = false;
+
+    for (i, raw) in contents.lines().enumerate() {
+        let line_no = i + 1;
+
+        if raw.starts_with([' ', '\t']) {
+            if in_recipe && !raw.trim().is_empty() {
+                if let Some(recipe) = recipes.last_mut() {
+                    recipe.body.push((line_no, raw.trim().to_string()));
+                }
+            }
+            continue;
+        }
+
+        in_recipe = false;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('[') {
+            continue;
+        }
+
+        // Recipe header: `name params: deps` (but not `name := value` or `set ...`)
+        if let Some((header, deps)) = trimmed.split_once(':') {
+            if deps.starts_with('=') || header.starts_with("set ") || header.starts_with("export ")
+            {
+                continue;
+            }
+            let Some(name) = header.split_whitespace().next() else {
+                continue;
+            };
+            let name = name.trim_start_matches('@');
+            recipes.push(JustRecipe {
+                name: name.to_string(),
+                dependencies: deps
+                    .split_whitespace()
+                    .filter(|d| !d.starts_with('('))
+                    .map(str::to_string)
+                    .collect(),
+                body: Vec::new(),
+            });
+            in_recipe = true;
+        }
+    }
+
+    recipes
+}
+
+fn collect_just_recipe(
+    recipes: &[JustRecipe],
+    name: &str,
+    path: &Path,
+    visited: &mut HashSet<String>,
+    collected: &mut Recipe,
+) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+    // Recipes may come from imported justfiles and modules, which aren't read
+    let Some(recipe) = recipes.iter().find(|r| r.name == name) else {
+        collected.unresolved = Some(path.to_path_buf());
+        return;
+    };
+
+    for dep in &recipe.dependencies {
+        collect_just_recipe(recipes, dep, path, visited, collected);
+    }
+
+    // Shebang recipes run their whole body with another interpreter; only shell
+    // bodies can be parsed, others are evaluated as a call to the interpreter
+    if let Some((line, first)) = recipe.body.first() {
+        if let Some(interpreter) = first.strip_prefix("#!") {
+            let is_shell = ["sh", "bash", "zsh", "dash"].iter().any(|sh| {
+                interpreter
+                    .split_whitespace()
+                    .any(|w| Path::new(w).file_name().is_some_and(|n| n == *sh))
+            });
+            if !is_shell {
+                collected.lines.push(RecipeLine {
+                    path: path.to_path_buf(),
+                    line: *line,
+                    text: interpreter.trim().to_string(),
+                });
+                return;
+            }
+        }
+    }
+
+    for (line, text) in &recipe.body {
+        if text.starts_with('#') {
+            continue;
+        }
+        collected.lines.push(RecipeLine {
+            path: path.to_path_buf(),
+            line: *line,
+            text: strip_line_prefixes(text).to_string(),
+        });
+    }
+}
+
+/// npm lifecycle commands that run the script of the same name
+const NPM_SCRIPT_SHORTCUTS: &[&str] = &["start", "stop", "test", "restart"];
+
+fn package_script(program: &str, args: &[String], dir: &Path) -> Recipe {
+    let positional = targets(
+        args,
+        &["--prefix", "-C", "--dir", "--cwd", "--filter", "-F"],
+    );
+    // Whether the invocation can only mean a script (bare names may be a built-in
+    // command or a package binary for pnpm and yarn)
+    let (script, explicit) = match positional.as_slice() {
+        ["run" | "run-script", name, ..] => (*name, true),
+        [name, ..] if NPM_SCRIPT_SHORTCUTS.contains(name) => (*name, true),
+        // pnpm and yarn run scripts directly by name
+        [name, ..] if program != "npm" => (*name, false),
+        _ => return Recipe::default(),
+    };
+
+    let dir = match option_value(args, &["--prefix", "-C", "--dir", "--cwd"]) {
+        Some(sub) => dir.join(sub),
+        None => dir.to_path_buf(),
+    };
+    let path = dir.join("package.json");
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Recipe::default();
+    };
+    let Ok(package) = serde_json::from_str::<serde_json::Value>(&contents) else {
+        return Recipe::default();
+    };
+    let scripts = package["scripts"].as_object();
+    let found = scripts.is_some_and(|scripts| scripts.contains_key(script));
+
+    // pre/post scripts run around the requested one
+    let lines = [
+        format!("pre{}", script),
+        script.to_string(),
+        format!("post{}", script),
+    ]
+    .iter()
+    .filter_map(|name| {
+        let text = scripts?.get(name)?.as_str()?;
+        Some(RecipeLine {
+            path: path.clone(),
+            line: line_of(&contents, &format!("\"{}\"", name)),
+            text: text.to_string(),
+        })
+    })
+    .collect();
+
+    Recipe {
+        lines,
+        unresolved: (explicit && !found).then_some(path),
+    }
+}
+
+/// 1-based line of the first occurrence of `needle` (or 1 if absent)
+fn line_of(contents: &str, needle: &str) -> usize {
+    contents
+        .lines()
+        .position(|l| l.contains(needle))
+        .map(|i| i + 1)
+        .unwrap_or(1)
+}
+
+fn cargo_alias(args: &[String], dir: &Path) -> Vec<RecipeLine> {
+    let Some(name) = first_target(args, &["-Z", "--config", "--color"]) else {
+        return Vec::new();
+    };
+
+    // Aliases may be defined in any .cargo/config.toml up the tree
+    for ancestor in dir.ancestors() {
+        let path = ancestor.join(".cargo").join("config.toml");
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let Ok(config) = contents.parse::<toml::Table>() else {
+            continue;
+        };
+        let Some(alias) = config.get("alias").and_then(|a| a.get(name)) else {
+            continue;
+        };
+
+        let expansion = match alias {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Array(parts) => parts
+                .iter()
+                .filter_map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => continue,
+        };
+
+        let rest: Vec<&str> = args
+            .iter()
+            .skip_while(|a| a.as_str() != name)
+            .skip(1)
+            .map(String::as_str)
+            .collect();
+        let mut text = format!("cargo {}", expansion);
+        if !rest.is_empty() {
+            text.push(' ');
+            text.push_str(&rest.join(" "));
+        }
+
+        return vec![RecipeLine {
+            line: line_of(&contents, name),
+            path,
+            text,
+        }];
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn programs(commands: &[ParsedCommand]) -> Vec<&str> {
+        commands.iter().map(|c| c.program.as_str()).collect()
+    }
+
+    #[test]
+    fn test_make_target_with_prerequisites() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("Makefile"),
+            "CC := gcc\n\nbuild:\n\tcargo build\n\ndeploy: build\n\t@echo deploying\n\t-rsync -a dist/ \\\n\t  prod:/srv\n",
+        )
+        .unwrap();
+
+        let commands = ParsedCommand::parse_all("make deploy").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+
+        assert_eq!(programs(&resolved), vec!["make", "cargo", "echo", "rsync"]);
+        let rsync = resolved[3].file_source.as_ref().unwrap();
+        assert_eq!(rsync.line, 8);
+        assert_eq!(rsync.label.as_deref(), Some("make deploy"));
+    }
+
+    #[test]
+    fn test_just_default_recipe() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("Justfile"),
+            "_:\n    @just --list\n\nrelease: test\n    git push --tags\n\ntest:\n    cargo test\n",
+        )
+        .unwrap();
+
+        let commands = ParsedCommand::parse_all("just release").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["just", "cargo", "git"]);
+
+        // `just` alone runs the first recipe, which calls just again (depth-limited)
+        let commands = ParsedCommand::parse_all("just").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(&programs(&resolved)[..2], &["just", "just"]);
+    }
+
+    #[test]
+    fn test_npm_scripts() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("package.json"),
+            r#"{
+  "scripts": {
+    "prenuke": "echo bye",
+    "nuke": "rm -rf node_modules && git clean -fdx",
+    "test": "jest"
+  }
+}"#,
+        )
+        .unwrap();
+
+        let commands = ParsedCommand::parse_all("npm run nuke").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["npm", "echo", "rm", "git"]);
+        assert_eq!(resolved[2].file_source.as_ref().unwrap().line, 4);
+
+        let commands = ParsedCommand::parse_all("npm test").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["npm", "jest"]);
+
+        let commands = ParsedCommand::parse_all("yarn nuke").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["yarn", "echo", "rm", "git"]);
+    }
+
+    #[test]
+    fn test_cargo_alias() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join(".cargo")).unwrap();
+        fs::write(
+            temp.path().join(".cargo").join("config.toml"),
+            "[alias]\nship = \"publish --allow-dirty\"\n",
+        )
+        .unwrap();
+
+        let commands = ParsedCommand::parse_all("cargo ship").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["cargo", "cargo"]);
+        assert_eq!(resolved[1].subcommands, vec!["publish"]);
+        assert!(resolved[1].flags.contains("--allow-dirty"));
+    }
+
+    #[test]
+    fn test_unresolved_recipes() {
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("Makefile"),
+            "include rules.mk\n\nall: main.c build/out.o\n\tcc -o app build/out.o\n\n%.o: %.c\n\tcc -c $<\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("main.c"), "").unwrap();
+        fs::write(
+            temp.path().join("package.json"),
+            r#"{"scripts": {"test": "jest"}}"#,
+        )
+        .unwrap();
+
+        let status = |input: &str| {
+            let commands = ParsedCommand::parse_all(input).unwrap();
+            resolve_recipes(commands, temp.path())[0]
+                .script
+                .as_ref()
+                .map(|script| script.status)
+        };
+        // Targets only a pattern rule or an included makefile can build
+        assert_eq!(status("make nosuch"), Some(ScriptStatus::RecipeNotFound));
+        assert_eq!(status("make all"), Some(ScriptStatus::RecipeNotFound));
+        // Existing files need no rule
+        assert_eq!(status("make main.c"), None);
+        assert_eq!(
+            status("npm run missing"),
+            Some(ScriptStatus::RecipeNotFound)
+        );
+        assert_eq!(status("npm test"), None);
+        // Bare pnpm/yarn names may be built-in commands
+        assert_eq!(status("yarn install"), None);
+    }
+
+    #[test]
+    fn test_no_task_runner_files() {
+        let temp = TempDir::new().unwrap();
+        let commands = ParsedCommand::parse_all("make deploy && npm run x").unwrap();
+        let resolved = resolve_recipes(commands, temp.path());
+        assert_eq!(programs(&resolved), vec!["make", "npm"]);
+    }
+}
@@ -0,0 +1,396 @@
+//! Resolution of local shell scripts run by a command
+//!
+//! `./scripts/deploy.sh`, `bash tools/reset.sh` and `source env.sh` run whatever the
+//! script contains. When enabled, scripts inside the workspace are parsed with the
+//! same adapter and each of their commands is evaluated like any other.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::hash::sha256_hex;
+
+use super::{
+    brush_adapter::parse_with_brush,
+    command::{FileSource, ParsedCommand, ScriptFile, ScriptStatus},
+};
+
+/// Maximum nesting of scripts that run other scripts
+const MAX_DEPTH: usize = 4;
+
+/// Bytes read to tell scripts from binaries
+const HEADER_SIZE: usize = 8192;
+
+/// Shells whose scripts can be parsed
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh"];
+
+/// Other interpreters that run a file argument, with the short flags that make them
+/// run inline code or a module instead (`python -c`, `node -e`, `perl -ne`)
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "cm"),
+    ("node", "ep"),
+    ("deno", ""),
+    ("bun", "e"),
+    ("ruby", "e"),
+    ("perl", "eE"),
+    ("php", "r"),
+    ("lua", "e"),
+];
+
+/// Resolve scripts run by `commands`, inserting each script's commands right after
+/// the command that runs it.
+///
+/// `workspace` is both the directory relative paths are resolved against and the
+/// boundary scripts must stay inside. Scripts for which `is_pinned` returns true
+/// (e.g., allowed by a rule pinning their content hash) are not expanded.
+pub fn resolve_scripts(
+    commands: Vec<ParsedCommand>,
+    workspace: &Path,
+    max_size: u64,
+    is_pinned: &dyn Fn(&ParsedCommand) -> bool,
+) -> Vec<ParsedCommand> {
+    let workspace = workspace
+        .canonicalize()
+        .unwrap_or_else(|_| workspace.to_path_buf());
+    resolve_at_depth(commands, &workspace, max_size, is_pinned, 0)
+}
+
+fn resolve_at_depth(
+    commands: Vec<ParsedCommand>,
+    workspace: &Path,
+    max_size: u64,
+    is_pinned: &dyn Fn(&ParsedCommand) -> bool,
+    depth: usize,
+) -> Vec<ParsedCommand> {
+    let mut results = Vec::new();
+
+    for mut command in commands {
+        let Some((path, by_interpreter)) = script_path(&command) else {
+            results.push(command);
+            continue;
+        };
+        let path = workspace.join(&path);
+        let (mut script, contents) = match load_script(&path, workspace, max_size) {
+            Some(loaded) => loaded,
+            // Interpreters run their file argument even if it looks binary (.pyc)
+            None if by_interpreter => (
+                ScriptFile {
+                    path,
+                    sha256: None,
+                    status: ScriptStatus::NotShell,
+                },
+                String::new(),
+            ),
+            None => {
+                results.push(command);
+                continue;
+            }
+        };
+
+        if by_interpreter && script.status == ScriptStatus::Resolved {
+            script.status = ScriptStatus::NotShell;
+        }
+        if depth >= MAX_DEPTH && script.status == ScriptStatus::Resolved {
+            script.status = ScriptStatus::TooDeep;
+        }
+        let script_path = script.path.clone();
+        let resolved = script.status == ScriptStatus::Resolved;
+        command.script = Some(script);
+
+        let label = invocation_label(&command);
+        let expand = resolved && !is_pinned(&command);
+        let (span, nesting, control) = (
+            command.span,
+            command.nesting.clone(),
+            command.control.clone(),
+        );
+        results.push(command);
+
+        if !expand {
+            continue;
+        }
+
+        let parsed = match parse_with_brush(&contents) {
+            Ok(parsed) => parsed,
+            // Keep unparseable scripts whole so they fall through to the default action
+            Err(_) => vec![ParsedCommand::opaque(&contents)],
+        };
+
+        // Script commands are located in the script, and in the input by the
+        // command running it
+        for mut inner in resolve_at_depth(parsed, workspace, max_size, is_pinned, depth + 1) {
+            if inner.file_source.is_none() {
+                let line = inner
+                    .span
+                    .and_then(|span| contents.get(..span.start))
+                    .map_or(0, |before| before.matches('\n').count() + 1);
+                inner.file_source = Some(FileSource {
+                    path: script_path.clone(),
+                    line,
+                    label: Some(label.clone()),
+                });
+            }
+            inner.span = span;
+            inner.nesting.splice(0..0, nesting.iter().copied());
+            inner.control.within(&control);
+            results.push(inner);
+        }
+    }
+
+    results
+}
+
+fn invocation_label(command: &ParsedCommand) -> String {
+    std::iter::once(command.program.as_str())
+        .chain(command.raw_args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Path of the script a command would run, if any, and whether a non-shell
+/// interpreter runs it
+fn script_path(command: &ParsedCommand) -> Option<(PathBuf, bool)> {
+    let program = command.program.as_str();
+    let args = &command.raw_args;
+
+    // `source FILE` and `. FILE`
+    if program == "source" || program == "." {
+        return args.first().map(|arg| (PathBuf::from(arg), false));
+    }
+
+    // `bash [OPTIONS] FILE`, but not `bash -c '...'` or an interactive shell
+    let name = Path::new(program).file_name()?.to_string_lossy();
+    if SHELLS.contains(&name.as_ref()) {
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-c" => return None,
+                "-o" | "+o" | "-O" | "+O" => {
+                    iter.next();
+                }
+                a if a.starts_with('-') || a.starts_with('+') => {
+                    if a[1..].contains('c') && !a.starts_with("--") {
+                        return None;
+                    }
+                }
+                a => return Some((PathBuf::from(a), false)),
+            }
+        }
+        return None;
+    }
+
+    // `python [OPTIONS] FILE`, but not `python -c '...'` or `node -e '...'`
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    if let Some((_, inline)) = INTERPRETERS.iter().find(|(n, _)| *n == base) {
+        for arg in args {
+            match arg.strip_prefix('-') {
+                Some(flags)
+                    if !flags.starts_with('-') && flags.contains(|c| inline.contains(c)) =>
+                {
+                    return None;
+                }
+                Some(_) => {}
+                None => return Some((PathBuf::from(arg), true)),
+            }
+        }
+        return None;
+    }
+
+    // Programs run by path (a bare name is looked up in PATH, not the workspace)
+    program
+        .contains('/')
+        .then(|| (PathBuf::from(program), false))
+}
+
+/// Read a script, returning `None` if the file is a binary rather than a script
+fn load_script(path: &Path, workspace: &Path, max_size: u64) -> Option<(ScriptFile, String)> {
+    let unresolved = |status| {
+        Some((
+            ScriptFile {
+                path: path.to_path_buf(),
+                sha256: None,
+                status,
+            },
+            String::new(),
+        ))
+    };
+
+    let Ok(canonical) = path.canonicalize() else {
+        return unresolved(ScriptStatus::Unreadable);
+    };
+    let Ok(mut file) = File::open(&canonical) else {
+        return unresolved(ScriptStatus::Unreadable);
+    };
+
+    let mut header = vec![0; HEADER_SIZE];
+    let Ok(n) = file.read(&mut header) else {
+        return unresolved(ScriptStatus::Unreadable);
+    };
+    header.truncate(n);
+    if header.contains(&0) {
+        return None;
+    }
+
+    if !canonical.starts_with(workspace) {
+        return unresolved(ScriptStatus::OutsideWorkspace);
+    }
+    if fs::metadata(&canonical)
+        .map(|m| m.len())
+        .unwrap_or(u64::MAX)
+        > max_size
+    {
+        return unresolved(ScriptStatus::TooLarge);
+    }
+    let Ok(contents) = fs::read_to_string(&canonical) else {
+        return unresolved(ScriptStatus::Unreadable);
+    };
+
+    let status = match contents.lines().next().and_then(|l| l.strip_prefix("#!")) {
+        Some(interpreter) if !is_shell_interpreter(interpreter) => ScriptStatus::NotShell,
+        _ => ScriptStatus::Resolved,
+    };
+
+    Some((
+        ScriptFile {
+            path: canonical,
+            sha256: Some(sha256_hex(contents.as_bytes())),
+            status,
+        },
+        contents,
+    ))
+}
+
+fn is_shell_interpreter(interpreter: &str) -> bool {
+    interpreter.split_whitespace().any(|word| {
+        Path::new(word)
+            .file_name()
+            .is_some_and(|n| SHELLS.contains(&n.to_string_lossy().as_ref()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn programs(commands: &[ParsedCommand]) -> Vec<&str> {
+        commands.iter().map(|c| c.program.as_str()).collect()
+    }
+
+    fn resolve(input: &str, workspace: &Path) -> Vec<ParsedCommand> {
+        let commands = ParsedCommand::parse_all(input).unwrap();
+        resolve_scripts(commands, workspace, 1024, &|_| false)
+    }
+
+    #[test]
+    fn test_script_by_path_and_interpreter() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("scripts")).unwrap();
+        fs::write(
+            temp.path().join("scripts").join("deploy.sh"),
+            "#!/usr/bin/env bash\nset -e\ncargo build\nrsync -a dist/ prod:/srv\n",
+        )
+        .unwrap();
+
+        let resolved = resolve("./scripts/deploy.sh", temp.path());
+        assert_eq!(
+            programs(&resolved),
+            vec!["./scripts/deploy.sh", "set", "cargo", "rsync"]
+        );
+        let script = resolved[0].script.as_ref().unwrap();
+        assert_eq!(script.status, ScriptStatus::Resolved);
+        assert_eq!(script.sha256.as_ref().unwrap().len(), 64);
+        let source = resolved[2].file_source.as_ref().unwrap();
+        assert_eq!(source.label.as_deref(), Some("./scripts/deploy.sh"));
+        assert_eq!(source.line, 3);
+        assert_eq!(resolved[2].raw, "cargo build");
+        assert_eq!(resolved[2].span, resolved[0].span);
+
+        let resolved = resolve("bash -e scripts/deploy.sh", temp.path());
+        assert_eq!(programs(&resolved), vec!["bash", "set", "cargo", "rsync"]);
+
+        let resolved = resolve("source scripts/deploy.sh", temp.path());
+        assert_eq!(programs(&resolved), vec!["source", "set", "cargo", "rsync"]);
+
+        // Inline shell strings are not script files
+        let resolved = resolve("bash -c 'echo hi'", temp.path());
+        assert!(resolved[0].script.is_none());
+    }
+
+    #[test]
+    fn test_nested_scripts_and_depth_limit() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("a.sh"), "./b.sh\n").unwrap();
+        fs::write(temp.path().join("b.sh"), "rm -rf target\n").unwrap();
+        fs::write(temp.path().join("loop.sh"), "./loop.sh\n").unwrap();
+
+        let resolved = resolve("./a.sh", temp.path());
+        assert_eq!(programs(&resolved), vec!["./a.sh", "./b.sh", "rm"]);
+
+        let resolved = resolve("./loop.sh", temp.path());
+        assert_eq!(resolved.len(), MAX_DEPTH + 1);
+        assert_eq!(
+            resolved.last().unwrap().script.as_ref().unwrap().status,
+            ScriptStatus::TooDeep
+        );
+    }
+
+    #[test]
+    fn test_unresolvable_scripts() {
+        let temp = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("x.sh"), "rm -rf /\n").unwrap();
+        fs::write(temp.path().join("big.sh"), "echo hi\n".repeat(200)).unwrap();
+        fs::write(
+            temp.path().join("tool.py"),
+            "#!/usr/bin/env python3\nprint(1)\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("app"), b"\x7fELF\x00\x01").unwrap();
+
+        let status = |input: &str| {
+            let resolved = resolve(input, temp.path());
+            assert_eq!(resolved.len(), 1);
+            resolved[0].script.as_ref().map(|s| s.status)
+        };
+
+        let outside_script = outside.path().join("x.sh");
+        assert_eq!(
+            status(&outside_script.to_string_lossy()),
+            Some(ScriptStatus::OutsideWorkspace)
+        );
+        assert_eq!(status("./missing.sh"), Some(ScriptStatus::Unreadable));
+        assert_eq!(status("./big.sh"), Some(ScriptStatus::TooLarge));
+        assert_eq!(status("./tool.py"), Some(ScriptStatus::NotShell));
+
+        // Files run by other interpreters, whatever their shebang
+        fs::write(temp.path().join("x.py"), "import os\n").unwrap();
+        fs::write(temp.path().join("x.js"), "#!/bin/sh\nrm -rf /\n").unwrap();
+        assert_eq!(status("python x.py"), Some(ScriptStatus::NotShell));
+        assert_eq!(status("python3.12 -u x.py"), Some(ScriptStatus::NotShell));
+        assert_eq!(status("node x.js"), Some(ScriptStatus::NotShell));
+        assert_eq!(status("ruby ./app"), Some(ScriptStatus::NotShell));
+        assert_eq!(status("perl missing.pl"), Some(ScriptStatus::Unreadable));
+        assert_eq!(status("python -c 'print(1)'"), None);
+        assert_eq!(status("perl -ne 'print' x.py"), None);
+        assert_eq!(status("node --version"), None);
+
+        // Binaries and PATH lookups are not scripts
+        assert_eq!(status("./app"), None);
+        assert_eq!(status("ls -la"), None);
+    }
+
+    #[test]
+    fn test_pinned_script_not_expanded() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("ok.sh"), "echo ok\n").unwrap();
+
+        let commands = ParsedCommand::parse_all("./ok.sh").unwrap();
+        let resolved = resolve_scripts(commands, temp.path(), 1024, &|_| true);
+        assert_eq!(programs(&resolved), vec!["./ok.sh"]);
+        assert!(resolved[0].script.as_ref().unwrap().sha256.is_some());
+    }
+}
@@ -0,0 +1,224 @@
+//! Escalation after denied commands in a session
+//!
+//! An agent that gets denied may try `sudo`, another tool or an encoded variant to do
+//! the same thing. Escalation rules (`settings.escalation`) look at what was denied
+//! earlier in the session, read back from the session log, and make decisions
+//! stricter once there were too many recent denials or a command resembles a recently
+//! denied one.
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::{parse_duration, Action, EscalationRule},
+    parser::{Effect, ParsedCommand},
+};
+
+/// Effects that make two commands alike (reads and writes are too common)
+const SIMILAR_EFFECTS: &[Effect] = &[Effect::Network, Effect::Destructive, Effect::Privilege];
+
+/// How far back denials count for escalation rules without a `window`, so that an
+/// escalation wears off instead of lasting the rest of the session
+const DEFAULT_WINDOW: &str = "15m";
+
+/// A command denied earlier in the session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    pub timestamp: DateTime<Utc>,
+    /// The input the command was denied in
+    pub input: String,
+    /// Program of the command that was denied
+    pub program: String,
+    pub subcommands: Vec<String>,
+    pub flags: Vec<String>,
+    /// Arguments of the command that was denied (paths, hosts, URLs, ...)
+    pub targets: Vec<String>,
+    pub effects: Vec<Effect>,
+}
+
+impl Denial {
+    /// Whether `command` looks like another try at the denied command: the same
+    /// program doing the same thing (a shared subcommand if the denied command had
+    /// any, otherwise a shared flag or target), or a shared network, destructive or
+    /// privilege effect on a shared target (e.g., `wget URL` after `curl URL`).
+    /// `git status` doesn't resemble a denied `git push --force`.
+    pub fn resembles(&self, command: &ParsedCommand) -> bool {
+        let name = |program: &str| {
+            Path::new(program)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        };
+        let shared_target = || command.args.iter().any(|arg| self.targets.contains(arg));
+        if name(&self.program) == name(&command.program) {
+            if !self.subcommands.is_empty() {
+                return command
+                    .subcommands
+                    .iter()
+                    .any(|subcommand| self.subcommands.contains(subcommand));
+            }
+            let nothing_to_compare = self.flags.is_empty() && self.targets.is_empty();
+            return nothing_to_compare
+                || self.flags.iter().any(|flag| command.flags.contains(flag))
+                || shared_target();
+        }
+
+        let same_effect = command
+            .effects
+            .iter()
+            .any(|effect| SIMILAR_EFFECTS.contains(effect) && self.effects.contains(effect));
+        same_effect && shared_target()
+    }
+}
+
+/// What happened earlier in a session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionHistory {
+    /// Denied commands, oldest first
+    pub denials: Vec<Denial>,
+}
+
+/// Actions (with messages) of the escalation rules that apply to `commands` at `now`
+pub(super) fn escalations(
+    rules: &[EscalationRule],
+    history: &SessionHistory,
+    commands: &[ParsedCommand],
+    now: DateTime<Utc>,
+) -> Vec<(Action, String)> {
+    let mut escalations = Vec::new();
+    for rule in rules {
+        // Invalid windows are reported when loading the config
+        let period = rule.window.as_deref().unwrap_or(DEFAULT_WINDOW);
+        let Ok(window) = parse_duration(period) else {
+            continue;
+        };
+        let recent: Vec<&Denial> = history
+            .denials
+            .iter()
+            .filter(|denial| now - denial.timestamp <= window)
+            .collect();
+        if recent.is_empty() || recent.len() < rule.denials {
+            continue;
+        }
+
+        let message = if rule.similar {
+            let similar = recent
+                .iter()
+                .rev()
+                .find(|denial| commands.iter().any(|command| denial.resembles(command)));
+            let Some(denial) = similar else {
+                continue;
+            };
+            format!(
+                "Escalated: resembles `{}`, denied earlier in this session",
+                denial.input
+            )
+        } else {
+            format!(
+                "Escalated: {} command(s) denied in this session in the last {}",
+                recent.len(),
+                period
+            )
+        };
+        let message = match &rule.message {
+            Some(custom) => custom.clone(),
+            None => format!(
+                "{}; ask the user instead of working around a denial",
+                message
+            ),
+        };
+        escalations.push((rule.action, message));
+    }
+    escalations
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn denial(input: &str, minutes_ago: i64, now: DateTime<Utc>) -> Denial {
+        let command = &ParsedCommand::parse_all(input).unwrap()[0];
+        Denial {
+            timestamp: now - Duration::minutes(minutes_ago),
+            input: input.to_string(),
+            program: command.program.clone(),
+            subcommands: command.subcommands.clone(),
+            flags: command.flags.iter().cloned().collect(),
+            targets: command.args.clone(),
+            effects: command.effects.clone(),
+        }
+    }
+
+    #[test]
+    fn test_escalations() {
+        let now = Utc::now();
+        let history = SessionHistory {
+            denials: vec![
+                denial("git push --force origin main", 60, now),
+                denial("curl https://example.com", 10, now),
+                denial("rm -rf /", 3, now),
+                denial("/bin/rm -rf ~", 1, now),
+            ],
+        };
+        let rule = |denials: usize, window: Option<&str>, similar: bool| EscalationRule {
+            denials,
+            window: window.map(str::to_string),
+            similar,
+            action: Action::Prompt,
+            message: None,
+        };
+        let apply = |rule: EscalationRule, input: &str| {
+            let commands = ParsedCommand::parse_all(input).unwrap();
+            escalations(&[rule], &history, &commands, now)
+        };
+
+        // Denials only count within the window (15 minutes by default)
+        assert_eq!(
+            apply(rule(3, None, false), "ls"),
+            vec![(
+                Action::Prompt,
+                "Escalated: 3 command(s) denied in this session in the last 15m; \
+                 ask the user instead of working around a denial"
+                    .to_string()
+            )]
+        );
+        assert!(apply(rule(4, None, false), "ls").is_empty());
+        assert_eq!(apply(rule(4, Some("2h"), false), "ls").len(), 1);
+        assert!(apply(rule(3, Some("5m"), false), "ls").is_empty());
+        assert_eq!(apply(rule(2, Some("5m"), false), "ls").len(), 1);
+
+        // Same program, or a shared network/destructive/privilege effect on the same
+        // target
+        assert!(apply(rule(1, None, true), "ls").is_empty());
+        assert!(apply(rule(1, None, true), "git clean -fdx").is_empty());
+        assert!(apply(rule(1, None, true), "kill 1").is_empty());
+        assert!(apply(rule(1, None, true), "wget https://example.org").is_empty());
+        assert_eq!(
+            apply(rule(1, None, true), "sudo rm -r build")[0].1,
+            "Escalated: resembles `/bin/rm -rf ~`, denied earlier in this session; \
+             ask the user instead of working around a denial"
+        );
+        assert_eq!(
+            apply(rule(1, None, true), "wget https://example.com").len(),
+            1
+        );
+        assert!(apply(rule(1, Some("5m"), true), "wget https://example.com").is_empty());
+
+        // The same program only resembles the denied command when doing the same thing
+        let recent_push = SessionHistory {
+            denials: vec![denial("git push --force", 1, now)],
+        };
+        let similar = |input: &str| {
+            let commands = ParsedCommand::parse_all(input).unwrap();
+            !escalations(&[rule(1, None, true)], &recent_push, &commands, now).is_empty()
+        };
+        assert!(!similar("git status"));
+        assert!(!similar("git log --oneline"));
+        assert!(similar("git push -f origin main"));
+        assert!(!similar("rm -rf build"));
+        // ...and only recently
+        assert!(apply(rule(1, None, true), "git push origin main").is_empty());
+    }
+}
@@ -1,33 +1,177 @@
-use super::matcher::RuleMatcher;
+use std::{fmt, path::Path};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use super::{
+    escalation::{self, SessionHistory},
+    explain::{CommandExplanation, Explanation, RuleEvaluation},
+    guidance::Guidance,
+    limits::{AppliedLimit, SessionUsage},
+    matcher::RuleMatcher,
+    rewrite,
+    risk::{self, RiskScore},
+};
 use crate::{
-    config::{Action, Config, Rule},
-    parser::ParsedCommand,
+    config::{Action, Config, Mode, Resolution, Rule, DEFAULT_SANDBOX},
+    parser::{Effect, ParsedCommand, ScriptStatus},
+    store,
 };
 
+/// `bashguard` subcommands an agent may not run: they approve prompted commands or
+/// change what is allowed
+const HUMAN_SUBCOMMANDS: &[&str] = &["approve", "grant", "revoke"];
+
+/// Writing `git` subcommands that change the repository, not the files they name
+const REPOSITORY_WRITES: &[&str] = &["add", "stage", "commit", "notes", "tag"];
+
 /// The decision made about a command
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
 pub enum Decision {
     Allow,
-    Deny { message: String },
-    Prompt { message: String },
+    Deny {
+        message: String,
+    },
+    Prompt {
+        message: String,
+    },
+    /// Run `command` instead: the rewritten input from `evaluate_input` and
+    /// `explain`, or the rewritten command itself when evaluating commands alone
+    Rewrite {
+        command: String,
+        message: String,
+    },
+    /// Allowed, with a message for the agent and the user
+    Warn {
+        message: String,
+    },
+    /// Allowed, with a message for the log and the user
+    Audit {
+        message: String,
+    },
+    /// Run the input under `bashguard sandbox-exec` with the named sandbox profile
+    /// (see `sandbox_command()`)
+    Sandbox {
+        policy: String,
+        message: String,
+    },
+}
+
+impl Decision {
+    /// Whether this decision is stricter than `other`
+    /// (Deny > Prompt > Sandbox > Rewrite > Warn > Audit > Allow)
+    fn is_stricter_than(&self, other: &Decision) -> bool {
+        self.severity() > other.severity()
+    }
+
+    fn severity(&self) -> u8 {
+        match self {
+            Decision::Allow => 0,
+            Decision::Audit { .. } => 1,
+            Decision::Warn { .. } => 2,
+            Decision::Rewrite { .. } => 3,
+            Decision::Sandbox { .. } => 4,
+            Decision::Prompt { .. } => 5,
+            Decision::Deny { .. } => 6,
+        }
+    }
+
+    /// Point the message at the command it was made for, so the part of a longer
+    /// input to change is clear
+    fn locate(self, command: &ParsedCommand) -> Decision {
+        let located = |message: String| {
+            let mut message = format!("{} [in `{}`", message, command.raw.trim());
+            if let Some(source) = &command.file_source {
+                message.push_str(&format!(" at {}", source));
+            }
+            message + "]"
+        };
+
+        match self {
+            Decision::Allow => Decision::Allow,
+            Decision::Deny { message } => Decision::Deny {
+                message: located(message),
+            },
+            Decision::Prompt { message } => Decision::Prompt {
+                message: located(message),
+            },
+            Decision::Rewrite { command, message } => Decision::Rewrite {
+                command,
+                message: located(message),
+            },
+            Decision::Warn { message } => Decision::Warn {
+                message: located(message),
+            },
+            Decision::Audit { message } => Decision::Audit {
+                message: located(message),
+            },
+            Decision::Sandbox { policy, message } => Decision::Sandbox {
+                policy,
+                message: located(message),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "ALLOW"),
+            Decision::Deny { message } => write!(f, "DENY: {}", message),
+            Decision::Prompt { message } => write!(f, "PROMPT: {}", message),
+            Decision::Rewrite { command, message } => {
+                write!(f, "REWRITE: {} (to `{}`)", message, command)
+            }
+            Decision::Warn { message } => write!(f, "WARN: {}", message),
+            Decision::Audit { message } => write!(f, "AUDIT: {}", message),
+            Decision::Sandbox { policy, message } => {
+                write!(f, "SANDBOX: {} (policy {})", message, policy)
+            }
+        }
+    }
 }
 
 /// Evaluates commands against rules
 pub struct Evaluator<'a> {
     config: &'a Config,
+    history: SessionHistory,
+    usage: SessionUsage,
 }
 
 impl<'a> Evaluator<'a> {
     pub fn new(config: &'a Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            history: SessionHistory::default(),
+            usage: SessionUsage::default(),
+        }
+    }
+
+    /// Use what happened earlier in the session for `settings.escalation`
+    pub fn with_history(mut self, history: SessionHistory) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Use the session's counters for limits
+    pub fn with_usage(mut self, usage: SessionUsage) -> Self {
+        self.usage = usage;
+        self
     }
 
     /// Evaluate all commands and return the strictest decision.
     ///
     /// - If any command is Deny, the overall result is Deny
     /// - If any command is Prompt (and none is Deny), the overall result is Prompt
+    /// - If any command is Rewrite (and none is stricter), the overall result is that
+    ///   command's Rewrite (see `evaluate_input()` for rewriting the whole input)
+    /// - Otherwise a Warn, then an Audit, is kept over Allow
     /// - Only if all commands are Allow, the overall result is Allow
     ///
+    /// In audit mode (`settings.mode`), anything stricter than Warn becomes an Audit,
+    /// except the denial of commands only a human may run.
+    ///
     /// This is the recommended method for security evaluation as it prevents bypass
     /// via pipelines or command chains.
     pub fn evaluate_all(&self, commands: &[ParsedCommand]) -> Decision {
@@ -36,21 +180,58 @@ impl<'a> Evaluator<'a> {
 
     /// Evaluate all commands and return the strictest decision along with the matched rule.
     pub fn evaluate_all_with_trace(&self, commands: &[ParsedCommand]) -> (Decision, Option<Rule>) {
+        let (decision, rule) = self.combine(commands);
+        (self.apply_mode(commands, decision), rule)
+    }
+
+    /// Strictest decision over all commands, with the risk band, escalation and limits
+    /// applied
+    fn combine(&self, commands: &[ParsedCommand]) -> (Decision, Option<Rule>) {
+        let (decision, mut matched_rule) = self.strictest(commands);
+        let risk = self.risk(commands);
+        let (decision, banded) = self.apply_risk(risk.as_ref(), decision);
+        let (decision, escalated) = self.apply_escalation(commands, decision);
+        let (decision, limited) = self.apply_limits(commands, decision);
+        if banded || escalated || limited {
+            matched_rule = None;
+        }
+        (decision, matched_rule)
+    }
+
+    /// Whether escalation after earlier denials, rather than the policy, decides
+    /// `commands` (such denials are logged so they don't escalate again)
+    pub fn escalated(&self, commands: &[ParsedCommand]) -> bool {
+        let (decision, _) = self.strictest(commands);
+        let (decision, _) = self.apply_risk(self.risk(commands).as_ref(), decision);
+        self.apply_escalation(commands, decision).1
+    }
+
+    /// Strictest decision of the commands' own decisions
+    fn strictest(&self, commands: &[ParsedCommand]) -> (Decision, Option<Rule>) {
         let mut strictest_decision = Decision::Allow;
         let mut matched_rule: Option<Rule> = None;
 
         for command in commands {
             let (decision, rule) = self.evaluate_single_with_trace(command);
 
-            // Update to strictest decision: Deny > Prompt > Allow
-            match (&strictest_decision, &decision) {
-                (Decision::Allow, Decision::Deny { .. })
-                | (Decision::Allow, Decision::Prompt { .. })
-                | (Decision::Prompt { .. }, Decision::Deny { .. }) => {
-                    strictest_decision = decision;
+            // Update to strictest decision
+            if decision.is_stricter_than(&strictest_decision) {
+                strictest_decision = if commands.len() > 1 {
+                    decision.locate(command)
+                } else {
+                    decision
+                };
+                matched_rule = rule;
+            } else if strictest_decision == Decision::Allow && decision == Decision::Allow {
+                // Report a rule for allowed inputs too, preferring a temporary grant
+                // so its use shows up in logs
+                let granted = |rule: &Option<Rule>| {
+                    rule.as_ref()
+                        .is_some_and(|rule| rule.origin.grant.is_some())
+                };
+                if matched_rule.is_none() || (granted(&rule) && !granted(&matched_rule)) {
                     matched_rule = rule;
                 }
-                _ => {}
             }
 
             // Short-circuit on Deny - can't get stricter
@@ -62,6 +243,64 @@ impl<'a> Evaluator<'a> {
         (strictest_decision, matched_rule)
     }
 
+    /// Evaluate all commands of `input` like `evaluate_all_with_trace()`, turning
+    /// rewrites of its commands into a rewrite of the whole input.
+    ///
+    /// The rewritten input is not evaluated again here: it has to be parsed the same
+    /// way as the original input first.
+    pub fn evaluate_input(
+        &self,
+        input: &str,
+        commands: &[ParsedCommand],
+    ) -> (Decision, Option<Rule>) {
+        let (decision, rule) = self.combine(commands);
+        let decision =
+            self.rewrite_input(input, commands, decision, |i| self.evaluate(&commands[i]));
+        (self.apply_mode(commands, decision), rule)
+    }
+
+    /// Guidance for the agent about the command that makes the input denied or
+    /// prompted, if any
+    pub fn guidance(&self, commands: &[ParsedCommand]) -> Option<Guidance> {
+        let (decision, command, rule) = self.strictest_command(commands)?;
+        if !matches!(decision, Decision::Deny { .. } | Decision::Prompt { .. }) {
+            return None;
+        }
+        // Rules that allowed the command didn't block it (a global policy did)
+        let rule = rule.filter(|rule| matches!(rule.action, Action::Deny | Action::Prompt));
+        let needs_approval = matches!(decision, Decision::Prompt { .. });
+        Some(Guidance::new(
+            command,
+            needs_approval,
+            rule.as_ref(),
+            self.rules(),
+        ))
+    }
+
+    /// The command whose own decision is the strictest (the first one if all are
+    /// allowed)
+    pub fn deciding_command<'c>(&self, commands: &'c [ParsedCommand]) -> Option<&'c ParsedCommand> {
+        self.strictest_command(commands)
+            .map(|(_, command, _)| command)
+    }
+
+    fn strictest_command<'c>(
+        &self,
+        commands: &'c [ParsedCommand],
+    ) -> Option<(Decision, &'c ParsedCommand, Option<Rule>)> {
+        let mut deciding: Option<(Decision, &ParsedCommand, Option<Rule>)> = None;
+        for command in commands {
+            let (decision, rule) = self.evaluate_single_with_trace(command);
+            if deciding
+                .as_ref()
+                .is_none_or(|(strictest, ..)| decision.is_stricter_than(strictest))
+            {
+                deciding = Some((decision, command, rule));
+            }
+        }
+        deciding
+    }
+
     /// Evaluate a single command and return the decision.
     ///
     /// **Note:** For security evaluation with pipelines or chains, use `evaluate_all()`.
@@ -76,88 +315,712 @@ impl<'a> Evaluator<'a> {
         self.evaluate_single_with_trace(command)
     }
 
+    /// Evaluate all commands and return a full trace of every candidate rule.
+    ///
+    /// The final decision is the same as `evaluate_all()`, but every command is
+    /// evaluated (no short-circuit on Deny) so the trace is complete.
+    pub fn explain(&self, input: &str, commands: &[ParsedCommand]) -> Explanation {
+        let mut decision = Decision::Allow;
+        let mut deciding_command = None;
+        let mut explained = Vec::new();
+
+        for (i, command) in commands.iter().enumerate() {
+            let explanation = self.explain_single(command);
+            if explanation.decision.is_stricter_than(&decision) {
+                decision = if commands.len() > 1 {
+                    explanation.decision.clone().locate(command)
+                } else {
+                    explanation.decision.clone()
+                };
+                deciding_command = Some(i);
+            }
+            explained.push(explanation);
+        }
+
+        let risk = self.risk(commands);
+        let (decision, banded) = self.apply_risk(risk.as_ref(), decision);
+        let (decision, escalated) = self.apply_escalation(commands, decision);
+        let (decision, limited) = self.apply_limits(commands, decision);
+        if banded || escalated || limited {
+            deciding_command = None;
+        }
+        let decision =
+            self.rewrite_input(input, commands, decision, |i| explained[i].decision.clone());
+        let decision = self.apply_mode(commands, decision);
+
+        Explanation {
+            input: input.to_string(),
+            resolution: self.config.settings.resolution,
+            commands: explained,
+            deciding_command,
+            risk,
+            decision,
+        }
+    }
+
+    /// Turn a combined Rewrite decision into a rewrite of `input`, replacing the text
+    /// of every command whose own decision (`decide` by index) is a rewrite
+    fn rewrite_input(
+        &self,
+        input: &str,
+        commands: &[ParsedCommand],
+        decision: Decision,
+        decide: impl Fn(usize) -> Decision,
+    ) -> Decision {
+        let Decision::Rewrite { message, .. } = decision else {
+            return decision;
+        };
+
+        let mut edits = Vec::new();
+        for (i, command) in commands.iter().enumerate() {
+            let Decision::Rewrite {
+                command: replacement,
+                ..
+            } = decide(i)
+            else {
+                continue;
+            };
+            // Commands from scripts, recipes or another command's arguments have no
+            // text of their own in the input
+            let span = command
+                .span
+                .filter(|span| input.get(span.start..span.end) == Some(command.raw.as_str()));
+            match span {
+                Some(span) => edits.push((span, replacement)),
+                None => {
+                    return Decision::Prompt {
+                        message: format!(
+                            "Cannot rewrite `{}` in place: {}",
+                            command.raw.trim(),
+                            message
+                        ),
+                    }
+                }
+            }
+        }
+
+        match rewrite::splice(input, edits) {
+            Some(command) => Decision::Rewrite { command, message },
+            None => Decision::Prompt {
+                message: format!("Cannot rewrite nested commands: {}", message),
+            },
+        }
+    }
+
+    /// In audit mode, turn decisions that would block or change the command into an
+    /// Audit saying what would have happened
+    fn apply_mode(&self, commands: &[ParsedCommand], decision: Decision) -> Decision {
+        // Auditing never lets the agent do what only a human may
+        let human_only = commands.iter().any(|command| {
+            human_subcommand(command).is_some() || may_change_bashguard_files(command)
+        });
+        if self.config.settings.mode != Mode::Audit || human_only {
+            return decision;
+        }
+
+        let message = match decision {
+            Decision::Deny { message } => format!("would deny: {}", message),
+            Decision::Prompt { message } => format!("would prompt: {}", message),
+            Decision::Rewrite { command, message } => {
+                format!("would rewrite to `{}`: {}", command, message)
+            }
+            Decision::Sandbox { policy, message } => {
+                format!("would sandbox (policy {}): {}", policy, message)
+            }
+            decision => return decision,
+        };
+        Decision::Audit {
+            message: format!("Audit mode, {}", message),
+        }
+    }
+
+    /// Risk score of the whole input, or `None` when risk scoring is disabled
+    pub fn risk(&self, commands: &[ParsedCommand]) -> Option<RiskScore> {
+        let settings = &self.config.settings.risk;
+        settings.enabled.then(|| {
+            let workspace = std::env::current_dir().ok();
+            risk::score_input(commands, &settings.weights, workspace.as_deref())
+        })
+    }
+
+    /// Risk score of a single command, or `None` when risk scoring is disabled
+    pub fn command_risk(&self, command: &ParsedCommand) -> Option<RiskScore> {
+        self.risk(std::slice::from_ref(command))
+    }
+
+    /// Apply the band the input's risk score falls into when it is stricter than
+    /// the rule decision (returning `true`), and show the score in prompt messages
+    fn apply_risk(&self, risk: Option<&RiskScore>, decision: Decision) -> (Decision, bool) {
+        let Some(risk) = risk else {
+            return (decision, false);
+        };
+
+        if let Some(action) = risk::band_action(risk.score, &self.config.settings.risk.bands) {
+            let banded = Self::action_decision(action, &format!("Risk score {}", risk));
+            if banded.is_stricter_than(&decision) {
+                return (banded, true);
+            }
+        }
+
+        match decision {
+            Decision::Prompt { message } if risk.score > 0 => (
+                Decision::Prompt {
+                    message: format!("{} [risk {}]", message, risk),
+                },
+                false,
+            ),
+            decision => (decision, false),
+        }
+    }
+
+    /// Make the decision stricter according to `settings.escalation` and the session
+    /// history, returning whether it was
+    fn apply_escalation(&self, commands: &[ParsedCommand], decision: Decision) -> (Decision, bool) {
+        let rules = &self.config.settings.escalation;
+        let mut decision = decision;
+        let mut escalated = false;
+        for (action, message) in escalation::escalations(rules, &self.history, commands, Utc::now())
+        {
+            let escalation = Self::action_decision(action, &message);
+            if escalation.is_stricter_than(&decision) {
+                decision = escalation;
+                escalated = true;
+            }
+        }
+        (decision, escalated)
+    }
+
+    /// Whether any session limit or rule limit is configured
+    pub fn has_limits(&self) -> bool {
+        !self.config.settings.limits.is_empty() || self.rules().any(|r| r.limit.is_some())
+    }
+
+    /// Limits counting `commands`: session limits with a matching command, and limits
+    /// of the rules deciding a command, each with how many of the commands it counts
+    pub fn limits(&self, commands: &[ParsedCommand]) -> Vec<AppliedLimit> {
+        let mut limits: Vec<AppliedLimit> = Vec::new();
+        if !self.has_limits() {
+            return limits;
+        }
+
+        let settings_limits = self.config.settings.limits.iter().enumerate();
+        for (index, session_limit) in settings_limits {
+            let matched = commands
+                .iter()
+                .filter(|command| {
+                    RuleMatcher::check(&session_limit.condition, command)
+                        .iter()
+                        .all(|c| c.passed)
+                })
+                .count();
+            if matched > 0 {
+                limits.push(AppliedLimit {
+                    counter: session_limit
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| format!("settings.limits[{}]", index)),
+                    limit: session_limit.limit.clone(),
+                    matched,
+                });
+            }
+        }
+
+        for command in commands {
+            let (_, rule) = self.evaluate_single_with_trace(command);
+            let Some((rule, limit)) = rule.and_then(|rule| Some((rule.id(), rule.limit?))) else {
+                continue;
+            };
+            let counter = format!("rule:{}", rule);
+            match limits.iter_mut().find(|applied| applied.counter == counter) {
+                Some(applied) => applied.matched += 1,
+                None => limits.push(AppliedLimit {
+                    counter,
+                    limit,
+                    matched: 1,
+                }),
+            }
+        }
+        limits
+    }
+
+    /// Make the decision stricter for limits the input would take the session past,
+    /// returning whether it was
+    fn apply_limits(&self, commands: &[ParsedCommand], decision: Decision) -> (Decision, bool) {
+        let now = Utc::now();
+        let mut decision = decision;
+        let mut limited = false;
+        for applied in self.limits(commands) {
+            let Some((action, message)) = applied.reached(&self.usage, now) else {
+                continue;
+            };
+            let limit = Self::action_decision(action, &message);
+            if limit.is_stricter_than(&decision) {
+                decision = limit;
+                limited = true;
+            }
+        }
+        (decision, limited)
+    }
+
+    fn explain_single(&self, command: &ParsedCommand) -> CommandExplanation {
+        let first_match = self.config.settings.resolution == Resolution::FirstMatch;
+        let mut candidates = Vec::new();
+        let mut rules_checked = 0;
+        let mut matches = Vec::new();
+
+        for rule in self.rules() {
+            rules_checked += 1;
+            let conditions = RuleMatcher::check(&rule.condition, command);
+            let is_match = conditions.iter().all(|c| c.passed);
+            let program_failed = conditions
+                .iter()
+                .any(|c| c.condition == "program" && !c.passed);
+
+            if !program_failed {
+                candidates.push(RuleEvaluation {
+                    id: rule.id(),
+                    name: rule.name.clone(),
+                    origin: rule.origin.clone(),
+                    action: rule.action,
+                    priority: rule.priority,
+                    specificity: RuleMatcher::specificity(rule),
+                    matched: is_match,
+                    conditions,
+                });
+            }
+
+            if is_match {
+                matches.push(rule);
+                // A grant only decides if no other rule denies the command
+                if first_match && rule.origin.grant.is_none() {
+                    break;
+                }
+            }
+        }
+
+        let mut flags: Vec<String> = command.flags.iter().cloned().collect();
+        flags.sort();
+
+        let rule = self.decide(matches.into_iter());
+        let (decision, matched_rule) = match rule {
+            Some(rule) => (self.make_decision(rule, command), Some(rule.id())),
+            None => (self.default_decision(), None),
+        };
+        let mut notes = Vec::new();
+        let decision = self.apply_policies(command, rule, decision, &mut notes);
+
+        CommandExplanation {
+            raw: command.raw.clone(),
+            span: command.span,
+            nesting: command.nesting.clone(),
+            control: command.control.clone(),
+            program: command.program.clone(),
+            subcommands: command.subcommands.clone(),
+            flags,
+            args: command.args.clone(),
+            has_expansion: command.has_expansion,
+            has_substitution: command.has_substitution,
+            dynamic_program: command.dynamic_program,
+            dynamic_args: command.dynamic_args.clone(),
+            obfuscation: command.obfuscation.clone(),
+            effects: command.effects.clone(),
+            risk: self.command_risk(command),
+            has_redirect: command.has_redirect,
+            is_piped: command.is_piped,
+            context: command.context.clone(),
+            embedded_in: command.embedded_in.clone(),
+            file_source: command.file_source.clone(),
+            script: command.script.clone(),
+            candidates,
+            rules_checked,
+            matched_rule,
+            decision,
+            notes,
+        }
+    }
+
+    /// All rules in evaluation order: temporary grants first, then inline rules, then
+    /// profiles in listed order.
+    fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.config
+            .grants
+            .iter()
+            .chain(self.config.rules.iter())
+            .chain(
+                self.config
+                    .loaded_profiles
+                    .iter()
+                    .flat_map(|profile| profile.rules.iter()),
+            )
+    }
+
+    /// Pick the winning rule among matches (given in evaluation order) according to
+    /// the configured resolution strategy.
+    fn resolve<'r>(&self, mut matches: impl Iterator<Item = &'r Rule>) -> Option<&'r Rule> {
+        let resolution = self.config.settings.resolution;
+        if resolution == Resolution::FirstMatch {
+            return matches.next();
+        }
+
+        let key = |rule: &Rule| match resolution {
+            Resolution::FirstMatch | Resolution::Priority => (rule.priority as i64, 0),
+            Resolution::Specificity => {
+                (rule.priority as i64, RuleMatcher::specificity(rule) as i64)
+            }
+            Resolution::DenyWins => (action_severity(rule.action).into(), rule.priority as i64),
+        };
+
+        // Keep the earliest rule on ties
+        matches.reduce(|best, rule| if key(rule) > key(best) { rule } else { best })
+    }
+
+    /// Pick the deciding rule among matches: a matching grant decides unless the other
+    /// rules deny the command, whatever the resolution strategy, so a grant can allow
+    /// what would be prompted for but never what is denied.
+    fn decide<'r>(&self, matches: impl Iterator<Item = &'r Rule>) -> Option<&'r Rule> {
+        let (grants, rules): (Vec<&Rule>, Vec<&Rule>) =
+            matches.partition(|rule| rule.origin.grant.is_some());
+        let rule = self.resolve(rules.into_iter());
+        match grants.first() {
+            Some(grant) if rule.is_none_or(|rule| rule.action != Action::Deny) => Some(grant),
+            _ => rule,
+        }
+    }
+
     /// Internal method to evaluate a single command.
     fn evaluate_single_with_trace(&self, command: &ParsedCommand) -> (Decision, Option<Rule>) {
-        // First, check custom rules from config (highest priority)
-        for rule in &self.config.rules {
-            if RuleMatcher::matches(rule, command) {
-                return (Self::make_decision(rule), Some(rule.clone()));
+        // Grants come first, then custom rules from config, then profile rules (in
+        // order of profiles)
+        let matches = self
+            .rules()
+            .filter(|rule| RuleMatcher::matches(rule, command));
+
+        let (decision, rule) = match self.decide(matches) {
+            Some(rule) => (self.make_decision(rule, command), Some(rule.clone())),
+            // Finally, use default action
+            None => (self.default_decision(), None),
+        };
+
+        (
+            self.apply_policies(command, rule.as_ref(), decision, &mut Vec::new()),
+            rule,
+        )
+    }
+
+    /// Apply global `settings` policies on top of the rule decision, recording a note
+    /// for each policy that changed it.
+    fn apply_policies(
+        &self,
+        command: &ParsedCommand,
+        rule: Option<&Rule>,
+        decision: Decision,
+        notes: &mut Vec<String>,
+    ) -> Decision {
+        let settings = &self.config.settings;
+        let mut decision = decision;
+
+        // Dynamic content can't be statically analyzed, so don't silently allow it
+        if let Some(action) = settings.dynamic_content_action {
+            if command.has_expansion || command.has_substitution {
+                let policy = Self::action_decision(
+                    action,
+                    "Command contains expansion or substitution that cannot be statically analyzed",
+                );
+                if policy.is_stricter_than(&decision) {
+                    notes.push(format!(
+                        "dynamic_content_action: contains {}",
+                        match (command.has_expansion, command.has_substitution) {
+                            (true, true) => "expansion and substitution",
+                            (true, false) => "expansion",
+                            _ => "substitution",
+                        }
+                    ));
+                    decision = policy;
+                }
+            }
+        }
+
+        // A program only known at runtime could be anything, unless the rule says
+        // how to handle it
+        let handles_dynamic = rule.is_some_and(|r| r.condition.dynamic_program.is_some());
+        if command.dynamic_program && !handles_dynamic {
+            let policy = Self::action_decision(
+                settings.dynamic_program_action,
+                &format!(
+                    "Program name {:?} is only known at runtime",
+                    command.program
+                ),
+            );
+            if policy.is_stricter_than(&decision) {
+                notes.push(format!(
+                    "dynamic_program_action: program {:?} is dynamic",
+                    command.program
+                ));
+                decision = policy;
+            }
+        }
+
+        // Obfuscated commands are never silently allowed, unless the rule says how to
+        // handle them
+        let handles_obfuscation = rule.is_some_and(|r| r.condition.obfuscation.is_some());
+        if !command.obfuscation.is_empty() && !handles_obfuscation {
+            let techniques: Vec<&str> = command.obfuscation.iter().map(|o| o.name()).collect();
+            let policy = Self::action_decision(
+                settings.obfuscation_action,
+                &format!("Command uses obfuscation: {}", techniques.join(", ")),
+            );
+            if policy.is_stricter_than(&decision) {
+                notes.push(format!(
+                    "obfuscation_action: uses {}",
+                    techniques.join(", ")
+                ));
+                decision = policy;
             }
         }
 
-        // Then, check profile rules (in order of profiles)
-        for profile in &self.config.loaded_profiles {
-            for rule in &profile.rules {
-                if RuleMatcher::matches(rule, command) {
-                    return (Self::make_decision(rule), Some(rule.clone()));
+        // Scripts whose commands weren't evaluated can't be trusted by their call alone
+        if let Some(script) = &command.script {
+            if script.status != ScriptStatus::Resolved {
+                let policy = Self::action_decision(
+                    settings.unresolved_script_action,
+                    &format!(
+                        "Script {} cannot be evaluated: {}",
+                        script.path.display(),
+                        script.status
+                    ),
+                );
+                if policy.is_stricter_than(&decision) {
+                    notes.push(format!(
+                        "unresolved_script_action: script {}",
+                        script.status
+                    ));
+                    decision = policy;
                 }
             }
         }
 
-        // Finally, use default action
-        let decision = match self.config.settings.default_action {
+        // Approving prompts and granting access is up to a human, never the agent
+        if let Some(subcommand) = human_subcommand(command) {
+            notes.push(format!("`bashguard {}` is for humans only", subcommand));
+            decision = Decision::Deny {
+                message: format!("Only a human can run `bashguard {}`", subcommand),
+            };
+        }
+
+        // ...and so is changing bashguard's config, logs and state
+        if may_change_bashguard_files(command) {
+            notes.push("changes bashguard's files, which is for humans only".to_string());
+            decision = Decision::Deny {
+                message: "Only a human can change bashguard's configuration and state".to_string(),
+            };
+        }
+
+        decision
+    }
+
+    /// Whether the script run by `command` is pinned: the rule deciding the command
+    /// allows it and names the script's hash, so its content need not be evaluated.
+    pub fn trusts_script(&self, command: &ParsedCommand) -> bool {
+        let matches = self
+            .rules()
+            .filter(|rule| RuleMatcher::matches(rule, command));
+        self.resolve(matches).is_some_and(|rule| {
+            rule.action == Action::Allow && rule.condition.script_sha256.is_some()
+        })
+    }
+
+    fn action_decision(action: Action, message: &str) -> Decision {
+        match action {
             Action::Allow => Decision::Allow,
             Action::Deny => Decision::Deny {
-                message: "Blocked by default policy".to_string(),
+                message: message.to_string(),
             },
             Action::Prompt => Decision::Prompt {
-                message: "No matching rule found".to_string(),
+                message: message.to_string(),
             },
-        };
+            // Only rules have a template to rewrite with
+            Action::Rewrite => Decision::Prompt {
+                message: format!("{} (rewrite needs a rule template)", message),
+            },
+            Action::Warn => Decision::Warn {
+                message: message.to_string(),
+            },
+            Action::Audit => Decision::Audit {
+                message: message.to_string(),
+            },
+            Action::Sandbox => Decision::Sandbox {
+                policy: DEFAULT_SANDBOX.to_string(),
+                message: message.to_string(),
+            },
+        }
+    }
 
-        (decision, None)
+    fn default_decision(&self) -> Decision {
+        let message = match self.config.settings.default_action {
+            Action::Deny => "Blocked by default policy",
+            _ => "No matching rule found",
+        };
+        Self::action_decision(self.config.settings.default_action, message)
     }
 
-    fn make_decision(rule: &Rule) -> Decision {
+    fn make_decision(&self, rule: &Rule, command: &ParsedCommand) -> Decision {
+        // Include the rule id so a blocked command can be traced back to its rule
+        let message = |default: &str| {
+            format!(
+                "{} (rule: {})",
+                rule.message.as_deref().unwrap_or(default),
+                rule.id()
+            )
+        };
+
         match rule.action {
             Action::Allow => Decision::Allow,
             Action::Deny => Decision::Deny {
-                message: rule
-                    .message
-                    .clone()
-                    .unwrap_or_else(|| "Blocked by rule".to_string()),
+                message: message("Blocked by rule"),
             },
             Action::Prompt => Decision::Prompt {
-                message: rule
-                    .message
-                    .clone()
-                    .unwrap_or_else(|| "Requires confirmation".to_string()),
+                message: message("Requires confirmation"),
+            },
+            Action::Rewrite => match &rule.rewrite {
+                Some(template) => match rewrite::render(template, command) {
+                    Some(rewritten) => Decision::Rewrite {
+                        command: rewritten,
+                        message: message("Rewritten by rule"),
+                    },
+                    None => Decision::Prompt {
+                        message: message(
+                            "Cannot rewrite arguments the shell expands when the command runs",
+                        ),
+                    },
+                },
+                None => Decision::Prompt {
+                    message: message("Rewrite rule has no `rewrite` template"),
+                },
+            },
+            Action::Warn => Decision::Warn {
+                message: message("Allowed with a warning"),
             },
+            Action::Audit => Decision::Audit {
+                message: message("Allowed for audit"),
+            },
+            Action::Sandbox => {
+                let policy = rule.sandbox.as_deref().unwrap_or(DEFAULT_SANDBOX);
+                if self.config.sandbox(policy).is_some() {
+                    Decision::Sandbox {
+                        policy: policy.to_string(),
+                        message: message("Runs in a sandbox"),
+                    }
+                } else {
+                    Decision::Prompt {
+                        message: message(&format!("Unknown sandbox profile {:?}", policy)),
+                    }
+                }
+            }
         }
     }
 }
 
+/// The `bashguard` subcommand run by `command`, if only a human may run it
+fn human_subcommand(command: &ParsedCommand) -> Option<&str> {
+    if Path::new(&command.program).file_name()? != "bashguard" {
+        return None;
+    }
+    command
+        .args
+        .first()
+        .map(String::as_str)
+        .filter(|arg| HUMAN_SUBCOMMANDS.contains(arg))
+}
+
+/// Whether `command` may change bashguard's files (`.bashguard/` in the workspace,
+/// or the state directory with grants and approvals): a command writing to them
+/// through a redirect, one whose known effects write or destroy given them as
+/// arguments, or a `cd` into them (after which relative paths can't be resolved)
+fn may_change_bashguard_files(command: &ParsedCommand) -> bool {
+    let Ok(cwd) = std::env::current_dir() else {
+        return false;
+    };
+    let protected = [Some(cwd.join(".bashguard")), store::state_root()];
+    let is_protected = |word: &String| {
+        let path = risk::resolve_path(word, &cwd).unwrap_or_else(|| cwd.join(word));
+        protected.iter().flatten().any(|dir| path.starts_with(dir))
+    };
+
+    let program = Path::new(&command.program)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let writes_args = command
+        .program_effects()
+        .iter()
+        .any(|effect| matches!(effect, Effect::Write | Effect::Destructive))
+        && !(program == "git"
+            && command
+                .subcommands
+                .first()
+                .is_some_and(|sub| REPOSITORY_WRITES.contains(&sub.as_str())));
+    let moves_in = matches!(program, "cd" | "pushd");
+
+    command.written_files.iter().any(is_protected)
+        || ((writes_args || moves_in) && command.args.iter().any(is_protected))
+}
+
+/// Severity of the decisions a rule with `action` makes, ordered like [`Decision`]s
+fn action_severity(action: Action) -> u8 {
+    let message = String::new();
+    let decision = match action {
+        Action::Allow => Decision::Allow,
+        Action::Deny => Decision::Deny { message },
+        Action::Prompt => Decision::Prompt { message },
+        Action::Rewrite => Decision::Rewrite {
+            command: String::new(),
+            message,
+        },
+        Action::Warn => Decision::Warn { message },
+        Action::Audit => Decision::Audit { message },
+        Action::Sandbox => Decision::Sandbox {
+            policy: String::new(),
+            message,
+        },
+    };
+    decision.severity()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Profile, ProfileMetadata, ProfilesConfig, Settings};
+    use crate::config::{
+        Condition, EscalationRule, Limit, Profile, ProfileMetadata, ProfilesConfig, RiskBand,
+        RuleOrigin, SandboxProfile, SessionLimit, Settings,
+    };
 
     fn make_config_with_rules(rules: Vec<Rule>) -> Config {
-        Config {
+        let mut config = Config {
             settings: Settings::default(),
             profiles: ProfilesConfig {
                 builtins: vec![],
                 custom: vec![],
             },
             rules,
+            sandboxes: Default::default(),
+            grants: vec![],
             loaded_profiles: vec![],
             available_profiles: vec![],
-        }
+        };
+        config.assign_rule_origins(None);
+        config
     }
 
     #[test]
     fn test_allow_rule() {
         let config = make_config_with_rules(vec![Rule {
-            program: Some("git".to_string()),
-            subcommands: vec!["status".to_string()],
-            subcommands_exact: false,
-            args_match: None,
-            args_regex: None,
-            flags_present: vec![],
-            flags_absent: vec![],
-            working_dir: None,
+            condition: Condition {
+                program: Some("git".to_string()),
+                subcommands: vec!["status".to_string()],
+                ..Default::default()
+            },
             action: Action::Allow,
-            message: None,
+            ..Default::default()
         }]);
 
         let cmds = ParsedCommand::parse_all("git status").unwrap();
@@ -170,16 +1033,14 @@ mod tests {
     #[test]
     fn test_deny_rule() {
         let config = make_config_with_rules(vec![Rule {
-            program: Some("git".to_string()),
-            subcommands: vec!["push".to_string()],
-            subcommands_exact: false,
-            args_match: None,
-            args_regex: None,
-            flags_present: vec![],
-            flags_absent: vec![],
-            working_dir: None,
+            condition: Condition {
+                program: Some("git".to_string()),
+                subcommands: vec!["push".to_string()],
+                ..Default::default()
+            },
             action: Action::Deny,
             message: Some("Push not allowed".to_string()),
+            ..Default::default()
         }]);
 
         let cmds = ParsedCommand::parse_all("git push origin main").unwrap();
@@ -189,40 +1050,42 @@ mod tests {
         assert_eq!(
             decision,
             Decision::Deny {
-                message: "Push not allowed".to_string()
+                message: "Push not allowed (rule: inline#0)".to_string()
             }
         );
     }
 
     #[test]
     fn test_profile_rules() {
-        let config = Config {
+        let mut config = Config {
             settings: Settings::default(),
             profiles: ProfilesConfig {
                 builtins: vec!["test".to_string()],
                 custom: vec![],
             },
             rules: vec![],
+            sandboxes: Default::default(),
+            grants: vec![],
             loaded_profiles: vec![Profile {
                 profile: ProfileMetadata {
                     name: "test".to_string(),
                     description: None,
                 },
                 rules: vec![Rule {
-                    program: Some("rm".to_string()),
-                    subcommands: vec![],
-                    subcommands_exact: false,
-                    args_match: None,
-                    args_regex: None,
-                    flags_present: vec!["-r".to_string()],
-                    flags_absent: vec![],
-                    working_dir: None,
+                    condition: Condition {
+                        program: Some("rm".to_string()),
+                        flags_present: vec!["-r".to_string()],
+                        ..Default::default()
+                    },
                     action: Action::Deny,
                     message: Some("Recursive delete blocked".to_string()),
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
             available_profiles: vec![],
         };
+        config.assign_rule_origins(None);
 
         let cmds = ParsedCommand::parse_all("rm -rf /tmp/foo").unwrap();
         let evaluator = Evaluator::new(&config);
@@ -231,7 +1094,7 @@ mod tests {
         assert_eq!(
             decision,
             Decision::Deny {
-                message: "Recursive delete blocked".to_string()
+                message: "Recursive delete blocked (rule: test#0)".to_string()
             }
         );
     }
@@ -245,34 +1108,32 @@ mod tests {
                 custom: vec![],
             },
             rules: vec![Rule {
-                program: Some("git".to_string()),
-                subcommands: vec!["push".to_string()],
-                subcommands_exact: false,
-                args_match: None,
-                args_regex: None,
-                flags_present: vec![],
-                flags_absent: vec![],
-                working_dir: None,
+                condition: Condition {
+                    program: Some("git".to_string()),
+                    subcommands: vec!["push".to_string()],
+                    ..Default::default()
+                },
                 action: Action::Allow,
-                message: None,
+                ..Default::default()
             }],
+            sandboxes: Default::default(),
+            grants: vec![],
             loaded_profiles: vec![Profile {
                 profile: ProfileMetadata {
                     name: "test".to_string(),
                     description: None,
                 },
                 rules: vec![Rule {
-                    program: Some("git".to_string()),
-                    subcommands: vec!["push".to_string()],
-                    subcommands_exact: false,
-                    args_match: None,
-                    args_regex: None,
-                    flags_present: vec![],
-                    flags_absent: vec![],
-                    working_dir: None,
+                    condition: Condition {
+                        program: Some("git".to_string()),
+                        subcommands: vec!["push".to_string()],
+                        ..Default::default()
+                    },
                     action: Action::Deny,
                     message: Some("Blocked by profile".to_string()),
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
             available_profiles: vec![],
         };
@@ -290,13 +1151,15 @@ mod tests {
         let config = Config {
             settings: Settings {
                 default_action: Action::Deny,
-                log_decisions: false,
+                ..Default::default()
             },
             profiles: ProfilesConfig {
                 builtins: vec![],
                 custom: vec![],
             },
             rules: vec![],
+            sandboxes: Default::default(),
+            grants: vec![],
             loaded_profiles: vec![],
             available_profiles: vec![],
         };
@@ -318,28 +1181,21 @@ mod tests {
         // Test that a denied command in a pipeline causes overall deny
         let config = make_config_with_rules(vec![
             Rule {
-                program: Some("ls".to_string()),
-                subcommands: vec![],
-                subcommands_exact: false,
-                args_match: None,
-                args_regex: None,
-                flags_present: vec![],
-                flags_absent: vec![],
-                working_dir: None,
+                condition: Condition {
+                    program: Some("ls".to_string()),
+                    ..Default::default()
+                },
                 action: Action::Allow,
-                message: None,
+                ..Default::default()
             },
             Rule {
-                program: Some("rm".to_string()),
-                subcommands: vec![],
-                subcommands_exact: false,
-                args_match: None,
-                args_regex: None,
-                flags_present: vec![],
-                flags_absent: vec![],
-                working_dir: None,
+                condition: Condition {
+                    program: Some("rm".to_string()),
+                    ..Default::default()
+                },
                 action: Action::Deny,
                 message: Some("rm blocked".to_string()),
+                ..Default::default()
             },
         ]);
 
@@ -352,7 +1208,7 @@ mod tests {
         assert_eq!(
             decision,
             Decision::Deny {
-                message: "rm blocked".to_string()
+                message: "rm blocked (rule: inline#1) [in `rm -rf`]".to_string()
             }
         );
     }
@@ -361,16 +1217,13 @@ mod tests {
     fn test_chain_deny_blocks_all() {
         // Test that deny in && chain causes overall deny
         let config = make_config_with_rules(vec![Rule {
-            program: Some("dangerous".to_string()),
-            subcommands: vec![],
-            subcommands_exact: false,
-            args_match: None,
-            args_regex: None,
-            flags_present: vec![],
-            flags_absent: vec![],
-            working_dir: None,
+            condition: Condition {
+                program: Some("dangerous".to_string()),
+                ..Default::default()
+            },
             action: Action::Deny,
             message: Some("dangerous blocked".to_string()),
+            ..Default::default()
         }]);
 
         let cmds = ParsedCommand::parse_all("safe-cmd && dangerous").unwrap();
@@ -380,8 +1233,935 @@ mod tests {
         assert_eq!(
             decision,
             Decision::Deny {
-                message: "dangerous blocked".to_string()
+                message: "dangerous blocked (rule: inline#0) [in `dangerous`]".to_string()
             }
         );
     }
+
+    #[test]
+    fn test_explain_trace() {
+        let mut config = Config {
+            settings: Settings::default(),
+            profiles: ProfilesConfig {
+                builtins: vec!["test".to_string()],
+                custom: vec![],
+            },
+            rules: vec![Rule {
+                id: Some("git-status".to_string()),
+                condition: Condition {
+                    program: Some("git".to_string()),
+                    subcommands: vec!["status".to_string()],
+                    ..Default::default()
+                },
+                action: Action::Allow,
+                ..Default::default()
+            }],
+            sandboxes: Default::default(),
+            grants: vec![],
+            loaded_profiles: vec![Profile {
+                profile: ProfileMetadata {
+                    name: "test".to_string(),
+                    description: None,
+                },
+                rules: vec![Rule {
+                    condition: Condition {
+                        program: Some("git".to_string()),
+                        subcommands: vec!["push".to_string()],
+                        ..Default::default()
+                    },
+                    action: Action::Deny,
+                    message: Some("Push blocked".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            available_profiles: vec![],
+        };
+        config.assign_rule_origins(None);
+
+        let input = "ls && git push";
+        let cmds = ParsedCommand::parse_all(input).unwrap();
+        let evaluator = Evaluator::new(&config);
+        let explanation = evaluator.explain(input, &cmds);
+
+        assert_eq!(explanation.decision, evaluator.evaluate_all(&cmds));
+        assert_eq!(explanation.deciding_command, Some(1));
+
+        // ls: no candidates share its program, falls through to default
+        assert!(explanation.commands[0].candidates.is_empty());
+        assert_eq!(explanation.commands[0].rules_checked, 2);
+        assert_eq!(explanation.commands[0].matched_rule, None);
+
+        // git push: inline rule fails on subcommands, profile rule matches
+        let git = &explanation.commands[1];
+        assert_eq!(git.candidates.len(), 2);
+        assert!(!git.candidates[0].matched);
+        assert_eq!(git.candidates[0].id, "git-status");
+        assert_eq!(git.candidates[0].origin.profile, None);
+        assert!(git.candidates[0]
+            .conditions
+            .iter()
+            .any(|c| c.condition == "subcommands" && !c.passed));
+        assert!(git.candidates[1].matched);
+        assert_eq!(git.matched_rule, Some("test#0".to_string()));
+    }
+
+    fn make_composed_config(resolution: Resolution) -> Config {
+        let mut config = Config {
+            settings: Settings {
+                resolution,
+                ..Default::default()
+            },
+            sandboxes: Default::default(),
+            grants: vec![],
+            loaded_profiles: vec![
+                Profile {
+                    profile: ProfileMetadata {
+                        name: "broad".to_string(),
+                        description: None,
+                    },
+                    rules: vec![Rule {
+                        condition: Condition {
+                            program: Some("git".to_string()),
+                            ..Default::default()
+                        },
+                        action: Action::Allow,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                Profile {
+                    profile: ProfileMetadata {
+                        name: "specific".to_string(),
+                        description: None,
+                    },
+                    rules: vec![Rule {
+                        condition: Condition {
+                            program: Some("git".to_string()),
+                            subcommands: vec!["push".to_string()],
+                            flags_present: vec!["--force".to_string()],
+                            ..Default::default()
+                        },
+                        action: Action::Deny,
+                        message: Some("No force push".to_string()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        config.assign_rule_origins(None);
+        config
+    }
+
+    #[test]
+    fn test_resolution_strategies() {
+        let cmds = ParsedCommand::parse_all("git push --force").unwrap();
+        let denied = Decision::Deny {
+            message: "No force push (rule: specific#0)".to_string(),
+        };
+
+        // Broad allow in the earlier profile eclipses the specific deny
+        let config = make_composed_config(Resolution::FirstMatch);
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+
+        let config = make_composed_config(Resolution::Specificity);
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), denied);
+
+        let config = make_composed_config(Resolution::DenyWins);
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), denied);
+
+        // Equal priorities fall back to order
+        let mut config = make_composed_config(Resolution::Priority);
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+        config.loaded_profiles[1].rules[0].priority = 10;
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), denied);
+
+        // Priority outranks specificity
+        let mut config = make_composed_config(Resolution::Specificity);
+        config.loaded_profiles[0].rules[0].priority = 10;
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+
+        // Explain agrees with evaluate_all and lists every match
+        let config = make_composed_config(Resolution::Specificity);
+        let explanation = Evaluator::new(&config).explain("git push --force", &cmds);
+        assert_eq!(explanation.decision, denied);
+        assert_eq!(explanation.commands[0].candidates.len(), 2);
+        assert!(explanation.commands[0].candidates.iter().all(|c| c.matched));
+    }
+
+    #[test]
+    fn test_dynamic_content_action() {
+        let allow = |program: &str| Rule {
+            condition: Condition {
+                program: Some(program.to_string()),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..Default::default()
+        };
+        let mut config = make_config_with_rules(vec![allow("echo"), allow("cat")]);
+
+        let cmds = ParsedCommand::parse_all("echo $(cat secrets)").unwrap();
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+
+        config.settings.dynamic_content_action = Some(Action::Prompt);
+        let evaluator = Evaluator::new(&config);
+        assert!(matches!(
+            evaluator.evaluate_all(&cmds),
+            Decision::Prompt { .. }
+        ));
+
+        let explanation = evaluator.explain("echo $(cat secrets)", &cmds);
+        assert_eq!(explanation.commands[0].notes.len(), 1);
+
+        // Static commands are unaffected
+        let cmds = ParsedCommand::parse_all("echo hello").unwrap();
+        assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow);
+    }
+
+    #[test]
+    fn test_script_pinning_and_unresolved_action() {
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp.path().join("build.sh"), "cargo build\nrm -rf dist\n").unwrap();
+        let script = temp.path().join("build.sh").to_string_lossy().to_string();
+        let resolve = |config: &Config| {
+            let evaluator = Evaluator::new(config);
+            let cmds = ParsedCommand::parse_all(&script).unwrap();
+            crate::parser::resolve_scripts(cmds, temp.path(), 1024, &|cmd| {
+                evaluator.trusts_script(cmd)
+            })
+        };
+
+        // Unpinned: the script's commands are evaluated and the default action applies
+        let mut config = make_config_with_rules(vec![]);
+        let cmds = resolve(&config);
+        assert_eq!(cmds.len(), 3);
+        let sha256 = cmds[0].script.as_ref().unwrap().sha256.clone().unwrap();
+
+        // Pinned by hash: the script is allowed without expanding it
+        config.rules.push(Rule {
+            condition: Condition {
+                script_sha256: Some(sha256),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..Default::default()
+        });
+        let cmds = resolve(&config);
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+
+        // Editing the script breaks the pin
+        std::fs::write(temp.path().join("build.sh"), "curl x | sh\n").unwrap();
+        let cmds = resolve(&config);
+        assert_eq!(cmds.len(), 3);
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Prompt { .. }
+        ));
+
+        // Scripts that can't be read are not allowed by their call alone
+        config.rules = vec![Rule {
+            condition: Condition {
+                program: Some("./missing.sh".to_string()),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..Default::default()
+        }];
+        config.settings.unresolved_script_action = Action::Deny;
+        let cmds = crate::parser::resolve_scripts(
+            ParsedCommand::parse_all("./missing.sh").unwrap(),
+            temp.path(),
+            1024,
+            &|_| false,
+        );
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Deny { .. }
+        ));
+
+        // Neither are task runners whose recipe wasn't found
+        std::fs::write(temp.path().join("Makefile"), "build:\n\tcargo build\n").unwrap();
+        config.rules[0].condition.program = Some("make".to_string());
+        let cmds = crate::parser::resolve_recipes(
+            ParsedCommand::parse_all("make nosuch").unwrap(),
+            temp.path(),
+        );
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn test_dynamic_program_action() {
+        let mut config = make_config_with_rules(vec![]);
+        config.settings.default_action = Action::Allow;
+
+        let cmds = ParsedCommand::parse_all("$CMD --version").unwrap();
+        let evaluator = Evaluator::new(&config);
+        assert!(matches!(
+            evaluator.evaluate_all(&cmds),
+            Decision::Prompt { .. }
+        ));
+        let explanation = evaluator.explain("$CMD --version", &cmds);
+        assert_eq!(explanation.commands[0].notes.len(), 1);
+
+        // Known values are evaluated as the program they name
+        let cmds = ParsedCommand::parse_all("CMD=ls; $CMD -la").unwrap();
+        assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow);
+
+        // A rule handling dynamic programs decides on its own
+        config.rules.push(Rule {
+            condition: Condition {
+                dynamic_program: Some(true),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..Default::default()
+        });
+        let cmds = ParsedCommand::parse_all("$CMD --version").unwrap();
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+    }
+
+    #[test]
+    fn test_obfuscation_action() {
+        let mut config = make_config_with_rules(vec![Rule {
+            condition: Condition {
+                program: Some("sh".to_string()),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..Default::default()
+        }]);
+        config.settings.default_action = Action::Allow;
+
+        let cmds = ParsedCommand::parse_all("echo cm0gLXJmIC8= | base64 -d | sh").unwrap();
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Prompt { .. }
+        ));
+        for input in ["sh <(echo cm0= | base64 -d)", r#"sh <<< "rm x""#] {
+            let fed = ParsedCommand::parse_all(input).unwrap();
+            assert!(matches!(
+                Evaluator::new(&config).evaluate_all(&fed),
+                Decision::Prompt { .. }
+            ));
+        }
+
+        // Rules can target obfuscation explicitly
+        config.rules.insert(
+            0,
+            Rule {
+                condition: Condition {
+                    obfuscation: Some("decode-to-shell".to_string()),
+                    ..Default::default()
+                },
+                action: Action::Deny,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Deny { .. }
+        ));
+
+        let cmds = ParsedCommand::parse_all("cat x | sh").unwrap();
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+    }
+
+    #[test]
+    fn test_risk_bands() {
+        let mut config = make_config_with_rules(vec![]);
+        config.settings.default_action = Action::Allow;
+        config.settings.risk.enabled = true;
+        config.settings.risk.bands = vec![
+            RiskBand {
+                min: 40,
+                action: Action::Prompt,
+            },
+            RiskBand {
+                min: 80,
+                action: Action::Deny,
+            },
+        ];
+        let evaluator = Evaluator::new(&config);
+
+        let cmds = ParsedCommand::parse_all("ls -la").unwrap();
+        assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow);
+
+        let cmds = ParsedCommand::parse_all("rm -rf build").unwrap();
+        assert_eq!(
+            evaluator.evaluate_all(&cmds),
+            Decision::Prompt {
+                message: "Risk score 60 (rm destructive +40, rm force +20)".to_string()
+            }
+        );
+
+        // Scores add up across the input, and the band overrides the matched rule
+        let cmds = ParsedCommand::parse_all("rm -rf build && curl -s x").unwrap();
+        let (decision, rule) = evaluator.evaluate_all_with_trace(&cmds);
+        assert!(matches!(decision, Decision::Deny { .. }));
+        assert!(rule.is_none());
+        let explanation = evaluator.explain("rm -rf build && curl -s x", &cmds);
+        assert_eq!(explanation.decision, decision);
+        assert_eq!(explanation.deciding_command, None);
+        assert_eq!(explanation.risk.map(|r| r.score), Some(80));
+
+        // A stricter rule decision wins, and prompts show the score
+        config.settings.default_action = Action::Prompt;
+        config.settings.risk.bands.clear();
+        let cmds = ParsedCommand::parse_all("curl -s x").unwrap();
+        assert_eq!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Prompt {
+                message: "No matching rule found [risk 20 (curl network +20)]".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_rewrite_action() {
+        let rewrite_rule = |program: &str, template: &str| Rule {
+            condition: Condition {
+                program: Some(program.to_string()),
+                ..Default::default()
+            },
+            action: Action::Rewrite,
+            rewrite: Some(template.to_string()),
+            ..Default::default()
+        };
+        let mut config = make_config_with_rules(vec![
+            rewrite_rule("rm", "trash {args}"),
+            rewrite_rule("kubectl", "{program} --context dev {args}"),
+        ]);
+        config.settings.default_action = Action::Allow;
+        let evaluator = Evaluator::new(&config);
+
+        let input = "ls && rm -r build && kubectl get pods";
+        let cmds = ParsedCommand::parse_all(input).unwrap();
+        let (decision, _) = evaluator.evaluate_input(input, &cmds);
+        assert!(matches!(
+            &decision,
+            Decision::Rewrite { command, .. }
+                if command == "ls && trash -r build && kubectl --context dev get pods"
+        ));
+        assert_eq!(evaluator.explain(input, &cmds).decision, decision);
+
+        // Stricter decisions win over rewrites
+        config.rules.push(Rule {
+            condition: Condition {
+                program: Some("curl".to_string()),
+                ..Default::default()
+            },
+            action: Action::Deny,
+            ..Default::default()
+        });
+        let cmds = ParsedCommand::parse_all("rm x; curl y").unwrap();
+        assert!(matches!(
+            Evaluator::new(&config)
+                .evaluate_input("rm x; curl y", &cmds)
+                .0,
+            Decision::Deny { .. }
+        ));
+
+        // Commands without text of their own in the input can't be rewritten
+        let cmds = ParsedCommand::parse_all("find . -exec rm {} +").unwrap();
+        assert!(matches!(
+            Evaluator::new(&config)
+                .evaluate_input("find . -exec rm {} +", &cmds)
+                .0,
+            Decision::Prompt { .. }
+        ));
+
+        // Nor can arguments the shell expands when the command runs
+        let cmds = ParsedCommand::parse_all("rm *.log").unwrap();
+        assert_eq!(
+            Evaluator::new(&config).evaluate_input("rm *.log", &cmds).0,
+            Decision::Prompt {
+                message: "Cannot rewrite arguments the shell expands when the command runs \
+                          (rule: inline#0)"
+                    .to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_warn_audit_and_audit_mode() {
+        let rule = |program: &str, action: Action| Rule {
+            condition: Condition {
+                program: Some(program.to_string()),
+                ..Default::default()
+            },
+            action,
+            message: Some(format!("{} noted", program)),
+            ..Default::default()
+        };
+        let mut config = make_config_with_rules(vec![
+            rule("kubectl", Action::Warn),
+            rule("terraform", Action::Audit),
+            rule("rm", Action::Deny),
+        ]);
+        config.settings.default_action = Action::Allow;
+
+        let cmds = ParsedCommand::parse_all("terraform plan && kubectl get pods").unwrap();
+        assert_eq!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Warn {
+                message: "kubectl noted (rule: inline#0) [in `kubectl get pods`]".to_string()
+            }
+        );
+        let cmds = ParsedCommand::parse_all("terraform plan").unwrap();
+        assert!(matches!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Audit { .. }
+        ));
+
+        // Audit mode reports what would have happened, without blocking
+        config.settings.mode = Mode::Audit;
+        let evaluator = Evaluator::new(&config);
+        let cmds = ParsedCommand::parse_all("rm -rf build").unwrap();
+        let expected = Decision::Audit {
+            message: "Audit mode, would deny: rm noted (rule: inline#2)".to_string(),
+        };
+        assert_eq!(evaluator.evaluate_all(&cmds), expected);
+        assert_eq!(evaluator.explain("rm -rf build", &cmds).decision, expected);
+        let cmds = ParsedCommand::parse_all("kubectl get pods").unwrap();
+        assert!(matches!(
+            evaluator.evaluate_all(&cmds),
+            Decision::Warn { .. }
+        ));
+    }
+
+    #[test]
+    fn test_guidance_for_deciding_command() {
+        let mut config = make_config_with_rules(vec![Rule {
+            condition: Condition {
+                program: Some("rm".to_string()),
+                ..Default::default()
+            },
+            action: Action::Deny,
+            guidance: Some("Move files to the trash instead".to_string()),
+            suggest: vec!["trash {args}".to_string()],
+            ..Default::default()
+        }]);
+        config.settings.default_action = Action::Allow;
+        let evaluator = Evaluator::new(&config);
+
+        let cmds = ParsedCommand::parse_all("ls && rm -r build").unwrap();
+        let guidance = evaluator.guidance(&cmds).unwrap();
+        assert_eq!(guidance.command, "rm -r build");
+        assert_eq!(guidance.rule.as_deref(), Some("inline#0"));
+        assert_eq!(guidance.alternatives, vec!["trash -r build"]);
+
+        let cmds = ParsedCommand::parse_all("ls").unwrap();
+        assert_eq!(evaluator.guidance(&cmds), None);
+    }
+
+    #[test]
+    fn test_sandbox_action() {
+        let rule = |program: &str, sandbox: Option<&str>| Rule {
+            condition: Condition {
+                program: Some(program.to_string()),
+                ..Default::default()
+            },
+            action: Action::Sandbox,
+            sandbox: sandbox.map(str::to_string),
+            ..Default::default()
+        };
+        let mut config = make_config_with_rules(vec![
+            rule("make", Some("build")),
+            rule("npm", None),
+            rule("cargo", Some("missing")),
+            Rule {
+                condition: Condition {
+                    program: Some("rm".to_string()),
+                    ..Default::default()
+                },
+                action: Action::Deny,
+                ..Default::default()
+            },
+        ]);
+        config.sandboxes.insert(
+            "build".to_string(),
+            SandboxProfile {
+                writable: vec!["~/.cache".to_string()],
+                network: false,
+            },
+        );
+        let evaluator = Evaluator::new(&config);
+
+        let decision =
+            |input: &str| evaluator.evaluate_all(&ParsedCommand::parse_all(input).unwrap());
+        assert!(matches!(
+            decision("make test"),
+            Decision::Sandbox { policy, .. } if policy == "build"
+        ));
+        assert!(matches!(
+            decision("npm test"),
+            Decision::Sandbox { policy, .. } if policy == DEFAULT_SANDBOX
+        ));
+        assert!(matches!(decision("cargo build"), Decision::Prompt { .. }));
+
+        // Running a sandbox directly doesn't hide the command from rules
+        assert!(matches!(
+            decision("bashguard sandbox-exec -- rm -rf /"),
+            Decision::Deny { .. }
+        ));
+        assert!(matches!(
+            decision("bashguard sandbox-exec --policy build -- 'make test && rm -rf /'"),
+            Decision::Deny { .. }
+        ));
+
+        config.settings.mode = Mode::Audit;
+        let cmds = ParsedCommand::parse_all("make test").unwrap();
+        assert_eq!(
+            Evaluator::new(&config).evaluate_all(&cmds),
+            Decision::Audit {
+                message:
+                    "Audit mode, would sandbox (policy build): Runs in a sandbox (rule: inline#0)"
+                        .to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_grants_before_profiles() {
+        let rule = |program: &str, action: Action| Rule {
+            condition: Condition {
+                program: Some(program.to_string()),
+                ..Default::default()
+            },
+            action,
+            ..Default::default()
+        };
+        let mut config = make_config_with_rules(vec![rule("rm", Action::Deny)]);
+        config.loaded_profiles.push(Profile {
+            rules: vec![rule("git", Action::Prompt), rule("ls", Action::Allow)],
+            ..Default::default()
+        });
+        config.assign_rule_origins(None);
+        let grant = |program: &str, id: &str| Rule {
+            origin: RuleOrigin {
+                grant: Some(id.to_string()),
+                ..Default::default()
+            },
+            ..rule(program, Action::Allow)
+        };
+        config.grants = vec![grant("git", "g1"), grant("rm", "g2")];
+        let evaluator = Evaluator::new(&config);
+
+        let cmds = ParsedCommand::parse_all("ls && git push").unwrap();
+        let (decision, rule) = evaluator.evaluate_all_with_trace(&cmds);
+        assert_eq!(decision, Decision::Allow);
+        assert_eq!(rule.map(|rule| rule.id()).as_deref(), Some("grant:g1"));
+
+        // Grants never allow what is denied
+        let cmds = ParsedCommand::parse_all("rm -r build").unwrap();
+        assert!(matches!(
+            evaluator.evaluate_all(&cmds),
+            Decision::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn test_grant_overrides_inline_prompt() {
+        let prompt = Rule {
+            condition: Condition {
+                program: Some("git".to_string()),
+                subcommands: vec!["push".to_string()],
+                ..Default::default()
+            },
+            action: Action::Prompt,
+            priority: 10,
+            ..Default::default()
+        };
+        let grant = Rule {
+            origin: RuleOrigin {
+                grant: Some("g1".to_string()),
+                ..Default::default()
+            },
+            action: Action::Allow,
+            ..prompt.clone()
+        };
+        let cmds = ParsedCommand::parse_all("git push origin main").unwrap();
+
+        for resolution in [
+            Resolution::FirstMatch,
+            Resolution::Priority,
+            Resolution::Specificity,
+            Resolution::DenyWins,
+        ] {
+            let mut config = make_config_with_rules(vec![prompt.clone()]);
+            config.settings.resolution = resolution;
+            assert!(matches!(
+                Evaluator::new(&config).evaluate_all(&cmds),
+                Decision::Prompt { .. }
+            ));
+
+            config.grants = vec![grant.clone()];
+            let evaluator = Evaluator::new(&config);
+            let (decision, rule) = evaluator.evaluate_all_with_trace(&cmds);
+            assert_eq!(decision, Decision::Allow, "{:?}", resolution);
+            assert_eq!(rule.map(|rule| rule.id()).as_deref(), Some("grant:g1"));
+            assert_eq!(
+                evaluator.explain("git push origin main", &cmds).decision,
+                Decision::Allow
+            );
+        }
+    }
+
+    #[test]
+    fn test_agent_cannot_approve() {
+        let mut config = make_config_with_rules(vec![]);
+        config.settings.default_action = Action::Allow;
+        let evaluator = Evaluator::new(&config);
+
+        for input in [
+            "bashguard approve 3fa9c2",
+            "ls && ~/.cargo/bin/bashguard grant --for 1h git push",
+        ] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(
+                matches!(evaluator.evaluate_all(&cmds), Decision::Deny { .. }),
+                "{}",
+                input
+            );
+        }
+        let cmds = ParsedCommand::parse_all("bashguard grants").unwrap();
+        assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow);
+
+        // Audit mode doesn't let these through
+        config.settings.mode = Mode::Audit;
+        let evaluator = Evaluator::new(&config);
+        for input in ["bashguard approve 3fa9c2", "rm -rf .bashguard"] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(
+                matches!(evaluator.evaluate_all(&cmds), Decision::Deny { .. }),
+                "{}",
+                input
+            );
+            assert!(matches!(
+                evaluator.evaluate_input(input, &cmds).0,
+                Decision::Deny { .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_agent_cannot_change_bashguard_files() {
+        let mut config = make_config_with_rules(vec![]);
+        config.settings.default_action = Action::Allow;
+        let evaluator = Evaluator::new(&config);
+
+        for input in [
+            r#"echo '[{"id":"g1","spec":"rm -rf /"}]' > .bashguard/grants.json"#,
+            "cp grants.json ./.bashguard/",
+            "rm -rf .bashguard",
+            "cd .bashguard && touch x",
+            "sed -i s/deny/allow/ .bashguard/config.toml",
+            "tee ~/.local/state/bashguard/x/grants.json < forged.json",
+        ] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(
+                matches!(
+                    evaluator.evaluate_all(&cmds),
+                    Decision::Deny { message }
+                        if message.starts_with("Only a human can change bashguard's")
+                ),
+                "{}",
+                input
+            );
+        }
+
+        // Reading them, or naming them to commands that don't write them, is fine
+        for input in [
+            "cat .bashguard/config.toml",
+            "ls -la .bashguard/logs",
+            "git add .bashguard/config.toml",
+            "mytool .bashguard/logs/x",
+            "touch notes/.bashguard.md",
+            "grep deny .bashguard/config.toml > rules.txt",
+        ] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_escalation_with_history() {
+        let mut config = make_config_with_rules(vec![Rule {
+            condition: Condition {
+                program: Some("rm".to_string()),
+                ..Default::default()
+            },
+            action: Action::Deny,
+            ..Default::default()
+        }]);
+        config.settings.default_action = Action::Allow;
+        config.settings.escalation = vec![EscalationRule {
+            denials: 1,
+            window: Some("5m".to_string()),
+            similar: true,
+            action: Action::Deny,
+            message: Some("Looks like a retry of a denied command".to_string()),
+        }];
+        let rm = &ParsedCommand::parse_all("rm -rf build").unwrap()[0];
+        let history = SessionHistory {
+            denials: vec![escalation::Denial {
+                timestamp: Utc::now(),
+                input: "rm -rf build".to_string(),
+                program: rm.program.clone(),
+                subcommands: rm.subcommands.clone(),
+                flags: rm.flags.iter().cloned().collect(),
+                effects: rm.effects.clone(),
+                targets: rm.args.clone(),
+            }],
+        };
+
+        // Without history, nothing escalates
+        let cmds = ParsedCommand::parse_all("ls && git clean -fdx build").unwrap();
+        assert_eq!(Evaluator::new(&config).evaluate_all(&cmds), Decision::Allow);
+
+        let evaluator = Evaluator::new(&config).with_history(history);
+        let (decision, rule) = evaluator.evaluate_all_with_trace(&cmds);
+        assert_eq!(
+            decision,
+            Decision::Deny {
+                message: "Looks like a retry of a denied command".to_string()
+            }
+        );
+        assert!(rule.is_none());
+        assert!(evaluator.escalated(&cmds));
+        for input in ["ls", "git clean -fdx"] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert_eq!(evaluator.evaluate_all(&cmds), Decision::Allow, "{}", input);
+            assert!(!evaluator.escalated(&cmds), "{}", input);
+        }
+
+        // Denials by the rules themselves aren't escalations
+        let cmds = ParsedCommand::parse_all("rm -rf build").unwrap();
+        assert!(!evaluator.escalated(&cmds));
+
+        // The denied command is the one logged for later escalation
+        let cmds = ParsedCommand::parse_all("ls && rm -rf dist").unwrap();
+        let deciding = evaluator.deciding_command(&cmds).unwrap();
+        assert_eq!(deciding.raw.trim(), "rm -rf dist");
+
+        // Other subcommands of a denied program, like the ones guidance suggests,
+        // still run
+        let push = &ParsedCommand::parse_all("git push --force").unwrap()[0];
+        let history = SessionHistory {
+            denials: vec![escalation::Denial {
+                timestamp: Utc::now(),
+                input: "git push --force".to_string(),
+                program: push.program.clone(),
+                subcommands: push.subcommands.clone(),
+                flags: push.flags.iter().cloned().collect(),
+                effects: push.effects.clone(),
+                targets: push.args.clone(),
+            }],
+        };
+        let evaluator = Evaluator::new(&config).with_history(history);
+        for (input, escalated) in [("git status", false), ("git push origin main", true)] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert_eq!(evaluator.escalated(&cmds), escalated, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let mut config = make_config_with_rules(vec![Rule {
+            id: Some("kubectl-writes".to_string()),
+            condition: Condition {
+                program: Some("kubectl".to_string()),
+                subcommands: vec!["apply".to_string()],
+                ..Default::default()
+            },
+            action: Action::Allow,
+            limit: Some(Limit {
+                count: 3,
+                window: None,
+                action: Action::Deny,
+                message: None,
+            }),
+            ..Default::default()
+        }]);
+        config.settings.default_action = Action::Allow;
+        config.settings.limits = vec![SessionLimit {
+            id: Some("network".to_string()),
+            condition: Condition {
+                effects: vec![Effect::Network],
+                ..Default::default()
+            },
+            limit: Limit {
+                count: 2,
+                window: Some("10m".to_string()),
+                action: Action::Prompt,
+                message: Some("Too many network commands".to_string()),
+            },
+        }];
+
+        let curl = ParsedCommand::parse_all("curl https://example.com").unwrap();
+        let kubectl = ParsedCommand::parse_all("kubectl apply -f deploy.yaml").unwrap();
+        let ls = ParsedCommand::parse_all("ls").unwrap();
+        let counters = |cmds: &[ParsedCommand]| -> Vec<String> {
+            let evaluator = Evaluator::new(&config);
+            evaluator
+                .limits(cmds)
+                .into_iter()
+                .map(|applied| applied.counter)
+                .collect()
+        };
+        assert_eq!(counters(&curl), vec!["network"]);
+        // kubectl talks to the cluster, so both limits count it
+        assert_eq!(counters(&kubectl), vec!["network", "rule:kubectl-writes"]);
+        assert!(counters(&ls).is_empty());
+
+        let now = Utc::now();
+        let mut usage = SessionUsage::default();
+        usage.record("network", now, 3, 2);
+        usage.record("rule:kubectl-writes", now, 3, 3);
+        let evaluator = Evaluator::new(&config).with_usage(usage);
+        let (decision, rule) = evaluator.evaluate_all_with_trace(&curl);
+        assert_eq!(
+            decision,
+            Decision::Prompt {
+                message: "Too many network commands".to_string()
+            }
+        );
+        assert!(rule.is_none());
+        assert_eq!(
+            evaluator.evaluate_all(&kubectl),
+            Decision::Deny {
+                message: "Limit reached: 3 per session (rule:kubectl-writes)".to_string()
+            }
+        );
+        assert_eq!(evaluator.evaluate_all(&ls), Decision::Allow);
+
+        // Each matching command of an input counts, so one input can't run past the
+        // limit at once
+        let wgets = ParsedCommand::parse_all("wget a; wget b; wget c").unwrap();
+        let evaluator = Evaluator::new(&config);
+        let limits = evaluator.limits(&wgets);
+        assert_eq!(limits[0].matched, 3);
+        assert!(matches!(
+            evaluator.evaluate_all(&wgets),
+            Decision::Prompt { .. }
+        ));
+
+        let mut usage = SessionUsage::default();
+        usage.record("network", now, 1, 2);
+        let evaluator = Evaluator::new(&config).with_usage(usage);
+        let one = ParsedCommand::parse_all("wget a").unwrap();
+        let two = ParsedCommand::parse_all("wget a && wget b").unwrap();
+        assert_eq!(evaluator.evaluate_all(&one), Decision::Allow);
+        assert!(matches!(
+            evaluator.evaluate_all(&two),
+            Decision::Prompt { .. }
+        ));
+    }
 }
@@ -0,0 +1,246 @@
+//! Full evaluation traces for `bashguard explain` and `check --explain`
+
+use std::fmt;
+
+use serde::Serialize;
+
+use super::{evaluator::Decision, matcher::ConditionResult, risk::RiskScore};
+use crate::{
+    config::{Action, Resolution, RuleOrigin},
+    parser::{
+        CommandContext, ControlFlow, Effect, FileSource, Nesting, Obfuscation, ScriptFile, Span,
+    },
+};
+
+/// Result of checking one candidate rule against a command
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleEvaluation {
+    pub id: String,
+    pub name: Option<String>,
+    pub origin: RuleOrigin,
+    pub action: Action,
+    pub priority: i32,
+    pub specificity: usize,
+    pub matched: bool,
+    pub conditions: Vec<ConditionResult>,
+}
+
+/// Evaluation trace for a single extracted command
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandExplanation {
+    /// The command's text as written
+    pub raw: String,
+    /// Byte range of the command in the input
+    pub span: Option<Span>,
+    pub nesting: Vec<Nesting>,
+    pub control: ControlFlow,
+    pub program: String,
+    pub subcommands: Vec<String>,
+    pub flags: Vec<String>,
+    pub args: Vec<String>,
+    pub has_expansion: bool,
+    pub has_substitution: bool,
+    pub dynamic_program: bool,
+    pub dynamic_args: Vec<String>,
+    pub obfuscation: Vec<Obfuscation>,
+    pub effects: Vec<Effect>,
+    /// Risk score of this command alone (when `settings.risk` is enabled)
+    pub risk: Option<RiskScore>,
+    pub has_redirect: bool,
+    pub is_piped: bool,
+    pub context: CommandContext,
+    pub embedded_in: Option<String>,
+    pub file_source: Option<FileSource>,
+    pub script: Option<ScriptFile>,
+    /// Rules considered, in evaluation order. With first-match resolution this stops
+    /// at the first match. Rules whose `program` condition fails are omitted.
+    pub candidates: Vec<RuleEvaluation>,
+    /// Total number of rules checked before a match (or all rules if none matched)
+    pub rules_checked: usize,
+    /// Id of the matching rule (`None` means the default action was used)
+    pub matched_rule: Option<String>,
+    pub decision: Decision,
+    /// Global policies from `settings` that changed the rule decision
+    pub notes: Vec<String>,
+}
+
+/// Evaluation trace for a full input, combining all extracted commands
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub input: String,
+    pub resolution: Resolution,
+    pub commands: Vec<CommandExplanation>,
+    /// Index into `commands` of the command that produced the final decision
+    /// (`None` when a risk band did)
+    pub deciding_command: Option<usize>,
+    /// Risk score of the whole input (when `settings.risk` is enabled)
+    pub risk: Option<RiskScore>,
+    pub decision: Decision,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Command: {}", self.input)?;
+        writeln!(f, "Resolution: {}", resolution_name(self.resolution))?;
+
+        for (i, cmd) in self.commands.iter().enumerate() {
+            let mut flags = cmd.flags.clone();
+            flags.sort();
+
+            writeln!(f, "\n[{}] Program: {}", i + 1, cmd.program)?;
+            if let Some(span) = cmd.span {
+                for line in span.underline(&self.input).lines() {
+                    writeln!(f, "    | {}", line)?;
+                }
+            }
+            if !cmd.nesting.is_empty() {
+                let path: Vec<String> = cmd.nesting.iter().map(Nesting::to_string).collect();
+                writeln!(f, "    Nested in: {}", path.join(" > "))?;
+            }
+            if !cmd.control.is_empty() {
+                writeln!(f, "    Control flow: {}", cmd.control)?;
+            }
+            writeln!(f, "    Subcommands: {:?}", cmd.subcommands)?;
+            writeln!(f, "    Flags: {:?}", flags)?;
+            writeln!(f, "    Args: {:?}", cmd.args)?;
+            let structure: Vec<&str> = [
+                (cmd.has_expansion, "expansion"),
+                (cmd.has_substitution, "substitution"),
+                (cmd.has_redirect, "redirect"),
+                (cmd.is_piped, "pipe"),
+            ]
+            .iter()
+            .filter(|(present, _)| *present)
+            .map(|(_, name)| *name)
+            .collect();
+            if !structure.is_empty() {
+                writeln!(f, "    Structure: {}", structure.join(", "))?;
+            }
+            if cmd.dynamic_program || !cmd.dynamic_args.is_empty() {
+                let mut dynamic = cmd.dynamic_args.clone();
+                if cmd.dynamic_program {
+                    dynamic.insert(0, cmd.program.clone());
+                }
+                writeln!(f, "    Unknown until runtime: {:?}", dynamic)?;
+            }
+            if !cmd.effects.is_empty() {
+                let effects: Vec<&str> = cmd.effects.iter().map(|e| e.name()).collect();
+                writeln!(f, "    Effects: {}", effects.join(", "))?;
+            }
+            if let Some(risk) = &cmd.risk {
+                writeln!(f, "    Risk: {}", risk)?;
+            }
+            if !cmd.obfuscation.is_empty() {
+                let techniques: Vec<&str> = cmd.obfuscation.iter().map(|o| o.name()).collect();
+                writeln!(f, "    Obfuscation: {}", techniques.join(", "))?;
+            }
+            if let Some(outer) = &cmd.embedded_in {
+                writeln!(f, "    Embedded in: {}", outer)?;
+            }
+            if cmd.context != CommandContext::Local {
+                writeln!(f, "    Runs: {}", cmd.context)?;
+            }
+            if let Some(source) = &cmd.file_source {
+                writeln!(f, "    From: {}", source)?;
+            }
+            if let Some(script) = &cmd.script {
+                writeln!(f, "    Script: {}", script)?;
+            }
+            writeln!(
+                f,
+                "    Candidate rules ({} of {} checked):",
+                cmd.candidates.len(),
+                cmd.rules_checked
+            )?;
+            if cmd.candidates.is_empty() {
+                writeln!(f, "      (none)")?;
+            }
+            for candidate in &cmd.candidates {
+                writeln!(
+                    f,
+                    "      {} ({}) [{}, priority {}, specificity {}] {}",
+                    candidate.id,
+                    candidate.origin,
+                    action_name(candidate.action),
+                    candidate.priority,
+                    candidate.specificity,
+                    if candidate.matched {
+                        "MATCH"
+                    } else {
+                        "no match"
+                    }
+                )?;
+                write_conditions(f, &candidate.conditions, 8)?;
+            }
+            for note in &cmd.notes {
+                writeln!(f, "    Policy: {}", note)?;
+            }
+            match &cmd.matched_rule {
+                Some(id) => writeln!(f, "    => {} (from {})", cmd.decision, id)?,
+                None => writeln!(f, "    => {} (default action)", cmd.decision)?,
+            }
+        }
+
+        writeln!(
+            f,
+            "\nCombination: strictest wins (deny > prompt > sandbox > rewrite > warn > audit > allow)"
+        )?;
+        if let Some(risk) = &self.risk {
+            writeln!(f, "Risk score: {}", risk)?;
+        }
+        match self.deciding_command {
+            Some(i) => {
+                write!(f, "Final decision: {} (from [{}]", self.decision, i + 1)?;
+                if let Some(source) = &self.commands[i].file_source {
+                    write!(f, " at {}", source)?;
+                }
+                write!(f, ")")
+            }
+            None => write!(f, "Final decision: {}", self.decision),
+        }
+    }
+}
+
+fn write_conditions(
+    f: &mut fmt::Formatter<'_>,
+    conditions: &[ConditionResult],
+    indent: usize,
+) -> fmt::Result {
+    for condition in conditions {
+        write!(
+            f,
+            "{:indent$}{} {}",
+            "",
+            if condition.passed { "pass" } else { "FAIL" },
+            condition.condition,
+        )?;
+        if condition.detail.is_empty() {
+            writeln!(f)?;
+        } else {
+            writeln!(f, ": {}", condition.detail)?;
+        }
+        write_conditions(f, &condition.children, indent + 2)?;
+    }
+    Ok(())
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Allow => "allow",
+        Action::Deny => "deny",
+        Action::Prompt => "prompt",
+        Action::Rewrite => "rewrite",
+        Action::Warn => "warn",
+        Action::Audit => "audit",
+        Action::Sandbox => "sandbox",
+    }
+}
+
+fn resolution_name(resolution: Resolution) -> &'static str {
+    match resolution {
+        Resolution::FirstMatch => "first-match",
+        Resolution::Priority => "priority",
+        Resolution::Specificity => "specificity",
+        Resolution::DenyWins => "deny-wins",
+    }
+}
@@ -0,0 +1,221 @@
+//! Guidance for the agent when a command is blocked or needs approval
+//!
+//! A bare "Blocked by rule" invites retrying variants of the command until one gets
+//! through. Guidance says which part of the input was blocked and what to do instead:
+//! the rule's own `guidance` and `suggest` templates, or else the commands active
+//! profiles allow for the same program.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use super::rewrite;
+use crate::{
+    config::{Action, Rule},
+    parser::{FileSource, ParsedCommand, Span},
+};
+
+/// Most alternatives listed from profiles, to keep messages short
+const MAX_PROFILE_ALTERNATIVES: usize = 8;
+
+/// What was blocked, and what to do instead
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Guidance {
+    /// The blocked command as written
+    pub command: String,
+    /// Whether the command only needs approval (it was prompted, not denied)
+    pub needs_approval: bool,
+    /// Byte range of the command in the input
+    pub span: Option<Span>,
+    /// File the command comes from, for commands in scripts and recipes
+    pub file_source: Option<FileSource>,
+    /// Id of the rule that blocked it (`None` for global policies)
+    pub rule: Option<String>,
+    /// The rule's advice
+    pub guidance: Option<String>,
+    /// Commands to run instead
+    pub alternatives: Vec<String>,
+}
+
+impl Guidance {
+    /// Guidance for `command`, decided by `rule` (prompted if `needs_approval`),
+    /// looking for alternatives in `rules`
+    pub(super) fn new<'r>(
+        command: &ParsedCommand,
+        needs_approval: bool,
+        rule: Option<&Rule>,
+        rules: impl Iterator<Item = &'r Rule>,
+    ) -> Self {
+        let suggested: Vec<String> = rule
+            .map(|rule| {
+                rule.suggest
+                    .iter()
+                    .filter_map(|template| rewrite::render(template, command))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let alternatives = if suggested.is_empty() {
+            profile_alternatives(command, rules)
+        } else {
+            suggested
+        };
+
+        Self {
+            command: command.raw.trim().to_string(),
+            needs_approval,
+            span: command.span,
+            file_source: command.file_source.clone(),
+            rule: rule.map(Rule::id),
+            guidance: rule.and_then(|rule| rule.guidance.clone()),
+            alternatives,
+        }
+    }
+}
+
+impl fmt::Display for Guidance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.needs_approval {
+            "Needs approval"
+        } else {
+            "Blocked"
+        };
+        write!(f, "{}: `{}`", status, self.command)?;
+        if let Some(source) = &self.file_source {
+            write!(f, " at {}", source)?;
+        }
+        if let Some(guidance) = &self.guidance {
+            write!(f, "\nGuidance: {}", guidance)?;
+        }
+        if !self.alternatives.is_empty() {
+            write!(f, "\nInstead, use one of:")?;
+            for alternative in &self.alternatives {
+                write!(f, "\n  {}", alternative)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Commands allowed by profile rules for the same program (e.g., `git status` and
+/// `git log` for a blocked `git push`)
+fn profile_alternatives<'r>(
+    command: &ParsedCommand,
+    rules: impl Iterator<Item = &'r Rule>,
+) -> Vec<String> {
+    let mut alternatives: Vec<String> = Vec::new();
+    let allowed = rules.filter(|rule| {
+        rule.action == Action::Allow
+            && rule.origin.profile.is_some()
+            && rule.condition.program.as_deref() == Some(command.program.as_str())
+            && !rule.condition.subcommands.is_empty()
+    });
+    for rule in allowed {
+        let alternative = format!(
+            "{} {}",
+            command.program,
+            rule.condition.subcommands.join(" ")
+        );
+        if !alternatives.contains(&alternative) {
+            alternatives.push(alternative);
+        }
+        if alternatives.len() == MAX_PROFILE_ALTERNATIVES {
+            break;
+        }
+    }
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Condition, RuleOrigin};
+
+    fn rule(subcommands: &[&str], action: Action, profile: Option<&str>) -> Rule {
+        Rule {
+            condition: Condition {
+                program: Some("git".to_string()),
+                subcommands: subcommands.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            },
+            action,
+            origin: RuleOrigin {
+                profile: profile.map(str::to_string),
+                path: None,
+                index: 0,
+                grant: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_suggestions_and_profile_fallback() {
+        let commands = ParsedCommand::parse_all("git push --force origin main").unwrap();
+        let profile = "git/read-only";
+        let rules = [
+            rule(&["status"], Action::Allow, Some(profile)),
+            rule(&["log"], Action::Allow, Some(profile)),
+            rule(&["log"], Action::Allow, Some(profile)),
+            rule(&["push"], Action::Deny, Some(profile)),
+            rule(&["fetch"], Action::Allow, None),
+        ];
+
+        let mut blocking = rule(&["push"], Action::Deny, None);
+        blocking.guidance = Some("Never force-push shared branches".to_string());
+        let guidance = Guidance::new(&commands[0], false, Some(&blocking), rules.iter());
+        assert_eq!(guidance.alternatives, vec!["git status", "git log"]);
+        assert_eq!(
+            guidance.to_string(),
+            "Blocked: `git push --force origin main`\n\
+             Guidance: Never force-push shared branches\n\
+             Instead, use one of:\n  git status\n  git log"
+        );
+
+        blocking.suggest = vec!["{program} push --force-with-lease origin main".to_string()];
+        let guidance = Guidance::new(&commands[0], false, Some(&blocking), rules.iter());
+        assert_eq!(
+            guidance.alternatives,
+            vec!["git push --force-with-lease origin main"]
+        );
+    }
+
+    #[test]
+    fn test_shipped_suggestion_example() {
+        // The commented example rule in the config template, uncommented
+        let template = include_str!("../templates/config.toml");
+        let lines: Vec<&str> = template.lines().collect();
+        let end = lines
+            .iter()
+            .position(|line| line.starts_with("# suggest = "))
+            .unwrap();
+        let start = lines[..end]
+            .iter()
+            .rposition(|line| *line == "# [[rules]]")
+            .unwrap();
+        let example: Vec<&str> = lines[start + 1..=end]
+            .iter()
+            .map(|line| line.trim_start_matches("# "))
+            .collect();
+        let example: Rule = toml::from_str(&example.join("\n")).unwrap();
+
+        let commands = ParsedCommand::parse_all("git push --force origin main").unwrap();
+        let guidance = Guidance::new(&commands[0], false, Some(&example), [].iter());
+        assert_eq!(
+            guidance.alternatives,
+            vec!["git push --force-with-lease origin main"]
+        );
+    }
+
+    #[test]
+    fn test_prompted_guidance() {
+        let commands = ParsedCommand::parse_all("git push origin main").unwrap();
+        let mut prompting = rule(&["push"], Action::Prompt, None);
+        prompting.guidance = Some("Pushes are reviewed by the user".to_string());
+        let guidance = Guidance::new(&commands[0], true, Some(&prompting), [].iter());
+        assert_eq!(
+            guidance.to_string(),
+            "Needs approval: `git push origin main`\n\
+             Guidance: Pushes are reviewed by the user"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-session rate limits and command budgets
+//!
+//! Limits (`settings.limits`, and `limit` on rules) cap how many commands run in a
+//! session. `check` records each command of an input that runs against the counters
+//! of the limits counting it; an input that would take a counter past the limit's
+//! `count` within its `window` gets the limit's action.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{parse_duration, Action, Limit};
+
+/// When the commands counted by each limit ran in a session, by counter name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionUsage {
+    #[serde(default)]
+    pub counters: BTreeMap<String, Vec<DateTime<Utc>>>,
+}
+
+impl SessionUsage {
+    /// How many commands counted by `counter` ran within `window` before `now`
+    pub fn count(&self, counter: &str, window: Option<Duration>, now: DateTime<Utc>) -> usize {
+        self.counters.get(counter).map_or(0, |times| {
+            times
+                .iter()
+                .filter(|time| window.is_none_or(|window| now - **time < window))
+                .count()
+        })
+    }
+
+    /// Count `commands` commands for `counter`, keeping only the latest `keep` times
+    /// (all a limit with that count looks at)
+    pub fn record(&mut self, counter: &str, now: DateTime<Utc>, commands: usize, keep: usize) {
+        let times = self.counters.entry(counter.to_string()).or_default();
+        times.extend(std::iter::repeat_n(now, commands));
+        let excess = times.len().saturating_sub(keep);
+        times.drain(..excess);
+    }
+}
+
+/// A limit that applies to an input, with the name of its counter
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedLimit {
+    /// `settings.limits` entry id (or "settings.limits[i]"), or "rule:<rule id>"
+    pub counter: String,
+    pub limit: Limit,
+    /// How many of the input's commands the limit counts
+    pub matched: usize,
+}
+
+impl AppliedLimit {
+    /// The limit's action and message, if running the input's matched commands would
+    /// take `usage` past the limit at `now`
+    pub(super) fn reached(
+        &self,
+        usage: &SessionUsage,
+        now: DateTime<Utc>,
+    ) -> Option<(Action, String)> {
+        // Invalid windows are reported when loading the config
+        let window = self
+            .limit
+            .window
+            .as_deref()
+            .and_then(|window| parse_duration(window).ok());
+        if usage.count(&self.counter, window, now) + self.matched <= self.limit.count {
+            return None;
+        }
+
+        let message = self.limit.message.clone().unwrap_or_else(|| {
+            format!(
+                "Limit reached: {} per {} ({})",
+                self.limit.count,
+                self.limit.window.as_deref().unwrap_or("session"),
+                self.counter
+            )
+        });
+        Some((self.limit.action, message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_and_limits() {
+        let now = Utc::now();
+        let mut usage = SessionUsage::default();
+        for minutes_ago in [30, 8, 2] {
+            usage.record("network", now - Duration::minutes(minutes_ago), 1, 3);
+        }
+        usage.record("network", now - Duration::minutes(1), 1, 3);
+        assert_eq!(usage.counters["network"].len(), 3);
+        assert_eq!(usage.count("network", None, now), 3);
+        assert_eq!(usage.count("network", Some(Duration::minutes(5)), now), 2);
+        assert_eq!(usage.count("other", None, now), 0);
+
+        let applied = |count: usize, window: Option<&str>, matched: usize| AppliedLimit {
+            counter: "network".to_string(),
+            limit: Limit {
+                count,
+                window: window.map(str::to_string),
+                action: Action::Deny,
+                message: None,
+            },
+            matched,
+        };
+        assert_eq!(
+            applied(3, None, 1).reached(&usage, now),
+            Some((
+                Action::Deny,
+                "Limit reached: 3 per session (network)".to_string()
+            ))
+        );
+        assert_eq!(applied(3, Some("5m"), 1).reached(&usage, now), None);
+        assert!(applied(2, Some("5m"), 1).reached(&usage, now).is_some());
+        // Several matching commands in one input count separately
+        assert!(applied(3, Some("5m"), 2).reached(&usage, now).is_some());
+
+        usage.record("batch", now, 5, 3);
+        assert_eq!(usage.count("batch", None, now), 3);
+    }
+}
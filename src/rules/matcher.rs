@@ -1,96 +1,554 @@
 use regex::Regex;
+use serde::Serialize;
 
-use crate::{config::Rule, parser::ParsedCommand};
+use crate::{
+    config::{ArgPattern, Condition, Rule},
+    parser::{Effect, Obfuscation, ParsedCommand},
+};
 
 /// Matches rules against parsed commands
 pub struct RuleMatcher;
 
+/// Outcome of checking a single rule condition against a command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConditionResult {
+    /// Name of the rule field that was checked (e.g., "program")
+    pub condition: String,
+    /// Whether the condition passed
+    pub passed: bool,
+    /// What was compared, for display
+    pub detail: String,
+    /// Results of nested conditions (for `all`, `any` and `not`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ConditionResult>,
+}
+
+impl ConditionResult {
+    fn new(condition: &str, passed: bool, detail: String) -> Self {
+        Self {
+            condition: condition.to_string(),
+            passed,
+            detail,
+            children: Vec::new(),
+        }
+    }
+
+    /// Group the results of one nested condition into a single result
+    fn branch(label: String, children: Vec<ConditionResult>) -> Self {
+        Self {
+            condition: label,
+            passed: children.iter().all(|c| c.passed),
+            detail: String::new(),
+            children,
+        }
+    }
+}
+
 impl RuleMatcher {
     /// Check if a rule matches a parsed command
     pub fn matches(rule: &Rule, command: &ParsedCommand) -> bool {
+        Self::check(&rule.condition, command)
+            .iter()
+            .all(|c| c.passed)
+    }
+
+    /// How constrained a rule is: one point per condition it checks, plus one per
+    /// subcommand and flag, with an extra point for exact subcommand matching.
+    pub fn specificity(rule: &Rule) -> usize {
+        Self::condition_specificity(&rule.condition)
+    }
+
+    fn condition_specificity(condition: &Condition) -> usize {
+        let mut score = 0;
+        if condition.program.is_some() {
+            score += 1;
+        }
+        score += condition.subcommands.len();
+        if condition.subcommands_exact && !condition.subcommands.is_empty() {
+            score += 1;
+        }
+        score += condition.arg.len();
+        score += [condition.any_arg.is_some(), condition.all_args.is_some()]
+            .iter()
+            .filter(|c| **c)
+            .count();
+        score += [condition.args_min, condition.args_max]
+            .iter()
+            .filter(|c| c.is_some())
+            .count();
+        score += [
+            condition.allow_expansion,
+            condition.allow_substitution,
+            condition.allow_redirect,
+            condition.allow_pipe,
+        ]
+        .iter()
+        .filter(|c| **c == Some(false))
+        .count();
+        score += condition.flags_present.len();
+        score += condition.flags_absent.len();
+        score += usize::from(condition.flags_only.is_some());
+        score += usize::from(!condition.effects.is_empty());
+        score += condition.effects_absent.len();
+        score += [
+            &condition.args_match,
+            &condition.args_regex,
+            &condition.working_dir,
+            &condition.script_sha256,
+            &condition.obfuscation,
+        ]
+        .iter()
+        .filter(|c| c.is_some())
+        .count();
+        score += [
+            condition.dynamic_program,
+            condition.in_loop,
+            condition.conditional,
+            condition.background,
+        ]
+        .iter()
+        .filter(|c| c.is_some())
+        .count();
+        // Nested `all` adds every branch; `any` only guarantees its weakest branch
+        score += condition
+            .all
+            .iter()
+            .map(Self::condition_specificity)
+            .sum::<usize>();
+        score += condition
+            .any
+            .iter()
+            .map(Self::condition_specificity)
+            .min()
+            .unwrap_or(0);
+        if let Some(not) = &condition.not {
+            score += Self::condition_specificity(not);
+        }
+        score
+    }
+
+    /// Check every field of a condition against a parsed command.
+    ///
+    /// Unlike `matches()`, this does not stop at the first failing condition, so the
+    /// result can be used to explain why a rule did or did not match.
+    pub fn check(condition: &Condition, command: &ParsedCommand) -> Vec<ConditionResult> {
+        let mut results = Vec::new();
+
         // Check program
-        if let Some(ref program) = rule.program {
-            if command.program != *program {
-                return false;
-            }
+        if let Some(ref program) = condition.program {
+            results.push(ConditionResult::new(
+                "program",
+                command.program == *program,
+                format!("expected {:?}, got {:?}", program, command.program),
+            ));
         }
 
         // Check subcommands
-        if !rule.subcommands.is_empty() {
-            if rule.subcommands_exact {
+        if !condition.subcommands.is_empty() {
+            let passed = if condition.subcommands_exact {
                 // Exact match: command subcommands must equal rule subcommands
-                if command.subcommands != rule.subcommands {
-                    return false;
-                }
+                command.subcommands == condition.subcommands
             } else {
                 // Prefix match: command subcommands must start with rule subcommands
-                if command.subcommands.len() < rule.subcommands.len() {
-                    return false;
-                }
-                for (i, subcmd) in rule.subcommands.iter().enumerate() {
-                    if command.subcommands.get(i) != Some(subcmd) {
-                        return false;
-                    }
-                }
-            }
+                command.subcommands.starts_with(&condition.subcommands)
+            };
+            let mode = if condition.subcommands_exact {
+                "exactly"
+            } else {
+                "starting with"
+            };
+            results.push(ConditionResult::new(
+                "subcommands",
+                passed,
+                format!(
+                    "expected {} {:?}, got {:?}",
+                    mode, condition.subcommands, command.subcommands
+                ),
+            ));
         }
 
         // Check flags_present
-        for flag in &rule.flags_present {
-            if !command.flags.contains(flag) {
-                return false;
-            }
+        if !condition.flags_present.is_empty() {
+            let missing: Vec<&String> = condition
+                .flags_present
+                .iter()
+                .filter(|f| !command.flags.contains(*f))
+                .collect();
+            results.push(ConditionResult::new(
+                "flags_present",
+                missing.is_empty(),
+                format!(
+                    "required {:?}, missing {:?}",
+                    condition.flags_present, missing
+                ),
+            ));
         }
 
         // Check flags_absent
-        for flag in &rule.flags_absent {
-            if command.flags.contains(flag) {
-                return false;
+        if !condition.flags_absent.is_empty() {
+            let found: Vec<&String> = condition
+                .flags_absent
+                .iter()
+                .filter(|f| command.flags.contains(*f))
+                .collect();
+            results.push(ConditionResult::new(
+                "flags_absent",
+                found.is_empty(),
+                format!("forbidden {:?}, found {:?}", condition.flags_absent, found),
+            ));
+        }
+
+        // Check flags_only
+        if let Some(allowed) = &condition.flags_only {
+            let mut extra: Vec<&String> = command
+                .flags
+                .iter()
+                .filter(|f| !allowed.contains(*f))
+                .collect();
+            extra.sort();
+            results.push(ConditionResult::new(
+                "flags_only",
+                extra.is_empty(),
+                format!("allowed {:?}, extra {:?}", allowed, extra),
+            ));
+        }
+
+        // Check effects (any listed) and effects_absent (none listed)
+        if !condition.effects.is_empty() || !condition.effects_absent.is_empty() {
+            let names =
+                |effects: &[Effect]| -> Vec<&str> { effects.iter().map(|e| e.name()).collect() };
+            let found = names(&command.effects);
+
+            if !condition.effects.is_empty() {
+                results.push(ConditionResult::new(
+                    "effects",
+                    condition
+                        .effects
+                        .iter()
+                        .any(|e| command.effects.contains(e)),
+                    format!("any of {:?} in {:?}", names(&condition.effects), found),
+                ));
+            }
+            if !condition.effects_absent.is_empty() {
+                let present: Vec<Effect> = condition
+                    .effects_absent
+                    .iter()
+                    .copied()
+                    .filter(|e| command.effects.contains(e))
+                    .collect();
+                // A command without known effects could have any of them
+                let known = !command.effects.is_empty();
+                results.push(ConditionResult::new(
+                    "effects_absent",
+                    known && present.is_empty(),
+                    if known {
+                        format!(
+                            "forbidden {:?}, found {:?}",
+                            names(&condition.effects_absent),
+                            names(&present)
+                        )
+                    } else {
+                        format!(
+                            "forbidden {:?}, effects unknown",
+                            names(&condition.effects_absent)
+                        )
+                    },
+                ));
             }
         }
 
         // Check args_match (substring)
-        if let Some(ref pattern) = rule.args_match {
+        if let Some(ref pattern) = condition.args_match {
             let args_str = command.args.join(" ");
-            if !args_str.contains(pattern) {
-                return false;
-            }
+            results.push(ConditionResult::new(
+                "args_match",
+                args_str.contains(pattern),
+                format!("{:?} in {:?}", pattern, args_str),
+            ));
         }
 
         // Check args_regex
-        if let Some(ref pattern) = rule.args_regex {
+        if let Some(ref pattern) = condition.args_regex {
             let args_str = command.args.join(" ");
-            match Regex::new(pattern) {
-                Ok(re) => {
-                    if !re.is_match(&args_str) {
-                        return false;
-                    }
-                }
-                Err(_) => {
-                    // Invalid regex, don't match
-                    return false;
-                }
-            }
+            let result = match Regex::new(pattern) {
+                Ok(re) => ConditionResult::new(
+                    "args_regex",
+                    re.is_match(&args_str),
+                    format!("/{}/ against {:?}", pattern, args_str),
+                ),
+                // Invalid regex, don't match
+                Err(_) => ConditionResult::new(
+                    "args_regex",
+                    false,
+                    format!("invalid regex /{}/", pattern),
+                ),
+            };
+            results.push(result);
+        }
+
+        // Check positional args against the unjoined args
+        for (index, pattern) in &condition.arg {
+            let label = format!("arg[{}]", index);
+            let result = match index.parse::<usize>() {
+                Ok(i) => match command.args.get(i) {
+                    Some(arg) => match Self::arg_matches(pattern, arg) {
+                        Ok(passed) => ConditionResult::new(
+                            &label,
+                            passed,
+                            format!("{} against {:?}", pattern, arg),
+                        ),
+                        Err(e) => ConditionResult::new(&label, false, e),
+                    },
+                    None => ConditionResult::new(
+                        &label,
+                        false,
+                        format!("{} but only {} arg(s)", pattern, command.args.len()),
+                    ),
+                },
+                Err(_) => ConditionResult::new(&label, false, format!("invalid index {:?}", index)),
+            };
+            results.push(result);
+        }
+
+        // Check any_arg
+        if let Some(ref pattern) = condition.any_arg {
+            let result = match Self::count_matching_args(pattern, &command.args) {
+                Ok(n) => ConditionResult::new(
+                    "any_arg",
+                    n > 0,
+                    format!("{} matched {} of {:?}", pattern, n, command.args),
+                ),
+                Err(e) => ConditionResult::new("any_arg", false, e),
+            };
+            results.push(result);
+        }
+
+        // Check all_args
+        if let Some(ref pattern) = condition.all_args {
+            let result = match Self::count_matching_args(pattern, &command.args) {
+                Ok(n) => ConditionResult::new(
+                    "all_args",
+                    n == command.args.len(),
+                    format!("{} matched {} of {:?}", pattern, n, command.args),
+                ),
+                Err(e) => ConditionResult::new("all_args", false, e),
+            };
+            results.push(result);
+        }
+
+        // Check arg count bounds
+        if let Some(min) = condition.args_min {
+            results.push(ConditionResult::new(
+                "args_min",
+                command.args.len() >= min,
+                format!("at least {}, got {}", min, command.args.len()),
+            ));
+        }
+        if let Some(max) = condition.args_max {
+            results.push(ConditionResult::new(
+                "args_max",
+                command.args.len() <= max,
+                format!("at most {}, got {}", max, command.args.len()),
+            ));
         }
 
         // Check working_dir (glob pattern)
-        if let Some(ref pattern) = rule.working_dir {
+        if let Some(ref pattern) = condition.working_dir {
             if let Ok(cwd) = std::env::current_dir() {
                 let cwd_str = cwd.to_string_lossy();
-                match glob::Pattern::new(pattern) {
-                    Ok(glob) => {
-                        if !glob.matches(&cwd_str) {
-                            return false;
-                        }
+                let result = match glob::Pattern::new(pattern) {
+                    Ok(glob) => ConditionResult::new(
+                        "working_dir",
+                        glob.matches(&cwd_str),
+                        format!("{:?} against {:?}", pattern, cwd_str),
+                    ),
+                    Err(_) => ConditionResult::new(
+                        "working_dir",
+                        false,
+                        format!("invalid glob {:?}", pattern),
+                    ),
+                };
+                results.push(result);
+            }
+        }
+
+        // Check the pinned script hash
+        if let Some(ref expected) = condition.script_sha256 {
+            let actual = command.script.as_ref().and_then(|s| s.sha256.as_deref());
+            results.push(ConditionResult::new(
+                "script_sha256",
+                actual.is_some_and(|a| a.eq_ignore_ascii_case(expected)),
+                format!("{} against {}", expected, actual.unwrap_or("no script")),
+            ));
+        }
+
+        // Check whether the program name is only known at runtime
+        if let Some(dynamic) = condition.dynamic_program {
+            results.push(ConditionResult::new(
+                "dynamic_program",
+                command.dynamic_program == dynamic,
+                format!(
+                    "expected {}, program {:?} is {}",
+                    dynamic,
+                    command.program,
+                    if command.dynamic_program {
+                        "dynamic"
+                    } else {
+                        "static"
                     }
-                    Err(_) => {
-                        return false;
+                ),
+            ));
+        }
+
+        // Check control flow around the command
+        let control = [
+            (
+                "in_loop",
+                condition.in_loop,
+                !command.control.loops.is_empty(),
+            ),
+            (
+                "conditional",
+                condition.conditional,
+                command.control.conditional,
+            ),
+            (
+                "background",
+                condition.background,
+                command.control.background,
+            ),
+        ];
+        for (name, expected, actual) in control {
+            if let Some(expected) = expected {
+                let detail = match command.control.to_string() {
+                    flow if flow.is_empty() => {
+                        format!("expected {}, command runs unconditionally", expected)
                     }
-                }
+                    flow => format!("expected {}, command runs in: {}", expected, flow),
+                };
+                results.push(ConditionResult::new(name, actual == expected, detail));
+            }
+        }
+
+        // Check obfuscation techniques
+        if let Some(ref expected) = condition.obfuscation {
+            let found: Vec<&str> = command.obfuscation.iter().map(|o| o.name()).collect();
+            let passed = match expected.as_str() {
+                "any" => !found.is_empty(),
+                "none" => found.is_empty(),
+                name => found.contains(&name),
+            };
+            let detail = if Obfuscation::ALL.iter().any(|o| o.name() == expected)
+                || expected == "any"
+                || expected == "none"
+            {
+                format!("{:?} against {:?}", expected, found)
+            } else {
+                format!("unknown technique {:?}", expected)
+            };
+            results.push(ConditionResult::new("obfuscation", passed, detail));
+        }
+
+        // Check shell structure flags
+        let structure = [
+            (
+                "allow_expansion",
+                condition.allow_expansion,
+                command.has_expansion,
+            ),
+            (
+                "allow_substitution",
+                condition.allow_substitution,
+                command.has_substitution,
+            ),
+            (
+                "allow_redirect",
+                condition.allow_redirect,
+                command.has_redirect,
+            ),
+            ("allow_pipe", condition.allow_pipe, command.is_piped),
+        ];
+        for (name, allowed, present) in structure {
+            if allowed == Some(false) {
+                results.push(ConditionResult::new(
+                    name,
+                    !present,
+                    format!(
+                        "not allowed, {}",
+                        if present { "present" } else { "absent" }
+                    ),
+                ));
             }
         }
 
-        true
+        // Check nested combinators
+        for (i, nested) in condition.all.iter().enumerate() {
+            results.push(ConditionResult::branch(
+                format!("all[{}]", i),
+                Self::check(nested, command),
+            ));
+        }
+
+        if !condition.any.is_empty() {
+            let branches: Vec<ConditionResult> = condition
+                .any
+                .iter()
+                .enumerate()
+                .map(|(i, nested)| {
+                    ConditionResult::branch(format!("any[{}]", i), Self::check(nested, command))
+                })
+                .collect();
+            let passed = branches.iter().filter(|b| b.passed).count();
+            results.push(ConditionResult {
+                condition: "any".to_string(),
+                passed: passed > 0,
+                detail: format!("{} of {} branches passed", passed, branches.len()),
+                children: branches,
+            });
+        }
+
+        if let Some(nested) = &condition.not {
+            let inner =
+                ConditionResult::branch("condition".to_string(), Self::check(nested, command));
+            results.push(ConditionResult {
+                condition: "not".to_string(),
+                passed: !inner.passed,
+                detail: format!(
+                    "nested condition {}",
+                    if inner.passed {
+                        "matched"
+                    } else {
+                        "did not match"
+                    }
+                ),
+                children: vec![inner],
+            });
+        }
+
+        results
+    }
+
+    /// Check a single argument against a pattern (errors on an invalid glob or regex)
+    fn arg_matches(pattern: &ArgPattern, arg: &str) -> Result<bool, String> {
+        match pattern {
+            ArgPattern::Exact(expected) => Ok(arg == expected),
+            ArgPattern::Glob { glob } => glob::Pattern::new(glob)
+                .map(|p| p.matches(arg))
+                .map_err(|_| format!("invalid glob {:?}", glob)),
+            ArgPattern::Regex { regex } => Regex::new(regex)
+                .map(|re| re.is_match(arg))
+                .map_err(|_| format!("invalid regex /{}/", regex)),
+        }
+    }
+
+    fn count_matching_args(pattern: &ArgPattern, args: &[String]) -> Result<usize, String> {
+        let mut count = 0;
+        for arg in args {
+            if Self::arg_matches(pattern, arg)? {
+                count += 1;
+            }
+        }
+        Ok(count)
     }
 }
 
@@ -101,16 +559,13 @@ mod tests {
 
     fn make_rule(program: Option<&str>, subcommands: Vec<&str>, action: Action) -> Rule {
         Rule {
-            program: program.map(|s| s.to_string()),
-            subcommands: subcommands.into_iter().map(|s| s.to_string()).collect(),
-            subcommands_exact: false,
-            args_match: None,
-            args_regex: None,
-            flags_present: vec![],
-            flags_absent: vec![],
-            working_dir: None,
+            condition: Condition {
+                program: program.map(|s| s.to_string()),
+                subcommands: subcommands.into_iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            },
             action,
-            message: None,
+            ..Default::default()
         }
     }
 
@@ -141,7 +596,7 @@ mod tests {
     #[test]
     fn test_subcommand_exact_match() {
         let mut rule = make_rule(Some("git"), vec!["remote"], Action::Allow);
-        rule.subcommands_exact = true;
+        rule.condition.subcommands_exact = true;
 
         let cmds1 = ParsedCommand::parse_all("git remote").unwrap();
         assert!(RuleMatcher::matches(&rule, &cmds1[0]));
@@ -153,7 +608,7 @@ mod tests {
     #[test]
     fn test_flags_present() {
         let mut rule = make_rule(Some("git"), vec!["push"], Action::Deny);
-        rule.flags_present = vec!["--force".to_string()];
+        rule.condition.flags_present = vec!["--force".to_string()];
 
         let cmds1 = ParsedCommand::parse_all("git push --force").unwrap();
         assert!(RuleMatcher::matches(&rule, &cmds1[0]));
@@ -165,7 +620,7 @@ mod tests {
     #[test]
     fn test_flags_absent() {
         let mut rule = make_rule(Some("git"), vec!["push"], Action::Allow);
-        rule.flags_absent = vec!["--force".to_string(), "-f".to_string()];
+        rule.condition.flags_absent = vec!["--force".to_string(), "-f".to_string()];
 
         let cmds1 = ParsedCommand::parse_all("git push").unwrap();
         assert!(RuleMatcher::matches(&rule, &cmds1[0]));
@@ -177,10 +632,25 @@ mod tests {
         assert!(!RuleMatcher::matches(&rule, &cmds3[0]));
     }
 
+    #[test]
+    fn test_flags_only() {
+        let mut rule = make_rule(Some("git"), vec!["push"], Action::Allow);
+        rule.condition.flags_only = Some(vec!["-u".to_string()]);
+
+        let cmds1 = ParsedCommand::parse_all("git push -u origin main").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds1[0]));
+
+        let cmds2 = ParsedCommand::parse_all("git push -u --force origin main").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds2[0]));
+
+        rule.condition.flags_only = Some(Vec::new());
+        assert!(!RuleMatcher::matches(&rule, &cmds1[0]));
+    }
+
     #[test]
     fn test_args_regex() {
         let mut rule = make_rule(Some("rm"), vec![], Action::Deny);
-        rule.args_regex = Some(r"/\*".to_string());
+        rule.condition.args_regex = Some(r"/\*".to_string());
 
         let cmds1 = ParsedCommand::parse_all("rm -rf /*").unwrap();
         assert!(RuleMatcher::matches(&rule, &cmds1[0]));
@@ -188,4 +658,273 @@ mod tests {
         let cmds2 = ParsedCommand::parse_all("rm foo.txt").unwrap();
         assert!(!RuleMatcher::matches(&rule, &cmds2[0]));
     }
+
+    #[test]
+    fn test_specificity() {
+        let broad = make_rule(Some("git"), vec![], Action::Allow);
+        let mut narrow = make_rule(Some("git"), vec!["push"], Action::Deny);
+        narrow.condition.flags_present = vec!["--force".to_string()];
+
+        assert_eq!(RuleMatcher::specificity(&broad), 1);
+        assert_eq!(RuleMatcher::specificity(&narrow), 3);
+    }
+
+    #[test]
+    fn test_any_combinator() {
+        let rule: Rule = toml::from_str(
+            r#"
+            program = "rm"
+            flags_present = ["-r"]
+            any = [{ flags_present = ["-f"] }, { flags_present = ["--force"] }]
+            action = "deny"
+            "#,
+        )
+        .unwrap();
+
+        let cmds = ParsedCommand::parse_all("rm -rf build").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm -r --force build").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm -r build").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm -f build").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_not_combinator() {
+        let rule: Rule = toml::from_str(
+            r#"
+            program = "kubectl"
+            action = "allow"
+
+            [not]
+            any = [{ args_regex = "(^| )prod( |$)" }, { args_match = "kube-system" }]
+            "#,
+        )
+        .unwrap();
+
+        let cmds = ParsedCommand::parse_all("kubectl get pods -n staging").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("kubectl get pods -n prod").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("kubectl get pods -n kube-system").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_check_reports_nested_results() {
+        let mut rule = make_rule(Some("git"), vec![], Action::Deny);
+        rule.condition.all = vec![
+            Condition {
+                subcommands: vec!["push".to_string()],
+                ..Default::default()
+            },
+            Condition {
+                flags_present: vec!["--force".to_string()],
+                ..Default::default()
+            },
+        ];
+
+        let cmds = ParsedCommand::parse_all("git push").unwrap();
+        let results = RuleMatcher::check(&rule.condition, &cmds[0]);
+
+        assert_eq!(results.len(), 3);
+        assert!(results[0].passed);
+        assert!(results[1].passed);
+        assert_eq!(results[2].condition, "all[1]");
+        assert!(!results[2].passed);
+        assert_eq!(results[2].children[0].condition, "flags_present");
+    }
+
+    #[test]
+    fn test_any_arg_exact_token() {
+        let mut rule = make_rule(Some("git"), vec!["push"], Action::Deny);
+        rule.condition.any_arg = Some(ArgPattern::Exact("main".to_string()));
+
+        let cmds = ParsedCommand::parse_all("git push origin main").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        // Substring matching would have matched this
+        let cmds = ParsedCommand::parse_all("git push origin maintenance").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_positional_args() {
+        let rule: Rule = toml::from_str(
+            r#"
+            program = "git"
+            subcommands = ["push"]
+            arg = { 0 = "origin", 1 = { glob = "release/*" } }
+            action = "deny"
+            "#,
+        )
+        .unwrap();
+
+        let cmds = ParsedCommand::parse_all("git push origin release/1.0").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("git push upstream release/1.0").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        // Missing positional arg fails
+        let cmds = ParsedCommand::parse_all("git push origin").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_all_args_and_count_bounds() {
+        let mut rule = make_rule(Some("rm"), vec![], Action::Allow);
+        rule.condition.all_args = Some(ArgPattern::Regex {
+            regex: r"^target/".to_string(),
+        });
+        rule.condition.args_min = Some(1);
+        rule.condition.args_max = Some(2);
+
+        let cmds = ParsedCommand::parse_all("rm -r target/debug target/tmp").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm -r target/debug src").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm -r").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm target/a target/b target/c").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_quoted_arg_kept_whole() {
+        let mut rule = make_rule(Some("echo"), vec![], Action::Allow);
+        rule.condition.arg.insert(
+            "0".to_string(),
+            ArgPattern::Exact("hello world".to_string()),
+        );
+
+        let cmds = ParsedCommand::parse_all(r#"echo "hello world""#).unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("echo hello world").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_structure_conditions() {
+        let mut rule = make_rule(Some("echo"), vec![], Action::Allow);
+        rule.condition.allow_substitution = Some(false);
+        rule.condition.allow_redirect = Some(false);
+
+        let cmds = ParsedCommand::parse_all("echo hello").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("echo $(cat ~/.ssh/id_rsa)").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("echo hello > out.txt").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        // Unset or true places no restriction
+        rule.condition.allow_substitution = Some(true);
+        rule.condition.allow_redirect = None;
+        let cmds = ParsedCommand::parse_all("echo $(date) > out.txt").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_control_flow_conditions() {
+        let rule: Rule = toml::from_str(
+            r#"
+            program = "rm"
+            in_loop = true
+            conditional = false
+            action = "prompt"
+            "#,
+        )
+        .unwrap();
+
+        let cmds = ParsedCommand::parse_all("for f in *.log; do rm $f; done").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("rm a.log").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        let cmds = ParsedCommand::parse_all("while true; do test -f x && rm x; done").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[2]));
+
+        let mut rule = make_rule(None, vec![], Action::Prompt);
+        rule.condition.background = Some(true);
+        for input in ["sleep 1 &", "nohup ./server", "setsid -f ./server"] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(RuleMatcher::matches(&rule, &cmds[0]), "{}", input);
+        }
+        let cmds = ParsedCommand::parse_all("sleep 1").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+    }
+
+    #[test]
+    fn test_effect_conditions() {
+        let rule: Rule = toml::from_str(
+            r#"
+            effects = ["network"]
+            action = "deny"
+            "#,
+        )
+        .unwrap();
+        for input in [
+            "curl -s x",
+            "wget x",
+            "git push",
+            "npm publish",
+            "scp a h:b",
+        ] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(RuleMatcher::matches(&rule, &cmds[0]), "{}", input);
+        }
+        let cmds = ParsedCommand::parse_all("git status").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        // Read-only: reads, and nothing else
+        let rule: Rule = toml::from_str(
+            r#"
+            effects = ["read"]
+            effects_absent = ["write", "network", "destructive", "privilege"]
+            action = "allow"
+            "#,
+        )
+        .unwrap();
+        let cmds = ParsedCommand::parse_all("grep -r foo src").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+        let cmds = ParsedCommand::parse_all("sed -i s/a/b/ x").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+        let cmds = ParsedCommand::parse_all("cat x > y").unwrap();
+        assert!(!RuleMatcher::matches(&rule, &cmds[0]));
+
+        // Commands without known effects never pass `effects_absent`
+        let rule: Rule = toml::from_str(
+            r#"
+            effects_absent = ["write", "network", "destructive", "privilege"]
+            action = "allow"
+            "#,
+        )
+        .unwrap();
+        for input in [
+            "frob x",
+            "git apply x.patch",
+            "git config core.hooksPath /tmp/h",
+            "git update-ref -d refs/heads/main",
+            "git worktree remove --force ../w",
+        ] {
+            let cmds = ParsedCommand::parse_all(input).unwrap();
+            assert!(!RuleMatcher::matches(&rule, &cmds[0]), "{}", input);
+        }
+        let cmds = ParsedCommand::parse_all("git log -3").unwrap();
+        assert!(RuleMatcher::matches(&rule, &cmds[0]));
+    }
 }
@@ -1,5 +1,17 @@
+mod escalation;
 mod evaluator;
+mod explain;
+mod guidance;
+mod limits;
 mod matcher;
+mod rewrite;
+mod risk;
 
+pub use escalation::{Denial, SessionHistory};
 pub use evaluator::{Decision, Evaluator};
-pub use matcher::RuleMatcher;
+pub use explain::{CommandExplanation, Explanation, RuleEvaluation};
+pub use guidance::Guidance;
+pub use limits::{AppliedLimit, SessionUsage};
+pub use matcher::{ConditionResult, RuleMatcher};
+pub use rewrite::sandbox_command;
+pub use risk::{RiskFactor, RiskScore, RiskSignal};
@@ -0,0 +1,149 @@
+//! Rewrite templates for rules with `action = "rewrite"`
+
+use crate::parser::{ParsedCommand, Span};
+
+/// Render a rule's `rewrite` template for `command`.
+///
+/// - `{command}`: the command as written
+/// - `{program}`: the program name
+/// - `{args}`: the arguments after the program, quoted for the shell as needed
+/// - `{positional}`: the arguments other than subcommands and flags (`origin main`
+///   for `git push --force origin main`), quoted the same way
+///
+/// Returns `None` if a placeholder stands for a word the shell only expands when the
+/// command runs (a glob pattern, `$VAR` or `$(...)`), which quoting would turn into a
+/// literal string.
+pub(super) fn render(template: &str, command: &ParsedCommand) -> Option<String> {
+    let expanded_later: Vec<&String> = command
+        .raw_args
+        .iter()
+        .enumerate()
+        .filter(|(i, arg)| command.glob_args.contains(i) || command.dynamic_args.contains(arg))
+        .map(|(_, arg)| arg)
+        .collect();
+    if template.contains("{program}") && command.dynamic_program {
+        return None;
+    }
+    if template.contains("{args}") && !expanded_later.is_empty() {
+        return None;
+    }
+    if template.contains("{positional}")
+        && command.args.iter().any(|arg| expanded_later.contains(&arg))
+    {
+        return None;
+    }
+
+    let quote_all = |words: &[String]| -> String {
+        words
+            .iter()
+            .map(|word| quote(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    Some(
+        template
+            .replace("{command}", command.raw.trim())
+            .replace("{program}", &quote(&command.program))
+            .replace("{args}", &quote_all(&command.raw_args))
+            .replace("{positional}", &quote_all(&command.args))
+            .trim()
+            .to_string(),
+    )
+}
+
+/// Command running `command` under `bashguard sandbox-exec` with a sandbox profile
+pub fn sandbox_command(policy: &str, command: &str) -> String {
+    format!(
+        "bashguard sandbox-exec --policy {} -- {}",
+        quote(policy),
+        quote(command)
+    )
+}
+
+/// Replace each span of `input` with its replacement, or `None` if spans overlap
+/// (e.g., a command and a command in its substitution are both rewritten)
+pub(super) fn splice(input: &str, mut edits: Vec<(Span, String)>) -> Option<String> {
+    edits.sort_by_key(|(span, _)| span.start);
+    if edits.windows(2).any(|pair| pair[0].0.end > pair[1].0.start) {
+        return None;
+    }
+
+    let mut output = input.to_string();
+    for (span, replacement) in edits.iter().rev() {
+        output.replace_range(span.start..span.end, replacement);
+    }
+    Some(output)
+}
+
+/// Quote a word for the shell unless it only has characters that need no quoting
+fn quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_and_splice() {
+        let input = "ls && rm -rf 'my dir' build";
+        let commands = ParsedCommand::parse_all(input).unwrap();
+        let rm = &commands[1];
+
+        let render =
+            |template: &str, command: &ParsedCommand| super::render(template, command).unwrap();
+        assert_eq!(render("trash {args}", rm), "trash -rf 'my dir' build");
+        assert_eq!(
+            render("{command} --dry-run", rm),
+            "rm -rf 'my dir' build --dry-run"
+        );
+        assert_eq!(
+            render("{program} -i {args}", rm),
+            "rm -i -rf 'my dir' build"
+        );
+        assert_eq!(render("trash {positional}", rm), "trash 'my dir' build");
+
+        let edit = (rm.span.unwrap(), "trash build".to_string());
+        assert_eq!(
+            splice(input, vec![edit.clone()]).as_deref(),
+            Some("ls && trash build")
+        );
+        assert_eq!(splice(input, vec![edit.clone(), edit]), None);
+
+        assert_eq!(
+            sandbox_command("default", "make test && echo 'ok'"),
+            r"bashguard sandbox-exec --policy default -- 'make test && echo '\''ok'\'''"
+        );
+    }
+
+    #[test]
+    fn test_render_refuses_words_expanded_at_runtime() {
+        for input in [
+            "rm *.log",
+            r#"for f in *; do rm "$f"; done"#,
+            r#"rm "$HOME/x""#,
+            "rm $(cat list)",
+        ] {
+            let commands = ParsedCommand::parse_all(input).unwrap();
+            let rm = commands.iter().find(|c| c.program == "rm").unwrap();
+            assert_eq!(render("trash {args}", rm), None, "{}", input);
+            // The command as written still expands when it runs
+            assert!(render("{command} -i", rm).is_some(), "{}", input);
+        }
+
+        // Quoted patterns and variables known from the input are plain words
+        let commands = ParsedCommand::parse_all(r#"X=a.log; rm "*.log" $X {b,c}"#).unwrap();
+        assert_eq!(
+            render("trash {args}", &commands[0]).as_deref(),
+            Some("trash '*.log' a.log b c")
+        );
+
+        let commands = ParsedCommand::parse_all("$RM x").unwrap();
+        assert_eq!(render("{program} -i x", &commands[0]), None);
+    }
+}
@@ -0,0 +1,335 @@
+//! Numeric risk scoring of commands (see `settings.risk`)
+
+use std::{
+    env, fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::{Action, RiskBand, RiskWeights},
+    parser::{Effect, ParsedCommand},
+};
+
+/// Something about a command that makes it riskier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RiskSignal {
+    Destructive,
+    Force,
+    OutsideWorkspace,
+    Substitution,
+    Privilege,
+    Obfuscation,
+    Network,
+}
+
+impl RiskSignal {
+    /// Name matching the `settings.risk.weights` key
+    pub fn name(self) -> &'static str {
+        match self {
+            RiskSignal::Destructive => "destructive",
+            RiskSignal::Force => "force",
+            RiskSignal::OutsideWorkspace => "outside_workspace",
+            RiskSignal::Substitution => "substitution",
+            RiskSignal::Privilege => "privilege",
+            RiskSignal::Obfuscation => "obfuscation",
+            RiskSignal::Network => "network",
+        }
+    }
+
+    fn weight(self, weights: &RiskWeights) -> u32 {
+        match self {
+            RiskSignal::Destructive => weights.destructive,
+            RiskSignal::Force => weights.force,
+            RiskSignal::OutsideWorkspace => weights.outside_workspace,
+            RiskSignal::Substitution => weights.substitution,
+            RiskSignal::Privilege => weights.privilege,
+            RiskSignal::Obfuscation => weights.obfuscation,
+            RiskSignal::Network => weights.network,
+        }
+    }
+}
+
+/// A signal found in a command, and what it added to the score
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RiskFactor {
+    pub program: String,
+    pub signal: RiskSignal,
+    pub weight: u32,
+}
+
+/// Risk score of a command or a whole input, with the factors adding up to it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RiskScore {
+    pub score: u32,
+    pub factors: Vec<RiskFactor>,
+}
+
+impl fmt::Display for RiskScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.score)?;
+        if !self.factors.is_empty() {
+            let factors: Vec<String> = self
+                .factors
+                .iter()
+                .map(|factor| {
+                    format!(
+                        "{} {} +{}",
+                        factor.program,
+                        factor.signal.name(),
+                        factor.weight
+                    )
+                })
+                .collect();
+            write!(f, " ({})", factors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Flags that skip a command's safety checks, by program and subcommand (`-f` means
+/// something else to most programs, like `tail -f` or `grep -f`)
+const FORCE_FLAGS: &[(&str, &[&str])] = &[
+    ("rm", &["-f", "--force"]),
+    ("cp", &["-f", "--force"]),
+    ("mv", &["-f", "--force"]),
+    ("ln", &["-f", "--force"]),
+    ("shred", &["-f", "--force"]),
+    ("git push", &["-f", "--force", "--force-with-lease"]),
+    ("git clean", &["-f", "--force"]),
+    ("git checkout", &["-f", "--force"]),
+    ("git switch", &["-f", "--force", "--discard-changes"]),
+    ("git branch", &["-f", "--force"]),
+    ("git worktree remove", &["-f", "--force"]),
+    ("docker rm", &["-f", "--force"]),
+    ("docker rmi", &["-f", "--force"]),
+    ("docker system prune", &["-f", "--force"]),
+    ("kubectl delete", &["--force"]),
+    ("npm install", &["-f", "--force"]),
+    ("cargo install", &["-f", "--force"]),
+];
+
+/// Whether `command` has a force flag of its program and subcommands
+fn has_force_flag(command: &ParsedCommand) -> bool {
+    let name = Path::new(&command.program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&command.program);
+    FORCE_FLAGS.iter().any(|(spec, flags)| {
+        let mut words = spec.split(' ');
+        words.next() == Some(name)
+            && words.enumerate().all(|(i, word)| {
+                command
+                    .subcommands
+                    .get(i)
+                    .is_some_and(|subcommand| subcommand == word)
+            })
+            && flags.iter().any(|flag| command.flags.contains(*flag))
+    })
+}
+
+/// Score a single command. Paths are checked against `workspace`, if known.
+pub fn score_command(
+    command: &ParsedCommand,
+    weights: &RiskWeights,
+    workspace: Option<&Path>,
+) -> RiskScore {
+    let signals = [
+        (
+            RiskSignal::Destructive,
+            command.effects.contains(&Effect::Destructive),
+        ),
+        (RiskSignal::Force, has_force_flag(command)),
+        (
+            RiskSignal::OutsideWorkspace,
+            workspace.is_some_and(|w| has_path_outside(command, w)),
+        ),
+        (RiskSignal::Substitution, command.has_substitution),
+        (
+            RiskSignal::Privilege,
+            command.effects.contains(&Effect::Privilege),
+        ),
+        (RiskSignal::Obfuscation, !command.obfuscation.is_empty()),
+        (
+            RiskSignal::Network,
+            command.effects.contains(&Effect::Network),
+        ),
+    ];
+
+    let factors: Vec<RiskFactor> = signals
+        .into_iter()
+        .filter(|(_, present)| *present)
+        .map(|(signal, _)| RiskFactor {
+            program: command.program.clone(),
+            signal,
+            weight: signal.weight(weights),
+        })
+        .filter(|factor| factor.weight > 0)
+        .collect();
+
+    RiskScore {
+        score: factors.iter().map(|f| f.weight).sum(),
+        factors,
+    }
+}
+
+/// Score a whole input: the sum of its commands' scores. A command embedded in
+/// another (`sudo CMD`) doesn't score the signals its wrapper already did, such as
+/// the privileges it runs with.
+pub fn score_input(
+    commands: &[ParsedCommand],
+    weights: &RiskWeights,
+    workspace: Option<&Path>,
+) -> RiskScore {
+    let mut total = RiskScore::default();
+    // Every signal of each command, including those its own wrapper scored
+    let mut signals: Vec<Vec<RiskSignal>> = Vec::new();
+    for (i, command) in commands.iter().enumerate() {
+        let mut score = score_command(command, weights, workspace);
+        signals.push(score.factors.iter().map(|f| f.signal).collect());
+        // Embedded commands follow their wrapper and share its span
+        let wrapper = command.embedded_in.as_ref().and_then(|program| {
+            (0..i)
+                .rev()
+                .find(|&j| &commands[j].program == program && commands[j].span == command.span)
+        });
+        if let Some(wrapper) = wrapper {
+            let scored = &signals[wrapper];
+            score
+                .factors
+                .retain(|factor| !scored.contains(&factor.signal));
+            score.score = score.factors.iter().map(|f| f.weight).sum();
+        }
+
+        total.score = total.score.saturating_add(score.score);
+        total.factors.extend(score.factors);
+    }
+    total
+}
+
+/// Action of the highest band the score reaches
+pub fn band_action(score: u32, bands: &[RiskBand]) -> Option<Action> {
+    bands
+        .iter()
+        .filter(|band| score >= band.min)
+        .max_by_key(|band| band.min)
+        .map(|band| band.action)
+}
+
+/// Whether any path argument (or `--option=path` value) points outside `workspace`
+fn has_path_outside(command: &ParsedCommand, workspace: &Path) -> bool {
+    command.raw_args.iter().any(|arg| {
+        let value = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with('-') => value,
+            _ => arg.as_str(),
+        };
+        resolve_path(value, workspace).is_some_and(|path| !path.starts_with(workspace))
+    })
+}
+
+/// Resolve an argument that looks like a path (absolute, `~/`, or containing `/`),
+/// normalizing `..` without touching the filesystem
+pub(super) fn resolve_path(arg: &str, workspace: &Path) -> Option<PathBuf> {
+    if arg.contains("://") || arg.contains('$') || arg == "/dev/null" {
+        return None;
+    }
+
+    let path = if arg == "~" || arg.starts_with("~/") {
+        let home = env::var_os("HOME")?;
+        PathBuf::from(home).join(arg.trim_start_matches('~').trim_start_matches('/'))
+    } else if arg.starts_with('/') {
+        PathBuf::from(arg)
+    } else if arg.contains('/') || arg == ".." {
+        workspace.join(arg)
+    } else {
+        return None;
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(input: &str) -> RiskScore {
+        let commands = ParsedCommand::parse_all(input).unwrap();
+        score_input(
+            &commands,
+            &RiskWeights::default(),
+            Some(Path::new("/work/repo")),
+        )
+    }
+
+    fn signals(input: &str) -> Vec<&'static str> {
+        score(input)
+            .factors
+            .iter()
+            .map(|f| f.signal.name())
+            .collect()
+    }
+
+    #[test]
+    fn test_signals() {
+        assert!(signals("ls -la src").is_empty());
+        assert_eq!(signals("rm -rf build"), vec!["destructive", "force"]);
+        assert_eq!(signals("cat /etc/passwd"), vec!["outside_workspace"]);
+        assert_eq!(signals("cp ../../secrets x"), vec!["outside_workspace"]);
+        assert!(signals("cp ./a/../b x").is_empty());
+        assert_eq!(
+            signals("git push --force"),
+            vec!["destructive", "force", "network"]
+        );
+        // Privileges are scored once, for the wrapper that grants them
+        assert_eq!(signals("sudo ls"), vec!["privilege"]);
+        assert_eq!(signals("sudo xargs ls"), vec!["privilege"]);
+        assert_eq!(
+            signals("sudo rm -rf build"),
+            vec!["privilege", "destructive", "force"]
+        );
+        // `-f` only forces programs where it means that
+        assert!(signals("tail -f log/app.log").is_empty());
+        assert!(signals("grep -f pats src").is_empty());
+        assert_eq!(signals("git clean -f"), vec!["destructive", "force"]);
+        assert_eq!(
+            signals("echo $(curl -s x)"),
+            vec!["substitution", "network"]
+        );
+    }
+
+    #[test]
+    fn test_scores_and_bands() {
+        let total = score("rm -rf build && curl -s x");
+        assert_eq!(total.score, 40 + 20 + 20);
+        assert_eq!(
+            total.to_string(),
+            "80 (rm destructive +40, rm force +20, curl network +20)"
+        );
+
+        let bands = vec![
+            RiskBand {
+                min: 30,
+                action: Action::Prompt,
+            },
+            RiskBand {
+                min: 80,
+                action: Action::Deny,
+            },
+        ];
+        assert_eq!(band_action(10, &bands), None);
+        assert_eq!(band_action(30, &bands), Some(Action::Prompt));
+        assert_eq!(band_action(120, &bands), Some(Action::Deny));
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use bashguard::{cli, Config};
+
+/// Paths writable in every sandbox, besides the workspace
+const ALWAYS_WRITABLE: &[&str] = &["/tmp", "/dev"];
+
+pub fn sandbox_exec(args: cli::sandbox_exec::Args) -> Result<()> {
+    let cli::sandbox_exec::Args { policy, command } = args;
+
+    let config = Config::load()?;
+    let profile = config
+        .sandbox(&policy)
+        .with_context(|| format!("Unknown sandbox profile: {}", policy))?;
+
+    let workspace = env::current_dir().context("Failed to get current directory")?;
+    let mut writable = vec![workspace];
+    writable.extend(ALWAYS_WRITABLE.iter().map(PathBuf::from));
+    writable.extend(profile.writable.iter().map(|path| expand_home(path)));
+
+    // The network namespace has to be set up before Landlock hides /proc from writes
+    if !profile.network {
+        imp::drop_network().context("Failed to drop network access")?;
+    }
+    imp::restrict_filesystem(&writable).context("Failed to restrict filesystem access")?;
+
+    imp::exec_shell(&command.join(" "))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        fs::{self, OpenOptions},
+        io,
+        mem::size_of,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::{fs::OpenOptionsExt, process::CommandExt},
+        },
+        path::{Path, PathBuf},
+        process::Command,
+        ptr,
+    };
+
+    use anyhow::{bail, Context, Result};
+
+    // Landlock ABI (linux/landlock.h)
+    const CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+    const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+    const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+    /// Rights that apply to files (the rest only apply to directories)
+    const FILE_ACCESS: u64 = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_TRUNCATE
+        | ACCESS_FS_IOCTL_DEV;
+    const READ_ACCESS: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Filesystem rights known to a Landlock ABI version; handling all of them denies
+    /// everything not explicitly allowed
+    fn handled_access(abi: i64) -> u64 {
+        match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        }
+    }
+
+    /// Make everything read-only except `writable` (paths that don't exist are skipped)
+    pub(super) fn restrict_filesystem(writable: &[PathBuf]) -> Result<()> {
+        // SAFETY: querying the ABI version takes no attribute
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 0 {
+            bail!("Landlock is not available: {}", io::Error::last_os_error());
+        }
+        let handled = handled_access(abi);
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: `attr` outlives the call and its size is passed along
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Failed to create Landlock ruleset");
+        }
+        // SAFETY: the syscall returned a new file descriptor we now own
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        add_rule(&ruleset, Path::new("/"), READ_ACCESS)?;
+        for path in writable.iter().filter(|path| path.exists()) {
+            add_rule(&ruleset, path, handled)?;
+        }
+
+        // SAFETY: plain prctl/syscall calls without pointers
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error()).context("Failed to set no_new_privs");
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0u32) != 0 {
+                return Err(io::Error::last_os_error()).context("Failed to apply Landlock ruleset");
+            }
+        }
+        Ok(())
+    }
+
+    fn add_rule(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let access = if file.metadata()?.is_dir() {
+            access
+        } else {
+            access & FILE_ACCESS
+        };
+
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: file.as_raw_fd(),
+        };
+        // SAFETY: `attr` and the file outlive the call
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &attr as *const PathBeneathAttr,
+                0u32,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Failed to allow access to {}", path.display()));
+        }
+        Ok(())
+    }
+
+    /// Move into new user and network namespaces, leaving only a loopback device
+    pub(super) fn drop_network() -> Result<()> {
+        // SAFETY: plain libc calls without pointers
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+            return Err(io::Error::last_os_error()).context("Failed to create namespaces");
+        }
+
+        // Keep the same ids inside the user namespace
+        fs::write("/proc/self/setgroups", "deny")?;
+        fs::write("/proc/self/uid_map", format!("{} {} 1", uid, uid))?;
+        fs::write("/proc/self/gid_map", format!("{} {} 1", gid, gid))?;
+        Ok(())
+    }
+
+    pub(super) fn exec_shell(command: &str) -> Result<()> {
+        let error = Command::new("bash").arg("-c").arg(command).exec();
+        Err(error).context("Failed to run bash")
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::path::PathBuf;
+
+    use anyhow::{bail, Result};
+
+    pub(super) fn restrict_filesystem(_writable: &[PathBuf]) -> Result<()> {
+        bail!("Sandboxing is only supported on Linux")
+    }
+
+    pub(super) fn drop_network() -> Result<()> {
+        bail!("Sandboxing is only supported on Linux")
+    }
+
+    pub(super) fn exec_shell(_command: &str) -> Result<()> {
+        bail!("Sandboxing is only supported on Linux")
+    }
+}
@@ -0,0 +1,142 @@
+//! Updates of the JSON state files (grants, approvals, session counters)
+//!
+//! State lives outside the workspace, in `$XDG_STATE_HOME/bashguard/<workspace>/`
+//! (`~/.local/state` by default), so commands the agent runs in the workspace can't
+//! forge grants or approvals.
+//!
+//! Hooks can run concurrently, so an update holds an exclusive lock from reading a
+//! file to replacing it, and replaces it by renaming a complete copy, so readers never
+//! see a partial write and no writer drops another's change.
+
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::hash::sha256_hex;
+
+/// Directory holding the state of every workspace, if a home directory is known
+pub(crate) fn state_root() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state_home.join("bashguard"))
+}
+
+/// State directory of the current workspace, named by a hash of its path
+pub(crate) fn workspace_state_dir() -> Result<PathBuf> {
+    let workspace = env::current_dir().context("Failed to get current directory")?;
+    let root = state_root().context("Failed to find a state directory: HOME is not set")?;
+    let hash = sha256_hex(workspace.as_os_str().as_encoded_bytes());
+    Ok(root.join(&hash[..16]))
+}
+
+/// Contents of the state file at `path` (`what` names it in errors), or the default
+/// if it doesn't exist yet
+pub(crate) fn load<T: DeserializeOwned + Default>(path: &Path, what: &str) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}: {}", what, path.display()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}: {}", what, path.display()))
+}
+
+/// Exclusive lock on a state file, released when dropped
+pub(crate) struct Lock {
+    _file: File,
+}
+
+/// Wait for the exclusive lock on the state file at `path`
+pub(crate) fn lock(path: &Path, what: &str) -> Result<Lock> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = File::create(sibling(path, "lock"))
+        .and_then(|file| file.lock().map(|()| file))
+        .with_context(|| format!("Failed to lock {}: {}", what, path.display()))?;
+    Ok(Lock { _file: file })
+}
+
+/// Replace the state file at `path` with `value`, holding its lock
+pub(crate) fn save<T: Serialize>(_lock: &Lock, path: &Path, what: &str, value: &T) -> Result<()> {
+    let temp = sibling(path, "tmp");
+    let contents = serde_json::to_string_pretty(value)?;
+    fs::write(&temp, contents + "\n")
+        .and_then(|()| fs::rename(&temp, path))
+        .with_context(|| format!("Failed to write {}: {}", what, path.display()))
+}
+
+/// Change the state file at `path` with `change`, which sees the current contents; the
+/// file is only written if `change` succeeds
+pub(crate) fn update<T, R>(
+    path: &Path,
+    what: &str,
+    change: impl FnOnce(&mut T) -> Result<R>,
+) -> Result<R>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    let lock = lock(path, what)?;
+    let mut value = load(path, what)?;
+    let result = change(&mut value)?;
+    save(&lock, path, what, &value)?;
+    Ok(result)
+}
+
+/// `path` with `extension` appended (e.g., `grants.json.lock`)
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_updates() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = Arc::new(temp_dir.path().join("state").join("counts.json"));
+        assert!(load::<Vec<u32>>(&path, "counts").unwrap().is_empty());
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = Arc::clone(&path);
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        update(&path, "counts", |counts: &mut Vec<u32>| {
+                            counts.push(writer);
+                            Ok(())
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(load::<Vec<u32>>(&path, "counts").unwrap().len(), 80);
+
+        // Failed changes aren't written
+        let failed = update(&path, "counts", |counts: &mut Vec<u32>| -> Result<()> {
+            counts.clear();
+            anyhow::bail!("no")
+        });
+        assert!(failed.is_err());
+        assert_eq!(load::<Vec<u32>>(&path, "counts").unwrap().len(), 80);
+    }
+}
@@ -26,6 +26,15 @@ export const BashguardPlugin: Plugin = async () => {
         if (decision.abort) {
           throw new Error(`[bashguard] ${decision.abort}`)
         }
+
+        if (decision.warning) {
+          console.warn(`[bashguard] ${decision.warning}`)
+        }
+
+        // Run the command the policy rewrote this one to
+        if (decision.rewrite) {
+          output.args.command = decision.rewrite
+        }
       } catch (error: any) {
         // Re-throw bashguard denials
         if (error?.message?.startsWith("[bashguard]")) {
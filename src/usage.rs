@@ -0,0 +1,102 @@
+//! Persistent per-session counters for limits, in `sessions/` in the workspace's
+//! state directory
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    hash::sha256_hex,
+    rules::{AppliedLimit, SessionUsage},
+    store,
+};
+
+/// Counters of one session
+pub struct UsageStore {
+    path: PathBuf,
+}
+
+impl UsageStore {
+    /// Counters of `session_id` in the current workspace
+    pub fn new(session_id: &str) -> Result<Self> {
+        // Hashed so distinct session ids never share counters
+        let file_name = format!("{}.json", sha256_hex(session_id.as_bytes()));
+        Ok(Self::with_path(
+            store::workspace_state_dir()?
+                .join("sessions")
+                .join(file_name),
+        ))
+    }
+
+    /// Counters at a custom path (for testing)
+    pub fn with_path(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn load(&self) -> Result<SessionUsage> {
+        store::load(&self.path, "counters")
+    }
+
+    /// Lock the counters until the guard is dropped. `check` holds it from loading the
+    /// counters to recording the input, so concurrent checks can't both take the last
+    /// of a budget.
+    pub fn lock(&self) -> Result<UsageLock> {
+        Ok(UsageLock(store::lock(&self.path, "counters")?))
+    }
+
+    /// Count the commands of an input that ran against the counters of `limits`
+    pub fn record(
+        &self,
+        lock: &UsageLock,
+        limits: &[AppliedLimit],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        let mut usage = self.load()?;
+        for applied in limits {
+            usage.record(&applied.counter, now, applied.matched, applied.limit.count);
+        }
+        store::save(&lock.0, &self.path, "counters", &usage)
+    }
+}
+
+/// Exclusive lock on a session's counters (see [`UsageStore::lock`])
+pub struct UsageLock(store::Lock);
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::config::{Action, Limit};
+
+    #[test]
+    fn test_record_and_load() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = UsageStore::with_path(temp_dir.path().join("sessions").join("s1.json"));
+        assert_eq!(store.load().unwrap(), SessionUsage::default());
+
+        let applied = AppliedLimit {
+            counter: "rule:kubectl-writes".to_string(),
+            limit: Limit {
+                count: 2,
+                window: None,
+                action: Action::Prompt,
+                message: None,
+            },
+            matched: 1,
+        };
+        let now = Utc::now();
+        let lock = store.lock().unwrap();
+        for _ in 0..3 {
+            store
+                .record(&lock, std::slice::from_ref(&applied), now)
+                .unwrap();
+        }
+        assert_eq!(store.load().unwrap().count(&applied.counter, None, now), 2);
+    }
+}